@@ -1,26 +1,72 @@
 use std::prelude::v1::*;
 
-use std::sync::{Arc, mpsc::{channel, Sender}, Mutex};
+use std::sync::{Arc, mpsc::{channel, Sender}};
 
 use serde::{Deserialize, Serialize};
 use jsonrpc::{JsonrpcErrorObj, RpcArgs};
 
-use eth_types::{BlockHeader, Block, Transaction};
+use eth_types::{BlockHeader, Block, Signer, Transaction, TransactionInner, HexBytes, SH160, SH256, SU256};
+
+use statedb::StateDB;
+
+use crate::{AttestationReport, AuctionOutcome, BidReceipt, BlockAttestation, DispatcherStats, GetTranscriptRequest, Retraction, SignedResponse, TranscriptResponse, ProposerAideStatus, RequestQueue, SendBundleRequest, SubmitBlockRequest, WrappedBundle};
+use crate::{decode_transactions, TxRejectReason, TxRejection, TxValidator};
+use crate::{match_sidecars, BlobSidecar, KzgSettings};
+use crate::{MevBooTeeError, ProposerAuthenticator, PublishJob, SignedBlindedBeaconBlock};
+use crate::{PayloadAttributes, SignedPayloadAttributes, SignedValidatorRegistration};
 
 #[derive(Deserialize)]
 pub struct SubmitToBRequest {
     pub txns: Vec<String>,
-    pub bid: u32,
-    pub block_number: u32
+    pub bid: SU256,
+    pub block_number: u64,
+    #[serde(default)]
+    pub reverting_tx_hashes: Vec<SH256>,
+    #[serde(default)]
+    pub blob_sidecars: Vec<BlobSidecar>, // one for every blob transaction
 }
 
 impl SubmitToBRequest {
-    pub fn verify(&self) -> bool {
-        todo!()
+    // decode every transaction, recover its sender and check it against `state`,
+    // the state of the parent of the target block
+    pub fn verify<S: StateDB>(&self, chain_id: u64, state: &mut S) -> Result<Vec<Transaction>, TxRejection> {
+        let txns = self.into_transactions()?;
+        TxValidator::new(chain_id, state).validate_all(&txns)?;
+        Ok(txns)
+    }
+
+    pub fn into_transactions(&self) -> Result<Vec<Transaction>, TxRejection> {
+        decode_transactions(&self.txns)
+    }
+
+    // the submitter is the sender of the first transaction of the bundle
+    pub fn into_bundle(&self, chain_id: u64, timestamp: u64) -> Result<WrappedBundle, TxRejection> {
+        let txns = self.into_transactions()?;
+        let first = txns.first().ok_or(TxRejection { index: 0, reason: TxRejectReason::EmptyBundle })?;
+        let submitter = recover_sender(&Signer::new(chain_id.into()), first)
+            .map_err(|err| TxRejection { index: 0, reason: TxRejectReason::Signature(err) })?;
+        match_sidecars(&txns, &self.blob_sidecars)?;
+        Ok(WrappedBundle {
+            txns,
+            bid: self.bid,
+            block_number: self.block_number,
+            submitter,
+            timestamp,
+            reverting_tx_hashes: self.reverting_tx_hashes.clone(),
+            blob_sidecars: self.blob_sidecars.clone(),
+        })
     }
 
-    pub fn into_transactions(&self) -> Vec<Transaction> {
-        todo!()
+    // the KZG proofs of the sidecars, blobs are only accepted with a trusted setup
+    pub fn verify_blobs(&self, kzg: Option<&KzgSettings>) -> Result<(), JsonrpcErrorObj> {
+        if self.blob_sidecars.is_empty() {
+            return Ok(());
+        }
+        let kzg = kzg.ok_or_else(|| JsonrpcErrorObj::client("Bad request: blob transactions are not accepted".into()))?;
+        for sidecar in &self.blob_sidecars {
+            kzg.verify_sidecar(sidecar).map_err(|err| JsonrpcErrorObj::client(format!("Bad request: {}", err)))?;
+        }
+        Ok(())
     }
 }
 
@@ -32,38 +78,102 @@ pub struct GetBidRequest {
 }
 
 impl GetBidRequest {
+    // what the proposer signs: the block number and the raw transactions it wants included
+    pub fn signing_root(&self) -> SH256 {
+        let mut data = b"mev-bootee/get_bid".to_vec();
+        data.extend_from_slice(&(self.block_number as u64).to_be_bytes());
+        for txn in &self.txn_list {
+            data.extend_from_slice(&crypto::keccak_hash(txn.as_bytes()));
+        }
+        crypto::keccak_hash(&data).into()
+    }
+
     // check if sender is current proposer
-    pub fn validate_sender(&self) -> bool {
-        todo!()
+    pub fn validate_sender(&self, auth: &ProposerAuthenticator) -> Result<(), MevBooTeeError> {
+        auth.verify_request(self.block_number as u64, self.signing_root(), &self.signature)
+    }
+
+    // the request got its answer, it cannot be replayed anymore
+    pub fn mark_served(&self, auth: &ProposerAuthenticator) {
+        auth.mark_served(self.block_number as u64, self.signing_root())
     }
 
-    pub fn into_transactions(&self) -> Vec<Transaction> {
-        todo!()
+    pub fn into_transactions(&self) -> Result<Vec<Transaction>, TxRejection> {
+        decode_transactions(&self.txn_list)
     }
 }
 
 #[derive(Deserialize)]
 pub struct SignedHeader {
     pub header: BlockHeader,
-    pub signature: Vec<u8>
+    pub signature: Vec<u8>,
+    #[serde(default)]
+    pub signed_blinded_block: Option<SignedBlindedBeaconBlock>, // what the beacon publisher sends to the network
 }
 
 impl SignedHeader {
+    pub fn signing_root(&self) -> SH256 {
+        let mut data = b"mev-bootee/commit_header".to_vec();
+        data.extend_from_slice(self.header.hash().as_bytes());
+        crypto::keccak_hash(&data).into()
+    }
+
     // check if sender is current proposer
-    pub fn validate_sender(&self) -> bool {
-        todo!()
+    pub fn validate_sender(&self, auth: &ProposerAuthenticator) -> Result<(), MevBooTeeError> {
+        auth.verify_header(self.header.number.as_u64(), self.header.hash(), self.signing_root(), &self.signature)
     }
 }
 
+// decode a hex encoded raw transaction
+pub fn decode_transaction(txn: &str) -> Result<Transaction, String> {
+    let raw = HexBytes::from_hex(txn.as_bytes()).map_err(|err| format!("invalid hex: {:?}", err))?;
+    let inner = TransactionInner::from_bytes(&raw).map_err(|err| format!("invalid transaction: {:?}", err))?;
+    Ok(inner.to_transaction(None))
+}
+
+pub fn recover_sender(signer: &Signer, txn: &Transaction) -> Result<SH160, String> {
+    let inner = txn.inner().ok_or_else(|| "unsupported transaction type".to_owned())?;
+    signer.sender(&inner).map_err(|err| format!("{:?}", err))
+}
+
 pub enum JsonRpcServerMsg {
-    SubmitToB(SubmitToBRequest, Sender<Result<String, JsonrpcErrorObj>>),
-    RetractToB(String, Sender<bool>),
-    GetBid(GetBidRequest, Sender<Result<(u32, BlockHeader), JsonrpcErrorObj>>),
-    CommitHeader(SignedHeader, Sender<Result<bool, JsonrpcErrorObj>>)
+    SubmitToB(SubmitToBRequest, Sender<Result<BidReceipt, JsonrpcErrorObj>>),
+    RetractToB(String, Sender<SignedResponse<Retraction>>),
+    GetBid(GetBidRequest, Sender<Result<SignedResponse<(SU256, BlockHeader)>, JsonrpcErrorObj>>),
+    GetAuctionOutcome(u64, Sender<Result<Option<AuctionOutcome>, JsonrpcErrorObj>>),
+    GetAuctionTranscript(GetTranscriptRequest, Sender<Option<TranscriptResponse>>),
+    AttestationReport(Sender<AttestationReport>),
+    CommitHeader(SignedHeader, Sender<Result<bool, JsonrpcErrorObj>>),
+    SubmitInclusionList(GetBidRequest, Sender<Result<BlockHeader, JsonrpcErrorObj>>),
+    GetSlotStatus(u64, Sender<Option<ProposerAideStatus>>),
+    SubmitBlock(SubmitBlockRequest, Sender<Result<BlockAttestation, JsonrpcErrorObj>>),
+    GetBestBid(u64, Sender<Option<BlockAttestation>>),
+    GetHeader(GetBidRequest, Sender<Result<(SU256, BlockHeader), JsonrpcErrorObj>>),
+    SendRawTransaction(String, Sender<Result<SH256, JsonrpcErrorObj>>),
+    SendBundle(SendBundleRequest, Sender<Result<SH256, JsonrpcErrorObj>>),
+    SubmitPayloadAttributes(SignedPayloadAttributes, Sender<Result<bool, JsonrpcErrorObj>>),
+    RegisterValidator(SignedValidatorRegistration, Sender<Result<bool, JsonrpcErrorObj>>),
+    BeaconPayloadAttributes(PayloadAttributes), // from the beacon node event stream, nobody waits for an answer
+    Published(PublishJob, Result<String, MevBooTeeError>), // from the publisher thread, the job carries the reply
+}
+
+impl JsonRpcServerMsg {
+    // requests which only read the state, they are served concurrently with the others
+    // GetBid is not one of them: it stores the block it builds and marks the request as served
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            JsonRpcServerMsg::GetAuctionOutcome(..)
+                | JsonRpcServerMsg::GetAuctionTranscript(..)
+                | JsonRpcServerMsg::AttestationReport(..)
+                | JsonRpcServerMsg::GetSlotStatus(..)
+                | JsonRpcServerMsg::GetBestBid(..)
+        )
+    }
 }
 
 pub struct MevBooTeeAPI {
-    pub sender: Arc<Mutex<Sender<JsonRpcServerMsg>>>
+    pub queue: Arc<RequestQueue>,
 }
 
 impl MevBooTeeAPI {
@@ -72,33 +182,129 @@ impl MevBooTeeAPI {
         Ok(req)
     }
 
-    pub fn submit_tob(&self, args: RpcArgs<SubmitToBRequest>) -> Result<String, JsonrpcErrorObj> {
+    // answered by the RPC thread itself, so it stays available when the main loop is busy
+    pub fn get_dispatcher_metrics(&self, _args: RpcArgs<serde_json::Value>) -> Result<DispatcherStats, JsonrpcErrorObj> {
+        Ok(self.queue.metrics.stats())
+    }
+
+    // the receipt proves the bid was recorded before the deadline
+    pub fn submit_tob(&self, args: RpcArgs<SubmitToBRequest>) -> Result<BidReceipt, JsonrpcErrorObj> {
         let req = args.params;
         let (sender, receiver) = channel();
-        self.sender.lock().unwrap().send(JsonRpcServerMsg::SubmitToB(req, sender)).map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?;
+        self.queue.send(JsonRpcServerMsg::SubmitToB(req, sender))?;
         receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
     }
 
-    pub fn retract_tob(&self, args: RpcArgs<String>) -> Result<bool, JsonrpcErrorObj> {
+    pub fn retract_tob(&self, args: RpcArgs<String>) -> Result<SignedResponse<Retraction>, JsonrpcErrorObj> {
         let tob_id = args.params;
         let (sender, receiver) = channel();
-        self.sender.lock().unwrap().send(JsonRpcServerMsg::RetractToB(tob_id, sender)).map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?;
-        let removed = receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?;
-        Ok(removed)
+        self.queue.send(JsonRpcServerMsg::RetractToB(tob_id, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))
     }
 
-    pub fn get_highest_bid(&self, args: RpcArgs<GetBidRequest>) -> Result<(u32, BlockHeader), JsonrpcErrorObj> {
+    pub fn get_highest_bid(&self, args: RpcArgs<GetBidRequest>) -> Result<SignedResponse<(SU256, BlockHeader)>, JsonrpcErrorObj> {
         let req = args.params;
         let (sender, receiver) = channel();
-        self.sender.lock().unwrap().send(JsonRpcServerMsg::GetBid(req, sender)).map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?;
+        self.queue.send(JsonRpcServerMsg::GetBid(req, sender))?;
         receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
     }
 
+    // the winning ToB of the slot, once its bid deadline passed
+    pub fn get_auction_outcome(&self, args: RpcArgs<u64>) -> Result<Option<AuctionOutcome>, JsonrpcErrorObj> {
+        let block_number = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::GetAuctionOutcome(block_number, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
+    }
+
+    // the signed transcript of a sealed slot, with the inclusion proof of `tob_id` when given
+    pub fn get_auction_transcript(&self, args: RpcArgs<GetTranscriptRequest>) -> Result<Option<TranscriptResponse>, JsonrpcErrorObj> {
+        let req = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::GetAuctionTranscript(req, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))
+    }
+
+    // the quote of the enclave, its report data binds the signing and the encryption keys
+    pub fn attestation_report(&self, _args: RpcArgs<serde_json::Value>) -> Result<AttestationReport, JsonrpcErrorObj> {
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::AttestationReport(sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))
+    }
+
 
     pub fn commit_header(&self, args: RpcArgs<SignedHeader>) -> Result<bool, JsonrpcErrorObj> {
         let signed_header = args.params;
         let (sender, receiver) = channel();
-        self.sender.lock().unwrap().send(JsonRpcServerMsg::CommitHeader(signed_header, sender)).map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?;
+        self.queue.send(JsonRpcServerMsg::CommitHeader(signed_header, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
+    }
+
+    // PEPC-TEE: the proposer sends its signed inclusion list and gets back the header of a block containing it
+    pub fn submit_inclusion_list(&self, args: RpcArgs<GetBidRequest>) -> Result<BlockHeader, JsonrpcErrorObj> {
+        let req = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::SubmitInclusionList(req, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
+    }
+
+    pub fn get_slot_status(&self, args: RpcArgs<u64>) -> Result<Option<ProposerAideStatus>, JsonrpcErrorObj> {
+        let block_number = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::GetSlotStatus(block_number, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))
+    }
+
+    // builder-relayer: a builder submits a full candidate block, the enclave re-executes it
+    pub fn submit_block(&self, args: RpcArgs<SubmitBlockRequest>) -> Result<BlockAttestation, JsonrpcErrorObj> {
+        let req = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::SubmitBlock(req, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
+    }
+
+    pub fn get_best_bid(&self, args: RpcArgs<u64>) -> Result<Option<BlockAttestation>, JsonrpcErrorObj> {
+        let block_number = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::GetBestBid(block_number, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))
+    }
+
+    // builder-relayer: the proposer fetches the header of the best verified block
+    pub fn get_header(&self, args: RpcArgs<GetBidRequest>) -> Result<(SU256, BlockHeader), JsonrpcErrorObj> {
+        let req = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::GetHeader(req, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
+    }
+
+    // full TEE builder: the transaction goes into the private mempool, only its hash comes back
+    pub fn send_raw_transaction(&self, args: RpcArgs<String>) -> Result<SH256, JsonrpcErrorObj> {
+        let txn = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::SendRawTransaction(txn, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
+    }
+
+    pub fn send_bundle(&self, args: RpcArgs<SendBundleRequest>) -> Result<SH256, JsonrpcErrorObj> {
+        let req = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::SendBundle(req, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
+    }
+
+    // the proposer supplies the payload attributes of its block when the enclave has no beacon node
+    pub fn submit_payload_attributes(&self, args: RpcArgs<SignedPayloadAttributes>) -> Result<bool, JsonrpcErrorObj> {
+        let req = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::SubmitPayloadAttributes(req, sender))?;
+        receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
+    }
+
+    pub fn register_validator(&self, args: RpcArgs<SignedValidatorRegistration>) -> Result<bool, JsonrpcErrorObj> {
+        let req = args.params;
+        let (sender, receiver) = channel();
+        self.queue.send(JsonRpcServerMsg::RegisterValidator(req, sender))?;
         receiver.recv().map_err(|_| JsonrpcErrorObj::unknown("unresponsive"))?
     }
 }
@@ -1,95 +1,359 @@
-use std::{prelude::v1::*, sync::Mutex};
+use std::{prelude::v1::*, sync::{Mutex, RwLock}};
 
-use apps::AppEnv;
+use apps::{AppEnv, Var};
 use base::trace::Alive;
 
 use jsonrpc::{RpcServer, JsonrpcErrorObj, RpcServerConfig};
-use std::sync::mpsc::{Sender, channel, Receiver, TryRecvError};
-use eth_types::{Block, BlockHeader, Transaction, SH256};
+use std::sync::mpsc::{Sender, channel, Receiver, RecvTimeoutError};
+use eth_types::{Block, BlockHeader, HexBytes, Transaction, Withdrawal, SH256, SU256};
 use eth_tools::{ExecutionClient, MixRpcClient};
+use evm_executor::ConsensusBlockInfo;
 
 use std::sync::Arc;
 use std::collections::BTreeMap;
-use crate::{GetBidRequest, MevBooTeeMode, SignedHeader};
+use std::time::{Duration, Instant};
+use crate::{GetBidRequest, MevBooTeeMode, SignedHeader, ProposerAide, ProposerAideStatus};
+use crate::{verify_candidate, BlockAttestation, BuilderAide, SubmitBlockRequest};
+use crate::{decode_transaction, EncryptedMempool, FullTeeBuilder, MempoolOrder, MevBooTeeError, SendBundleRequest};
+use crate::{parent_state, unix_timestamp, unix_timestamp_millis, BundlePool, ElClient, MevBooTeeConfig};
+use crate::{build_block_with, build_full_block_with, GreedyBlockBuildingStrategy, MergingBlockBuildingStrategy, StrategyKind};
+use crate::{is_blob_transaction, BlobSidecar, KzgSettings};
+use crate::{genesis_time, proposal_slot, BeaconClient, BeaconDuties, DutyFile, ProposerAuthenticator, ProposerDuties};
+use crate::{run_publisher, PublishJob, Publishers, SignedBlindedBeaconBlock, SignedBlock};
+use crate::{fetch_expected_withdrawals, parse_payload_attributes_event, PayloadAttributes, PayloadAttributesStore, SignedPayloadAttributes, SignedValidatorRegistration};
 
+use crate::{ChainHead, DispatcherMetrics, HeadEvent, HeadTracker, RequestQueue};
+use crate::{AuctionOutcome, Auctions, BidReceipt, Payment, EnclaveKey, WrappedBundle, SECONDS_PER_SLOT};
+use crate::{GetTranscriptRequest, Retraction, SignedResponse, TranscriptResponse, Transcripts};
+use crate::{default_quote_provider, AttestationReport, EncryptionKey};
 use crate::{MevBooTeeAPI, JsonRpcServerMsg, SubmitToBRequest};
 
+// how often the full TEE builder rebuilds the block of the next slot
+const FULL_BUILDER_INTERVAL: Duration = Duration::from_millis(500);
+// how often the chain head is polled, a payload_attributes event of the beacon node triggers a poll as well
+const HEAD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// longest wait for a request, so that a shutdown is noticed
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// how often the queue depths are logged
+const METRICS_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+// threads serving the read-only requests
+const READER_THREADS: usize = 4;
+// extra data of the blocks built by the enclave
+const EXTRA_DATA: &[u8] = b"mev-bootee";
+
+// what building the block of a slot needs from the execution client: its parent and its execution on the parent state
+// the block comes with its bid, its blob sidecars and what each included bundle pays
+pub trait ExecutionLayer: Send + Sync {
+    fn header(&self, block_number: u64) -> Result<BlockHeader, MevBooTeeError>;
+    fn build_block(
+        &self, strategy: StrategyKind, parent: BlockHeader, info: ConsensusBlockInfo, withdrawals: Option<Vec<Withdrawal>>,
+        parent_beacon_block_root: Option<SH256>, bundles: Vec<(String, WrappedBundle)>, inclusion_list: &[Transaction],
+    ) -> Result<(SU256, Block, Vec<BlobSidecar>, Vec<(String, SU256)>), MevBooTeeError>;
+}
+
+impl ExecutionLayer for ElClient {
+    fn header(&self, block_number: u64) -> Result<BlockHeader, MevBooTeeError> {
+        self.get_block_header(block_number.into())
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to fetch block {}: {:?}", block_number, err)))
+    }
+
+    fn build_block(
+        &self, strategy: StrategyKind, parent: BlockHeader, info: ConsensusBlockInfo, withdrawals: Option<Vec<Withdrawal>>,
+        parent_beacon_block_root: Option<SH256>, bundles: Vec<(String, WrappedBundle)>, inclusion_list: &[Transaction],
+    ) -> Result<(SU256, Block, Vec<BlobSidecar>, Vec<(String, SU256)>), MevBooTeeError> {
+        let el = self.clone();
+        match strategy {
+            StrategyKind::Greedy => build_block_with::<GreedyBlockBuildingStrategy>(el, parent, info, withdrawals, parent_beacon_block_root, bundles, inclusion_list),
+            StrategyKind::Merging => build_block_with::<MergingBlockBuildingStrategy>(el, parent, info, withdrawals, parent_beacon_block_root, bundles, inclusion_list),
+        }
+    }
+}
+
 pub struct MevBooTee {
     pub alive: Alive,
-    el: Arc<ExecutionClient<Arc<MixRpcClient>>>,
+    pub cfg: Var<MevBooTeeConfig>,
+    el: Var<ExecutionClient<Arc<MixRpcClient>>>,
+    execution: Var<Box<dyn ExecutionLayer>>, // the execution client, behind the blocks built for the slots
+    chain_id: Var<u64>,
+    auth: Var<ProposerAuthenticator>,
+    publishers: Var<Publishers>,
+    kzg: Var<Option<KzgSettings>>,
+    enclave_key: Var<EnclaveKey>,
+    encryption_key: Var<EncryptionKey>,
+    attestation: Var<AttestationReport>,
     pub srv_receiver: Mutex<Receiver<JsonRpcServerMsg>>,
-    pub srv_sender: Arc<Mutex<Sender<JsonRpcServerMsg>>>,
-    state: Mutex<State>,
-    pub mode: MevBooTeeMode,
-    pub do_verification: bool
+    pub srv_queue: Arc<RequestQueue>,
+    publish_jobs: Mutex<Sender<PublishJob>>,
+    publish_receiver: Mutex<Option<Receiver<PublishJob>>>, // taken by the publisher thread
+    state: Arc<Mutex<State>>,
+    records: Arc<RwLock<SlotRecords>>, // taken after `state` when both are needed
 }
 
 impl Default for MevBooTee {
     fn default() -> Self {
         let (sender, receiver) = channel();
-        let alive = Alive::new();
-        let mut client = MixRpcClient::new(None);
-        client
-            .add_endpoint(&alive, &["http://localhost:8545".to_owned()])
-            .unwrap();
-        let el: Arc<ExecutionClient<Arc<MixRpcClient>>> = Arc::new(ExecutionClient::new(Arc::new(client)));
+        let (publish_jobs, publish_receiver) = channel();
         Self {
-            alive,
+            alive: Alive::new(),
+            cfg: Var::default(),
+            el: Var::default(),
+            execution: Var::default(),
+            chain_id: Var::default(),
+            auth: Var::default(),
+            publishers: Var::default(),
+            kzg: Var::default(),
+            enclave_key: Var::default(),
+            encryption_key: Var::default(),
+            attestation: Var::default(),
             srv_receiver: Mutex::new(receiver),
-            srv_sender: Arc::new(Mutex::new(sender)),
-            state: Mutex::new(State::default()),
-            mode: MevBooTeeMode::Assembler,
-            do_verification: false,
-            el,
+            srv_queue: Arc::new(RequestQueue::new(sender, Arc::default())),
+            publish_jobs: Mutex::new(publish_jobs),
+            publish_receiver: Mutex::new(Some(publish_receiver)),
+            state: Arc::new(Mutex::new(State::default())),
+            records: Arc::default(),
         }
     }
 }
 
 impl MevBooTee {
-    pub fn config(&mut self, mode: MevBooTeeMode, do_verification: bool) {
-        self.mode = mode;
-        self.do_verification = do_verification;
+    // connect to the execution endpoints and check them against the config
+    pub fn init(&self, cfg: MevBooTeeConfig) -> Result<(), MevBooTeeError> {
+        let mut client = MixRpcClient::new(None);
+        client
+            .add_endpoint(&self.alive, &cfg.el_endpoints)
+            .map_err(|err| MevBooTeeError::Config(format!("invalid execution endpoints {:?}: {:?}", cfg.el_endpoints, err)))?;
+        let el = ExecutionClient::new(Arc::new(client));
+
+        let el_chain_id = el.chain_id()
+            .map_err(|err| MevBooTeeError::Config(format!("unable to reach execution endpoints {:?}: {:?}", cfg.el_endpoints, err)))?;
+        if let Some(chain_id) = cfg.chain_id {
+            if chain_id != el_chain_id {
+                return Err(MevBooTeeError::Config(format!("chain id mismatch: configured {}, execution client reports {}", chain_id, el_chain_id)));
+            }
+        }
+
+        self.el.set(el);
+        self.execution.set(Box::new(self.el()));
+        let duties: Box<dyn ProposerDuties> = match (&cfg.duty_file, &cfg.beacon_endpoint) {
+            (Some(path), _) => Box::new(DutyFile::from_file(path)?),
+            (None, Some(endpoint)) => Box::new(BeaconDuties::new(BeaconClient::new(endpoint), self.el())),
+            (None, None) => return Err(MevBooTeeError::Config("proposer duties unavailable: either a duty file or a beacon endpoint is required".into())),
+        };
+
+        glog::info!("mode: {:?}, listen: {}, chain id: {}, execution endpoints: {:?}", cfg.mode, cfg.server.listen_addr, el_chain_id, cfg.el_endpoints);
+        let publish_endpoints = cfg.publish_endpoints();
+        if publish_endpoints.is_empty() {
+            glog::warn!("no publish endpoint configured, committed blocks will not reach the network");
+        }
+        self.publishers.set(Publishers::from_endpoints(cfg.publisher.kind, &publish_endpoints, cfg.publisher.rounds));
+        let kzg = match &cfg.kzg_setup {
+            Some(path) => Some(KzgSettings::load(path)?),
+            None => {
+                glog::warn!("no KZG trusted setup configured, blob transactions will be rejected");
+                None
+            },
+        };
+        self.kzg.set(kzg);
+        let enclave_key = EnclaveKey::generate();
+        let encryption_key = EncryptionKey::generate();
+        let attestation = AttestationReport::new(default_quote_provider().as_ref(), &enclave_key, &encryption_key)?;
+        glog::info!("enclave key: {:?}, encryption key: {:?}, report data: {:?}", attestation.signing_key, attestation.encryption_key, HexBytes::from(attestation.report_data().to_vec()));
+        self.enclave_key.set(enclave_key);
+        self.encryption_key.set(encryption_key);
+        self.attestation.set(attestation);
+        // the mempool of the full TEE builder is sealed under the attested key
+        self.state.lock().unwrap().full_builder.mempool = EncryptedMempool::with_key(self.encryption_key.unwrap().derive_key(b"mempool"));
+        self.auth.set(ProposerAuthenticator::new(duties));
+        self.chain_id.set(el_chain_id);
+        self.cfg.set(cfg);
+        Ok(())
     }
 
+    pub fn mode(&self) -> MevBooTeeMode {
+        self.cfg.unwrap().mode
+    }
+
+    fn el(&self) -> ElClient {
+        self.el.unwrap()
+    }
+
+    fn chain_id(&self) -> u64 {
+        *self.chain_id.unwrap()
+    }
+
+    fn do_verification(&self) -> bool {
+        self.cfg.unwrap().do_verification
+    }
+
+    // read-only requests go to a pool of reader threads, so that they do not wait behind a block being built
     fn run(&self) {
+        let (read_sender, read_receiver) = channel();
+        let read_receiver = Arc::new(Mutex::new(read_receiver));
+        let readers: Vec<_> = (0..READER_THREADS)
+            .map(|i| {
+                let reader = self.reader();
+                let read_receiver = read_receiver.clone();
+                base::thread::spawn(format!("reader-{}", i), move || reader.run(&read_receiver))
+            })
+            .collect();
+        // the readers stop once `read_sender` is dropped
+        self.dispatch(read_sender);
+        for reader in readers {
+            if reader.join().is_err() {
+                glog::error!("reader thread panicked");
+            }
+        }
+    }
+
+    // block on the request queue, the periodic tasks run in between
+    fn dispatch(&self, reads: Sender<JsonRpcServerMsg>) {
+        let receiver = self.srv_receiver.lock().unwrap();
+        let metrics = self.srv_queue.metrics.clone();
+        let mut last_build = Instant::now();
+        let mut last_head_poll = Instant::now();
+        let mut last_report = Instant::now();
         while self.alive.is_alive() {
-            let msg = self.srv_receiver.lock().unwrap().try_recv();
-            match msg {
+            match receiver.recv_timeout(self.next_wait(last_head_poll, last_build)) {
                 Ok(msg) => {
-                    match msg {
-                        JsonRpcServerMsg::SubmitToB(req, sender) => self.handle_submit_tob_request(req, sender),
-                        JsonRpcServerMsg::RetractToB(req, sender) => self.handle_retract_tob_request(&req, sender),
-                        JsonRpcServerMsg::GetBid(req, sender) => self.handle_get_bid_request(req, sender),
-                        JsonRpcServerMsg::CommitHeader(signed_header, sender) => self.handle_commit_header_request(&signed_header, sender),
+                    metrics.requests.pop();
+                    if msg.is_read_only() {
+                        metrics.reads.push();
+                        if reads.send(msg).is_err() {
+                            metrics.reads.pop();
+                            glog::error!("no reader thread left");
+                        }
+                    } else {
+                        self.handle(msg);
                     }
                 },
-                Err(e) =>
-                    if e != TryRecvError::Empty {
-                        glog::error!("Error reading message from server: {:?}", e)
-                    },
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => {
+                    glog::error!("request queue closed");
+                    return;
+                },
+            }
+
+            if last_head_poll.elapsed() >= HEAD_POLL_INTERVAL {
+                self.poll_head();
+                last_head_poll = Instant::now();
+            }
+
+            if let MevBooTeeMode::FullTeeBuilder = self.mode() {
+                if last_build.elapsed() >= FULL_BUILDER_INTERVAL {
+                    self.run_full_builder();
+                    last_build = Instant::now();
+                }
+            }
+
+            if last_report.elapsed() >= METRICS_REPORT_INTERVAL {
+                glog::info!("dispatcher: {:?}", metrics.stats());
+                last_report = Instant::now();
             }
         }
     }
 
+    // until the next periodic task is due
+    fn next_wait(&self, last_head_poll: Instant, last_build: Instant) -> Duration {
+        let mut wait = HEAD_POLL_INTERVAL.saturating_sub(last_head_poll.elapsed());
+        if let MevBooTeeMode::FullTeeBuilder = self.mode() {
+            wait = wait.min(FULL_BUILDER_INTERVAL.saturating_sub(last_build.elapsed()));
+        }
+        wait.min(SHUTDOWN_CHECK_INTERVAL)
+    }
+
+    fn handle(&self, msg: JsonRpcServerMsg) {
+        match msg {
+            JsonRpcServerMsg::SubmitToB(req, sender) => self.handle_submit_tob_request(req, sender),
+            JsonRpcServerMsg::RetractToB(req, sender) => self.handle_retract_tob_request(&req, sender),
+            JsonRpcServerMsg::GetBid(req, sender) => self.handle_get_bid_request(req, sender),
+            JsonRpcServerMsg::CommitHeader(signed_header, sender) => match self.mode() {
+                MevBooTeeMode::ProposerAide => self.handle_proposer_commit_header_request(&signed_header, sender),
+                MevBooTeeMode::BuilderAide => self.handle_builder_commit_header_request(&signed_header, sender),
+                MevBooTeeMode::FullTeeBuilder => self.handle_full_builder_commit_header_request(&signed_header, sender),
+                _ => self.handle_commit_header_request(&signed_header, sender),
+            },
+            JsonRpcServerMsg::SubmitInclusionList(req, sender) => self.handle_submit_inclusion_list_request(req, sender),
+            JsonRpcServerMsg::SubmitBlock(req, sender) => self.handle_submit_block_request(req, sender),
+            JsonRpcServerMsg::GetHeader(req, sender) => match self.mode() {
+                MevBooTeeMode::FullTeeBuilder => self.handle_full_builder_get_header_request(req, sender),
+                _ => self.handle_get_header_request(req, sender),
+            },
+            JsonRpcServerMsg::SendRawTransaction(txn, sender) => self.handle_send_raw_transaction_request(&txn, sender),
+            JsonRpcServerMsg::SendBundle(req, sender) => self.handle_send_bundle_request(req, sender),
+            JsonRpcServerMsg::SubmitPayloadAttributes(req, sender) => self.handle_submit_payload_attributes_request(req, sender),
+            JsonRpcServerMsg::RegisterValidator(req, sender) => self.handle_register_validator_request(req, sender),
+            JsonRpcServerMsg::BeaconPayloadAttributes(attributes) => self.handle_beacon_payload_attributes(attributes),
+            JsonRpcServerMsg::Published(job, result) => self.handle_published(job, result),
+            msg => self.reader().handle(msg),
+        }
+    }
+
+    // what the reader threads need, they serve the read-only requests on their own clones
+    fn reader(&self) -> StateReader {
+        StateReader {
+            records: self.records.clone(),
+            attestation: self.attestation.unwrap(),
+            metrics: self.srv_queue.metrics.clone(),
+            payment: self.cfg.unwrap().auction.payment,
+        }
+    }
+
     pub fn start(&self) {
         glog::info!("running MEV-BooTEE");
 
         let rpc_srv_handle = base::thread::spawn("jsonrpc-server".into(), {
             let mut cfg = RpcServerConfig::default();
-            cfg.listen_addr = "0.0.0.0:1234".into();
-            let context = Arc::new(MevBooTeeAPI{sender: self.srv_sender.clone()});
+            cfg.listen_addr = self.cfg.unwrap().server.listen_addr.clone();
+            let context = Arc::new(MevBooTeeAPI{queue: self.srv_queue.clone()});
             let mut srv = RpcServer::<MevBooTeeAPI>::new(self.alive.clone(), cfg, context).unwrap();
-            match self.mode {
-                MevBooTeeMode::ProposerAide => todo!(),
-                MevBooTeeMode::BuilderAide => todo!(),
+            match self.mode() {
+                MevBooTeeMode::ProposerAide => {
+                    srv.jsonrpc("echo", MevBooTeeAPI::echo);
+                    srv.jsonrpc("get_dispatcher_metrics", MevBooTeeAPI::get_dispatcher_metrics);
+                    srv.jsonrpc("attestation_report", MevBooTeeAPI::attestation_report);
+                    srv.jsonrpc("submit_tob", MevBooTeeAPI::submit_tob);
+                    srv.jsonrpc("retract_tob", MevBooTeeAPI::retract_tob);
+                    srv.jsonrpc("get_auction_outcome", MevBooTeeAPI::get_auction_outcome);
+                    srv.jsonrpc("get_auction_transcript", MevBooTeeAPI::get_auction_transcript);
+                    srv.jsonrpc("submit_inclusion_list", MevBooTeeAPI::submit_inclusion_list);
+                    srv.jsonrpc("commit_header", MevBooTeeAPI::commit_header);
+                    srv.jsonrpc("get_slot_status", MevBooTeeAPI::get_slot_status);
+                    srv.jsonrpc("submit_payload_attributes", MevBooTeeAPI::submit_payload_attributes);
+                    srv.jsonrpc("register_validator", MevBooTeeAPI::register_validator);
+                },
+                MevBooTeeMode::BuilderAide => {
+                    srv.jsonrpc("echo", MevBooTeeAPI::echo);
+                    srv.jsonrpc("get_dispatcher_metrics", MevBooTeeAPI::get_dispatcher_metrics);
+                    srv.jsonrpc("attestation_report", MevBooTeeAPI::attestation_report);
+                    srv.jsonrpc("submit_block", MevBooTeeAPI::submit_block);
+                    srv.jsonrpc("get_best_bid", MevBooTeeAPI::get_best_bid);
+                    srv.jsonrpc("get_header", MevBooTeeAPI::get_header);
+                    srv.jsonrpc("commit_header", MevBooTeeAPI::commit_header);
+                },
                 MevBooTeeMode::Assembler => {
                     srv.jsonrpc("echo", MevBooTeeAPI::echo);
+                    srv.jsonrpc("get_dispatcher_metrics", MevBooTeeAPI::get_dispatcher_metrics);
+                    srv.jsonrpc("attestation_report", MevBooTeeAPI::attestation_report);
                     srv.jsonrpc("submit_tob", MevBooTeeAPI::submit_tob);
                     srv.jsonrpc("retract_tob", MevBooTeeAPI::retract_tob);
+                    srv.jsonrpc("get_auction_outcome", MevBooTeeAPI::get_auction_outcome);
+                    srv.jsonrpc("get_auction_transcript", MevBooTeeAPI::get_auction_transcript);
                     srv.jsonrpc("get_highest_bid", MevBooTeeAPI::get_highest_bid);
+                    srv.jsonrpc("submit_payload_attributes", MevBooTeeAPI::submit_payload_attributes);
+                    srv.jsonrpc("register_validator", MevBooTeeAPI::register_validator);
+                    srv.jsonrpc("commit_header", MevBooTeeAPI::commit_header);
+                },
+                MevBooTeeMode::FullTeeBuilder => {
+                    srv.jsonrpc("echo", MevBooTeeAPI::echo);
+                    srv.jsonrpc("get_dispatcher_metrics", MevBooTeeAPI::get_dispatcher_metrics);
+                    srv.jsonrpc("attestation_report", MevBooTeeAPI::attestation_report);
+                    srv.jsonrpc("send_raw_transaction", MevBooTeeAPI::send_raw_transaction);
+                    srv.jsonrpc("send_bundle", MevBooTeeAPI::send_bundle);
+                    srv.jsonrpc("submit_payload_attributes", MevBooTeeAPI::submit_payload_attributes);
+                    srv.jsonrpc("register_validator", MevBooTeeAPI::register_validator);
+                    srv.jsonrpc("get_header", MevBooTeeAPI::get_header);
                     srv.jsonrpc("commit_header", MevBooTeeAPI::commit_header);
                 },
-                MevBooTeeMode::FullTeeBuilder => todo!(),
             }
             
             move || {
@@ -97,102 +361,702 @@ impl MevBooTee {
             }
         });
 
+        let payload_attributes_handle = self.cfg.unwrap().beacon_endpoint.clone().map(|endpoint| {
+            base::thread::spawn("payload-attributes".into(), {
+                let alive = self.alive.clone();
+                let queue = self.srv_queue.clone();
+                let beacon = BeaconClient::new(&endpoint);
+                move || listen_payload_attributes(alive, beacon, queue)
+            })
+        });
+
+        let publisher_handle = self.publish_receiver.lock().unwrap().take().map(|jobs| {
+            base::thread::spawn("publisher".into(), {
+                let alive = self.alive.clone();
+                let publishers = self.publishers.unwrap();
+                let queue = self.srv_queue.clone();
+                move || run_publisher(alive, publishers, jobs, queue)
+            })
+        });
+
         self.run();
 
         rpc_srv_handle.join().expect("failed to join RPC server");
+        if let Some(handle) = payload_attributes_handle {
+            handle.join().expect("failed to join payload attributes listener");
+        }
+        if let Some(handle) = publisher_handle {
+            handle.join().expect("failed to join publisher");
+        }
     }
 
-    fn handle_submit_tob_request(&self, tob_request: SubmitToBRequest, sender: Sender<Result<String, JsonrpcErrorObj>>) {
-        if self.do_verification {
-            if !tob_request.verify() {
-                if let Err(e) = sender.send(Err(JsonrpcErrorObj::client("Bad request: ToB is invalid".into()))) {
+    // follow the chain head, each new head seals the slots that landed and opens the next one
+    fn poll_head(&self) {
+        let el = self.el();
+        let header = el.head()
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to fetch chain head: {:?}", err)))
+            .and_then(|head| self.parent_header(&el, head + 1));
+        let header = match header {
+            Ok(header) => header,
+            Err(err) => {
+                glog::error!("{}", err);
+                return;
+            }
+        };
+        let event = self.state.lock().unwrap().heads.update(ChainHead::from_header(&header));
+        if let Some(event) = event {
+            self.on_new_head(event);
+        }
+    }
+
+    // drop everything that targets a sealed slot, and what was built on a parent which is no longer canonical
+    fn on_new_head(&self, event: HeadEvent) {
+        let head = event.head;
+        let open = head.open_slot();
+        match event.reorg_from {
+            Some(from) => glog::warn!("reorg from block {}, new head {} {:?}", from, head.number, head.hash),
+            None => glog::info!("new head {} {:?}, sealed slots {:?}", head.number, head.hash, event.sealed()),
+        }
+        let enclave_key = self.enclave_key.unwrap();
+        {
+            let mut state = self.state.lock().unwrap();
+            let mut records = self.records.write().unwrap();
+            state.tobs.on_new_head(head.number);
+            for closed in records.auctions.seal(open) {
+                let transcript = records.transcripts.insert(&enclave_key, closed.block_number, closed.deadline, closed.receipts);
+                glog::info!("auction of block {} sealed, {} bids, transcript root {:?}", transcript.block_number, transcript.bids, transcript.root);
+            }
+            state.blocks.retain(|_, block| head.is_candidate(block));
+            state.blob_sidecars = state.blob_sidecars.split_off(&(open, SH256::default()));
+            records.proposer_aide.prune(open);
+            records.proposer_aide.rebase(&head);
+            records.builder_aide.prune(open);
+            records.builder_aide.rebase(&head);
+            state.full_builder.prune(open);
+            state.full_builder.rebase(&head);
+            state.payload_attributes.prune(open);
+        }
+        self.auth.unwrap().prune(open);
+
+        // the full builder does not wait for a request to rebuild on the new parent
+        if event.reorg_from.is_some() && self.mode() == MevBooTeeMode::FullTeeBuilder {
+            self.run_full_builder();
+        }
+    }
+
+    // only the slot following the chain head takes bids
+    fn check_open_slot(&self, block_number: u64) -> Result<(), MevBooTeeError> {
+        match self.state.lock().unwrap().heads.open_slot() {
+            Some(open) if open != block_number => Err(MevBooTeeError::Slot(format!("block {} is not the open slot {}", block_number, open))),
+            _ => Ok(()),
+        }
+    }
+
+    // the start of the slot of `block_number`: the timestamp of its payload attributes, or else the one expected from the head
+    fn slot_start(&self, block_number: u64) -> Result<u64, MevBooTeeError> {
+        let state = self.state.lock().unwrap();
+        if let Some(attributes) = state.payload_attributes.get(block_number) {
+            return Ok(attributes.timestamp);
+        }
+        match state.heads.head() {
+            Some(head) if block_number > head.number => Ok(head.timestamp + SECONDS_PER_SLOT * (block_number - head.number)),
+            _ => Err(MevBooTeeError::Slot(format!("start of slot {} unknown", block_number))),
+        }
+    }
+
+    fn bid_deadline(&self, block_number: u64) -> Result<u64, MevBooTeeError> {
+        Ok(self.cfg.unwrap().auction.deadline(self.slot_start(block_number)?))
+    }
+
+    // payload attributes built on a block we have not seen yet announce a new head
+    fn handle_beacon_payload_attributes(&self, attributes: PayloadAttributes) {
+        let block_number = attributes.block_number;
+        self.auth.unwrap().note_timestamp(block_number, attributes.timestamp);
+        let open_slot = {
+            let mut state = self.state.lock().unwrap();
+            state.payload_attributes.insert(attributes);
+            state.heads.open_slot()
+        };
+        if open_slot.map(|open| block_number > open).unwrap_or(true) {
+            self.poll_head();
+        }
+    }
+
+    fn handle_submit_tob_request(&self, tob_request: SubmitToBRequest, sender: Sender<Result<BidReceipt, JsonrpcErrorObj>>) {
+        if self.do_verification() {
+            if let Err(err) = self.verify_tob(&tob_request) {
+                if let Err(e) = sender.send(Err(err)) {
                     glog::error!("unable to send back on channel: {:?}", e);
                 }
                 return;
             }
         }
 
-        let mut random = [0_u8; 32];
-        crypto::read_rand(&mut random);
-        let tob_id = std::str::from_utf8(&random[..]).unwrap();
+        let kzg = self.kzg.unwrap();
+        let bundle = tob_request.into_bundle(self.chain_id(), unix_timestamp())
+            .map_err(Into::into)
+            .and_then(|bundle| tob_request.verify_blobs(Arc::as_ref(&kzg).as_ref()).map(|_| bundle));
+        let bundle = match bundle {
+            Ok(bundle) => bundle,
+            Err(err) => {
+                if let Err(e) = sender.send(Err(err)) {
+                    glog::error!("unable to send back on channel: {:?}", e);
+                }
+                return;
+            }
+        };
+
+        let result = self.bid_deadline(bundle.block_number)
+            .and_then(|deadline| self.record_tob(bundle, deadline))
+            .map_err(Into::into);
+        if let Err(e) = sender.send(result) {
+            glog::error!("unable to send receipt back: {:?}", e);
+        }
+    }
 
+    // the bid goes into the pool and into the auction of its slot
+    // a resubmission of a known bundle is acknowledged with the same receipt
+    fn record_tob(&self, bundle: WrappedBundle, deadline: u64) -> Result<BidReceipt, MevBooTeeError> {
+        let tob_id = bundle.id();
+        let now = unix_timestamp_millis();
         let mut state = self.state.lock().unwrap();
-        state.tobs.insert(tob_id.into(), (tob_request.bid, tob_request.txns));
-        if let Err(e) = sender.send(Ok(tob_id.into())) {
-            glog::error!("unable to send tob_id back: {:?}", e);
+        let mut records = self.records.write().unwrap();
+        let (received_at, deadline) = records.auctions.record(&tob_id, &bundle, deadline, now)?;
+        let receipt = BidReceipt::new(&self.enclave_key.unwrap(), tob_id.clone(), &bundle, received_at, deadline);
+        if let Err(err) = state.tobs.insert(tob_id.clone(), bundle) {
+            records.auctions.retract(receipt.block_number, &tob_id, now);
+            return Err(err);
         }
+        Ok(receipt)
+    }
+
+    // check the ToB against the state it will execute on
+    fn verify_tob(&self, tob_request: &SubmitToBRequest) -> Result<(), JsonrpcErrorObj> {
+        let el = self.el();
+        let parent_number = tob_request.block_number.checked_sub(1)
+            .ok_or_else(|| JsonrpcErrorObj::client("Bad request: invalid block number".into()))?;
+        let parent = el.get_block_header(parent_number.into())
+            .map_err(|err| JsonrpcErrorObj::client(format!("Bad request: parent block {} unavailable: {:?}", parent_number, err)))?;
+        let mut state = parent_state(&el, &parent);
+        tob_request.verify(self.chain_id(), &mut state)?;
+        Ok(())
     }
 
-    fn handle_retract_tob_request(&self, tob_id: &String, sender: Sender<bool>) {
-        let removed = match self.state.lock().unwrap().tobs.remove(tob_id) {
-            Some(_) => true,
-            None => false,
+    // bids are final once the deadline of their slot passed
+    fn handle_retract_tob_request(&self, tob_id: &String, sender: Sender<SignedResponse<Retraction>>) {
+        let now = unix_timestamp_millis();
+        let mut state = self.state.lock().unwrap();
+        let mut records = self.records.write().unwrap();
+        let removed = match state.tobs.get(tob_id).map(|bundle| bundle.block_number) {
+            Some(block_number) if records.auctions.retract(block_number, tob_id, now) => state.tobs.remove(tob_id).is_some(),
+            _ => false,
         };
-        if let Err(e) = sender.send(removed) {
+        drop(records);
+        drop(state);
+        let retraction = Retraction { tob_id: tob_id.clone(), retracted: removed, timestamp: now };
+        if let Err(e) = sender.send(SignedResponse::new(&self.enclave_key.unwrap(), "retract_tob", retraction)) {
             glog::error!("unable to send on channel back: {:?}", e);
         }
     }
 
-    fn handle_get_bid_request(&self, get_bid_request: GetBidRequest, sender: Sender<Result<(u32, BlockHeader), JsonrpcErrorObj>>) {
-        if !get_bid_request.validate_sender() {
-            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client("Bad sender".into()))) {
+    fn handle_get_bid_request(&self, get_bid_request: GetBidRequest, sender: Sender<Result<SignedResponse<(SU256, BlockHeader)>, JsonrpcErrorObj>>) {
+        if let Err(err) = get_bid_request.validate_sender(&self.auth.unwrap()) {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
                 glog::error!("unable to send back on channel: {:?}", e);
             }
             return;
         }
 
-        let mut rob = get_bid_request.txn_list.clone();
-        let tobs = self.state.lock().unwrap().tobs.clone();
-        let (tob_id, (bid, tob_txns)) = tobs.iter().max_by_key(|f| f.1.0).unwrap();
-        let mut block = tob_txns.clone();
-        if self.do_verification {
-            // prepare execution (although, tob is already verified so maybe we can optimize this step)
-            todo!();
+        let result = self.build_bid(&get_bid_request)
+            .map(|bid| SignedResponse::new(&self.enclave_key.unwrap(), "get_highest_bid", bid));
+        if result.is_ok() {
+            get_bid_request.mark_served(&self.auth.unwrap());
+        }
+        if let Err(e) = sender.send(result) {
+            glog::error!("unable to send back on channel: {:?}", e);
         }
+    }
+
+    // without any ToB the block only holds the RoB and the bid is its fees
+    fn build_bid(&self, req: &GetBidRequest) -> Result<(SU256, BlockHeader), JsonrpcErrorObj> {
+        let rob = req.into_transactions()?;
+        let (bid, block) = self.build_block(req.block_number as u64, &rob)?;
+        let header = block.header.clone();
+        self.state.lock().unwrap().blocks.insert(header.hash(), block);
+        Ok((bid, header))
+    }
 
-        for txn in rob {
-            if !block.contains(&txn) {
-                if self.do_verification {
-                    todo!();
-                    // skip this txn if the execution fails
-                    // if txn is correct, update bid to take into account this txn
+    fn handle_commit_header_request(&self, signed_header: &SignedHeader, sender: Sender<Result<bool, JsonrpcErrorObj>>) {
+        if let Err(err) = signed_header.validate_sender(&self.auth.unwrap()) {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
+                glog::error!("unable to send back on channel: {:?}", e);
+            }
+            return;
+        }
+
+        let hash = signed_header.header.hash();
+
+        let block = self.state.lock().unwrap().blocks.get(&hash).cloned();
+        match block {
+            Some(block) => self.publish_block(block, signed_header.signed_blinded_block.clone(), sender),
+            None => {
+                if let Err(e) = sender.send(Err(JsonrpcErrorObj::client("Unknown header".into()))) {
+                    glog::error!("unable to send back on channel: {:?}", e);
                 }
-                block.push(txn);
+            },
+        }
+    }
+
+    fn handle_submit_inclusion_list_request(&self, req: GetBidRequest, sender: Sender<Result<BlockHeader, JsonrpcErrorObj>>) {
+        if let Err(err) = req.validate_sender(&self.auth.unwrap()) {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
+                glog::error!("unable to send back on channel: {:?}", e);
             }
+            return;
         }
 
-        let (bid, header) = todo!();
-        if let Err(e) = sender.send(Ok((bid, header))) {
+        let result = self.build_proposer_aide_header(&req);
+        if result.is_ok() {
+            req.mark_served(&self.auth.unwrap());
+        }
+        if let Err(e) = sender.send(result) {
             glog::error!("unable to send back on channel: {:?}", e);
         }
     }
 
-    fn handle_commit_header_request(&self, signed_header: &SignedHeader, sender: Sender<Result<bool, JsonrpcErrorObj>>) {
-        if !signed_header.validate_sender() {
-            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client("Bad sender".into()))) {
+    fn build_proposer_aide_header(&self, req: &GetBidRequest) -> Result<BlockHeader, JsonrpcErrorObj> {
+        let block_number = req.block_number as u64;
+        let inclusion_list = req.into_transactions()?;
+        self.records.write().unwrap().proposer_aide.open(block_number, inclusion_list.clone())?;
+        let (_, block) = self.build_block(block_number, &inclusion_list)?;
+        let header = self.records.write().unwrap().proposer_aide.deliver(block_number, block)?;
+        Ok(header)
+    }
+
+    fn handle_proposer_commit_header_request(&self, signed_header: &SignedHeader, sender: Sender<Result<bool, JsonrpcErrorObj>>) {
+        if let Err(err) = signed_header.validate_sender(&self.auth.unwrap()) {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
                 glog::error!("unable to send back on channel: {:?}", e);
             }
             return;
         }
 
-        let hash = signed_header.header.hash();
+        // finish_publish runs once the publisher thread reports back
+        let block = self.records.write().unwrap().proposer_aide.begin_publish(&signed_header.header);
+        let result = match block {
+            Ok(Some(block)) => return self.publish_block(block, signed_header.signed_blinded_block.clone(), sender),
+            Ok(None) => Ok(true), // already on the network
+            Err(err) => Err(err.into()),
+        };
+        if let Err(e) = sender.send(result) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
 
-        let state = self.state.lock().unwrap();
-        if let Some(block) = self.state.lock().unwrap().blocks.get(&hash) {
-            let published = todo!();
-            if let Err(e) = sender.send(Ok(published)) {
+    fn handle_submit_block_request(&self, req: SubmitBlockRequest, sender: Sender<Result<BlockAttestation, JsonrpcErrorObj>>) {
+        let result = self.verify_and_accept_block(req);
+        if let Err(e) = sender.send(result) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    fn verify_and_accept_block(&self, req: SubmitBlockRequest) -> Result<BlockAttestation, JsonrpcErrorObj> {
+        let block_number = req.block.header.number.as_u64();
+        let pubkey = self.auth.unwrap().proposer_pubkey(block_number)?;
+        let fee_recipient = self.state.lock().unwrap().payload_attributes.fee_recipient(block_number, &pubkey)
+            .ok_or_else(|| JsonrpcErrorObj::client(format!("Bad request: no fee recipient known for block {}", block_number)))?;
+        let beacon_root = self.state.lock().unwrap().payload_attributes.parent_beacon_block_root(block_number);
+        req.verify_blobs(Arc::as_ref(&self.kzg.unwrap()).as_ref())?;
+        let attestation = verify_candidate(&self.el(), self.chain_id(), fee_recipient, beacon_root, &req)?;
+        let mut state = self.state.lock().unwrap();
+        self.records.write().unwrap().builder_aide.accept(req.block, attestation.clone())?;
+        if !req.blob_sidecars.is_empty() {
+            state.blob_sidecars.insert((block_number, attestation.block_hash), req.blob_sidecars);
+        }
+        Ok(attestation)
+    }
+
+    fn handle_get_header_request(&self, req: GetBidRequest, sender: Sender<Result<(SU256, BlockHeader), JsonrpcErrorObj>>) {
+        if let Err(err) = req.validate_sender(&self.auth.unwrap()) {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
                 glog::error!("unable to send back on channel: {:?}", e);
             }
-        } else {
-            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client("Unknown header".into()))) {
+            return;
+        }
+
+        let result = self.records.write().unwrap().builder_aide.deliver_best(req.block_number as u64).map_err(Into::into);
+        if result.is_ok() {
+            req.mark_served(&self.auth.unwrap());
+        }
+        if let Err(e) = sender.send(result) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    fn handle_builder_commit_header_request(&self, signed_header: &SignedHeader, sender: Sender<Result<bool, JsonrpcErrorObj>>) {
+        if let Err(err) = signed_header.validate_sender(&self.auth.unwrap()) {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
                 glog::error!("unable to send back on channel: {:?}", e);
             }
+            return;
+        }
+
+        let block = self.records.write().unwrap().builder_aide.delivered_block(&signed_header.header);
+        match block {
+            Ok(block) => self.publish_block(block, signed_header.signed_blinded_block.clone(), sender),
+            Err(err) => {
+                if let Err(e) = sender.send(Err(err.into())) {
+                    glog::error!("unable to send back on channel: {:?}", e);
+                }
+            },
+        }
+    }
+
+    fn handle_send_raw_transaction_request(&self, txn: &str, sender: Sender<Result<SH256, JsonrpcErrorObj>>) {
+        let result = decode_transaction(txn)
+            .map_err(|err| JsonrpcErrorObj::client(format!("Bad request: {}", err)))
+            .and_then(|txn| self.add_mempool_order(MempoolOrder::Transaction(txn)));
+        if let Err(e) = sender.send(result) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    fn handle_send_bundle_request(&self, req: SendBundleRequest, sender: Sender<Result<SH256, JsonrpcErrorObj>>) {
+        let result = req.into_order()
+            .map_err(|err| JsonrpcErrorObj::client(format!("Bad request: {}", err)))
+            .and_then(|order| self.add_mempool_order(order));
+        if let Err(e) = sender.send(result) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    // the order is checked against the state of the chain head before it goes into the mempool
+    fn add_mempool_order(&self, order: MempoolOrder) -> Result<SH256, JsonrpcErrorObj> {
+        let el = self.el();
+        let head = el.head()
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to fetch chain head: {:?}", err)))
+            .and_then(|head| self.parent_header(&el, head + 1))?;
+        let sender = order.verify(self.chain_id(), &mut parent_state(&el, &head))?;
+        self.state.lock().unwrap().full_builder.mempool.insert(&order, sender)?;
+        Ok(order.id())
+    }
+
+    // rebuild the block of the next slot out of the private mempool
+    fn run_full_builder(&self) {
+        let block_number = match self.el().head() {
+            Ok(head) => head + 1,
+            Err(err) => {
+                glog::error!("unable to fetch chain head: {:?}", err);
+                return;
+            }
+        };
+        let orders = {
+            let state = self.state.lock().unwrap();
+            if state.full_builder.is_sealed(block_number) {
+                return;
+            }
+            match state.full_builder.mempool.orders_for(block_number) {
+                Ok(orders) => orders,
+                Err(err) => {
+                    glog::error!("unable to read mempool: {}", err);
+                    return;
+                }
+            }
+        };
+        match self.build_full_block(block_number, orders) {
+            Ok((value, block, failed)) => {
+                let mut state = self.state.lock().unwrap();
+                state.full_builder.mempool.evict(&failed);
+                state.full_builder.update_best(block_number, value, block);
+            },
+            Err(err) => glog::warn!("unable to build block {}: {}", block_number, err),
+        }
+    }
+
+    // build the most valuable block for `block_number` out of the mempool orders
+    fn build_full_block(&self, block_number: u64, orders: Vec<MempoolOrder>) -> Result<(SU256, Block, Vec<SH256>), MevBooTeeError> {
+        if block_number == 0 {
+            return Err(MevBooTeeError::FullTeeBuilder("cannot build the genesis block".into()));
+        }
+        let el = self.el();
+        let parent = self.parent_header(&el, block_number)?;
+        let info = self.consensus_info(&parent)?;
+        let withdrawals = self.withdrawals(&parent)?;
+        let beacon_root = self.parent_beacon_block_root(&parent)?;
+        match self.cfg.unwrap().strategy {
+            StrategyKind::Greedy => build_full_block_with::<GreedyBlockBuildingStrategy>(el, parent, info, withdrawals, beacon_root, orders),
+            StrategyKind::Merging => build_full_block_with::<MergingBlockBuildingStrategy>(el, parent, info, withdrawals, beacon_root, orders),
+        }
+    }
+
+    fn handle_full_builder_get_header_request(&self, req: GetBidRequest, sender: Sender<Result<(SU256, BlockHeader), JsonrpcErrorObj>>) {
+        if let Err(err) = req.validate_sender(&self.auth.unwrap()) {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
+                glog::error!("unable to send back on channel: {:?}", e);
+            }
+            return;
+        }
+
+        let result = self.state.lock().unwrap().full_builder.deliver(req.block_number as u64).map_err(Into::into);
+        if result.is_ok() {
+            req.mark_served(&self.auth.unwrap());
+        }
+        if let Err(e) = sender.send(result) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    fn handle_full_builder_commit_header_request(&self, signed_header: &SignedHeader, sender: Sender<Result<bool, JsonrpcErrorObj>>) {
+        if let Err(err) = signed_header.validate_sender(&self.auth.unwrap()) {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
+                glog::error!("unable to send back on channel: {:?}", e);
+            }
+            return;
+        }
+
+        let block = self.state.lock().unwrap().full_builder.delivered_block(&signed_header.header);
+        match block {
+            Ok(block) => self.publish_block(block, signed_header.signed_blinded_block.clone(), sender),
+            Err(err) => {
+                if let Err(e) = sender.send(Err(err.into())) {
+                    glog::error!("unable to send back on channel: {:?}", e);
+                }
+            },
+        }
+    }
+
+    // build a block for `block_number` out of its ToBs that contains every transaction of `inclusion_list`
+    // returns the bid of the block along with it
+    fn build_block(&self, block_number: u64, inclusion_list: &[Transaction]) -> Result<(SU256, Block), JsonrpcErrorObj> {
+        if block_number == 0 {
+            return Err(JsonrpcErrorObj::client("Bad request: cannot build the genesis block".into()));
+        }
+        // the proposer txns come without sidecars
+        if let Some(index) = inclusion_list.iter().position(is_blob_transaction) {
+            return Err(JsonrpcErrorObj::client(format!("Bad request: txns[{}]: blob transactions are only accepted in bundles", index)));
+        }
+        self.check_open_slot(block_number)?;
+        // nothing is built out of the bids before the auction closed
+        let deadline = self.bid_deadline(block_number)?;
+        if unix_timestamp_millis() <= deadline {
+            return Err(MevBooTeeError::Slot(format!("bids for block {} are sealed until {}", block_number, deadline)).into());
+        }
+        let execution = self.execution.unwrap();
+        let parent = execution.header(block_number - 1)?;
+        let info = self.consensus_info(&parent)?;
+        let withdrawals = self.withdrawals(&parent)?;
+        let beacon_root = self.parent_beacon_block_root(&parent)?;
+        let mut bundles = self.state.lock().unwrap().tobs.bundles(block_number);
+        let cfg = self.cfg.unwrap();
+        // the block building enforces what the winner pays
+        cfg.auction.payment.apply(&mut bundles);
+        let strategy = cfg.strategy;
+        let (bid, block, blob_sidecars, payments) = execution.build_block(strategy, parent, info, withdrawals, beacon_root, bundles, inclusion_list)?;
+        // the auction outcome follows the block built last
+        self.records.write().unwrap().auctions.record_block(block_number, payments);
+        if !blob_sidecars.is_empty() {
+            self.state.lock().unwrap().blob_sidecars.insert((block_number, block.header.hash()), blob_sidecars);
+        }
+        Ok((bid, block))
+    }
+
+    fn parent_header(&self, el: &ElClient, block_number: u64) -> Result<BlockHeader, MevBooTeeError> {
+        el.get_block_header((block_number - 1).into())
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to fetch block {}: {:?}", block_number - 1, err)))
+    }
+
+    // the consensus fields of the block built on `parent`, as requested by its proposer
+    fn consensus_info(&self, parent: &BlockHeader) -> Result<ConsensusBlockInfo, MevBooTeeError> {
+        let pubkey = self.auth.unwrap().proposer_pubkey(parent.number.as_u64() + 1)?;
+        self.state.lock().unwrap().payload_attributes.consensus_info(parent, &pubkey, EXTRA_DATA)
+    }
+
+    // the parent beacon block root of the block built on `parent`, from its payload attributes
+    // none before Cancun, when the parent has no blob gas fields
+    fn parent_beacon_block_root(&self, parent: &BlockHeader) -> Result<Option<SH256>, MevBooTeeError> {
+        let block_number = parent.number.as_u64() + 1;
+        match self.state.lock().unwrap().payload_attributes.parent_beacon_block_root(block_number) {
+            Some(root) => Ok(Some(root)),
+            None if parent.excess_blob_gas.is_none() => Ok(None),
+            None => Err(MevBooTeeError::Proposer(format!("no parent beacon block root for block {}", block_number))),
+        }
+    }
+
+    // the withdrawals of the block built on `parent`, from its payload attributes or else the beacon node
+    // none before Shanghai, when the parent has no withdrawals root
+    fn withdrawals(&self, parent: &BlockHeader) -> Result<Option<Vec<Withdrawal>>, MevBooTeeError> {
+        if parent.withdrawals_root.is_none() {
+            return Ok(None);
+        }
+        let block_number = parent.number.as_u64() + 1;
+        let timestamp = {
+            let state = self.state.lock().unwrap();
+            if let Some(withdrawals) = state.payload_attributes.withdrawals(block_number) {
+                return Ok(Some(withdrawals));
+            }
+            state.payload_attributes.get(block_number).map(|attributes| attributes.timestamp)
+        };
+        let beacon = match &self.cfg.unwrap().beacon_endpoint {
+            Some(endpoint) => BeaconClient::new(endpoint),
+            None => return Err(MevBooTeeError::Proposer(format!("no withdrawals for block {}", block_number))),
+        };
+        let slot = proposal_slot(&beacon, &self.el(), genesis_time(&beacon)?, block_number, timestamp)?;
+        fetch_expected_withdrawals(&beacon, slot).map(Some)
+    }
+
+    fn handle_submit_payload_attributes_request(&self, req: SignedPayloadAttributes, sender: Sender<Result<bool, JsonrpcErrorObj>>) {
+        if let Err(err) = req.validate_sender(&self.auth.unwrap()) {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
+                glog::error!("unable to send back on channel: {:?}", e);
+            }
+            return;
+        }
+
+        req.mark_served(&self.auth.unwrap());
+        self.state.lock().unwrap().payload_attributes.insert(req.attributes);
+        if let Err(e) = sender.send(Ok(true)) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    fn handle_register_validator_request(&self, req: SignedValidatorRegistration, sender: Sender<Result<bool, JsonrpcErrorObj>>) {
+        if let Err(err) = req.verify() {
+            if let Err(e) = sender.send(Err(JsonrpcErrorObj::client(format!("Bad sender: {}", err)))) {
+                glog::error!("unable to send back on channel: {:?}", e);
+            }
+            return;
+        }
+
+        let registered = self.state.lock().unwrap().payload_attributes.register(req.message);
+        if let Err(e) = sender.send(Ok(registered)) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    // hand a block whose header has been signed by the proposer to the publisher thread
+    // `reply` gets the outcome from handle_published
+    fn publish_block(&self, block: Block, signed_blinded_block: Option<SignedBlindedBeaconBlock>, reply: Sender<Result<bool, JsonrpcErrorObj>>) {
+        let key = (block.header.number.as_u64(), block.header.hash());
+        let blob_sidecars = self.state.lock().unwrap().blob_sidecars.get(&key).cloned().unwrap_or_default();
+        let job = PublishJob { block: SignedBlock::new(block, blob_sidecars, signed_blinded_block), reply };
+        if let Err(err) = self.publish_jobs.lock().unwrap().send(job) {
+            self.handle_published(err.0, Err(MevBooTeeError::Publish("publisher stopped".into())));
+        }
+    }
+
+    // record the outcome of a publish and answer the proposer
+    fn handle_published(&self, job: PublishJob, result: Result<String, MevBooTeeError>) {
+        let block = &job.block.block;
+        match self.mode() {
+            MevBooTeeMode::ProposerAide => self.records.write().unwrap().proposer_aide.finish_publish(&block.header, result.is_ok()),
+            MevBooTeeMode::FullTeeBuilder if result.is_ok() => {
+                let mut state = self.state.lock().unwrap();
+                state.full_builder.mempool.remove_included(block);
+                state.full_builder.prune(block.header.number.as_u64() + 1);
+            },
+            _ => {},
+        }
+        if let Err(e) = job.reply.send(result.map(|_| true).map_err(Into::into)) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+}
+
+// forward the payload attributes announced by the beacon node to the main loop
+fn listen_payload_attributes(alive: Alive, beacon: BeaconClient, queue: Arc<RequestQueue>) {
+    while alive.is_alive() {
+        let events = match beacon.events("payload_attributes") {
+            Ok(events) => events,
+            Err(err) => {
+                glog::warn!("payload attributes stream interrupted: {}", err);
+                std::thread::sleep(HEAD_POLL_INTERVAL);
+                continue;
+            }
+        };
+        for event in events.iter().filter(|event| event.event == "payload_attributes") {
+            match parse_payload_attributes_event(&event.data) {
+                Ok(attributes) => {
+                    if queue.send(JsonRpcServerMsg::BeaconPayloadAttributes(attributes)).is_err() {
+                        return;
+                    }
+                },
+                Err(err) => glog::warn!("{}", err),
+            }
+        }
+    }
+}
+
+// serves the read-only requests, see JsonRpcServerMsg::is_read_only
+// it never takes the state, a read only waits for the short writes to the records
+struct StateReader {
+    records: Arc<RwLock<SlotRecords>>,
+    attestation: Arc<AttestationReport>,
+    metrics: Arc<DispatcherMetrics>,
+    payment: Payment, // what the winner of an auction pays
+}
+
+impl StateReader {
+    fn run(&self, reads: &Mutex<Receiver<JsonRpcServerMsg>>) {
+        loop {
+            let msg = match reads.lock().unwrap().recv() {
+                Ok(msg) => msg,
+                Err(_) => return,
+            };
+            self.metrics.reads.pop();
+            self.handle(msg);
+        }
+    }
+
+    fn handle(&self, msg: JsonRpcServerMsg) {
+        match msg {
+            JsonRpcServerMsg::GetAuctionOutcome(block_number, sender) => self.handle_get_auction_outcome_request(block_number, sender),
+            JsonRpcServerMsg::GetAuctionTranscript(req, sender) => self.handle_get_auction_transcript_request(req, sender),
+            JsonRpcServerMsg::AttestationReport(sender) => self.handle_attestation_report_request(sender),
+            JsonRpcServerMsg::GetSlotStatus(block_number, sender) => self.handle_get_slot_status_request(block_number, sender),
+            JsonRpcServerMsg::GetBestBid(block_number, sender) => self.handle_get_best_bid_request(block_number, sender),
+            _ => glog::error!("not a read-only request"),
+        }
+    }
+
+    fn handle_get_auction_outcome_request(&self, block_number: u64, sender: Sender<Result<Option<AuctionOutcome>, JsonrpcErrorObj>>) {
+        let result = self.records.read().unwrap().auctions.outcome(block_number, unix_timestamp_millis(), self.payment).map_err(Into::into);
+        if let Err(e) = sender.send(result) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    fn handle_get_auction_transcript_request(&self, req: GetTranscriptRequest, sender: Sender<Option<TranscriptResponse>>) {
+        let transcript = self.records.read().unwrap().transcripts.get(&req);
+        if let Err(e) = sender.send(transcript) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    fn handle_attestation_report_request(&self, sender: Sender<AttestationReport>) {
+        if let Err(e) = sender.send(self.attestation.as_ref().clone()) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    fn handle_get_slot_status_request(&self, block_number: u64, sender: Sender<Option<ProposerAideStatus>>) {
+        let status = self.records.read().unwrap().proposer_aide.status(block_number);
+        if let Err(e) = sender.send(status) {
+            glog::error!("unable to send back on channel: {:?}", e);
+        }
+    }
+
+    fn handle_get_best_bid_request(&self, block_number: u64, sender: Sender<Option<BlockAttestation>>) {
+        let best = self.records.read().unwrap().builder_aide.best_bid(block_number);
+        if let Err(e) = sender.send(best) {
+            glog::error!("unable to send back on channel: {:?}", e);
         }
     }
 }
 
 impl apps::App for MevBooTee {
-    fn run(&self, args: AppEnv) -> Result<(), String> {
+    fn run(&self, env: AppEnv) -> Result<(), String> {
+        let cfg = MevBooTeeConfig::from_args(&env.args).map_err(|err| err.to_string())?;
+        self.init(cfg).map_err(|err| err.to_string())?;
         self.start();
         Ok(())
     }
@@ -204,12 +1068,202 @@ impl apps::App for MevBooTee {
 }
 
 struct State {
-    tobs: BTreeMap<String, (u32, Vec<String>)>,
-    blocks: BTreeMap<SH256, Block>
+    tobs: BundlePool,
+    blocks: BTreeMap<SH256, Block>,
+    blob_sidecars: BTreeMap<(u64, SH256), Vec<BlobSidecar>>, // by block number and hash, for the blocks with blob transactions
+    heads: HeadTracker,
+    full_builder: FullTeeBuilder,
+    payload_attributes: PayloadAttributesStore,
 }
 
 impl Default for State {
     fn default() -> Self {
-        Self { tobs: BTreeMap::new(), blocks: BTreeMap::new() }
+        Self { tobs: BundlePool::default(), blocks: BTreeMap::new(), blob_sidecars: BTreeMap::new(), heads: HeadTracker::default(), full_builder: FullTeeBuilder::new(), payload_attributes: PayloadAttributesStore::default() }
+    }
+}
+
+// what the reader threads serve, written in short steps by the dispatcher
+#[derive(Default)]
+struct SlotRecords {
+    auctions: Auctions,
+    transcripts: Transcripts,
+    proposer_aide: ProposerAide, // the slot status
+    builder_aide: BuilderAide, // the best bid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blst::min_pk::SecretKey;
+
+    use crate::proposer::BLS_DST;
+
+    const BLOCK: u64 = 10;
+
+    struct Duty(Vec<u8>);
+
+    impl ProposerDuties for Duty {
+        fn proposer_pubkey(&self, _block_number: u64, _timestamp: Option<u64>) -> Result<Vec<u8>, MevBooTeeError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn txn(n: u8) -> Transaction {
+        let mut txn = Transaction::default();
+        txn.hash = SH256::from([n; 32]);
+        txn
+    }
+
+    fn parent() -> BlockHeader {
+        let mut parent = BlockHeader::default();
+        parent.number = (BLOCK - 1).into();
+        parent
+    }
+
+    // stands in for the execution client: the blocks hold the ToB followed by the inclusion list
+    struct MockExecutionClient {
+        tob: Vec<Transaction>,
+    }
+
+    impl ExecutionLayer for MockExecutionClient {
+        fn header(&self, block_number: u64) -> Result<BlockHeader, MevBooTeeError> {
+            match block_number {
+                n if n == BLOCK - 1 => Ok(parent()),
+                n => Err(MevBooTeeError::Execution(format!("unknown block {}", n))),
+            }
+        }
+
+        fn build_block(
+            &self, _strategy: StrategyKind, parent: BlockHeader, _info: ConsensusBlockInfo, _withdrawals: Option<Vec<Withdrawal>>,
+            _parent_beacon_block_root: Option<SH256>, _bundles: Vec<(String, WrappedBundle)>, inclusion_list: &[Transaction],
+        ) -> Result<(SU256, Block, Vec<BlobSidecar>, Vec<(String, SU256)>), MevBooTeeError> {
+            let mut block = Block::default();
+            block.header.number = (parent.number.as_u64() + 1).into();
+            block.header.parent_hash = parent.hash();
+            block.transactions = self.tob.iter().chain(inclusion_list).cloned().collect();
+            Ok((SU256::default(), block, Vec::new(), Vec::new()))
+        }
+    }
+
+    // a proposer aide for the proposer of BLOCK, the test plays the publisher thread
+    fn proposer_aide(proposer: &SecretKey) -> (MevBooTee, Receiver<PublishJob>) {
+        let app = MevBooTee::default();
+        let mut cfg = MevBooTeeConfig::default();
+        cfg.mode = MevBooTeeMode::ProposerAide;
+        app.cfg.set(cfg);
+        app.auth.set(ProposerAuthenticator::new(Box::new(Duty(proposer.sk_to_pk().to_bytes().to_vec()))));
+        app.execution.set(Box::new(MockExecutionClient { tob: vec![txn(1), txn(2)] }));
+        app.state.lock().unwrap().payload_attributes.insert(PayloadAttributes {
+            block_number: BLOCK,
+            parent_hash: parent().hash(),
+            timestamp: 1, // the bids are sealed
+            prev_randao: SH256::default(),
+            suggested_fee_recipient: Default::default(),
+            withdrawals: None,
+            parent_beacon_block_root: None,
+        });
+        let jobs = app.publish_receiver.lock().unwrap().take().unwrap();
+        (app, jobs)
+    }
+
+    fn submit_inclusion_list(app: &MevBooTee, proposer: &SecretKey) -> Result<BlockHeader, JsonrpcErrorObj> {
+        let mut req = GetBidRequest { txn_list: Vec::new(), block_number: BLOCK as u32, signature: Vec::new() };
+        req.signature = proposer.sign(req.signing_root().as_bytes(), BLS_DST, &[]).to_bytes().to_vec();
+        let (sender, receiver) = channel();
+        app.handle(JsonRpcServerMsg::SubmitInclusionList(req, sender));
+        receiver.recv().unwrap()
+    }
+
+    fn commit_header(app: &MevBooTee, proposer: &SecretKey, header: &BlockHeader) -> Receiver<Result<bool, JsonrpcErrorObj>> {
+        let mut signed_header = SignedHeader { header: header.clone(), signature: Vec::new(), signed_blinded_block: None };
+        signed_header.signature = proposer.sign(signed_header.signing_root().as_bytes(), BLS_DST, &[]).to_bytes().to_vec();
+        let (sender, receiver) = channel();
+        app.handle(JsonRpcServerMsg::CommitHeader(signed_header, sender));
+        receiver
+    }
+
+    fn status(app: &MevBooTee) -> Option<ProposerAideStatus> {
+        app.records.read().unwrap().proposer_aide.status(BLOCK)
+    }
+
+    #[test]
+    fn test_proposer_aide_sequence() {
+        let proposer = SecretKey::key_gen(&[1; 32], &[]).unwrap();
+        let (app, jobs) = proposer_aide(&proposer);
+
+        let header = submit_inclusion_list(&app, &proposer).unwrap();
+        assert_eq!(header.number.as_u64(), BLOCK);
+        assert_eq!(status(&app), Some(ProposerAideStatus::HeaderDelivered(header.hash())));
+        assert!(jobs.try_recv().is_err());
+
+        // the block goes to the publisher thread, the proposer gets its answer once it is out
+        let reply = commit_header(&app, &proposer, &header);
+        let job = jobs.try_recv().unwrap();
+        assert_eq!(job.block.block.header.hash(), header.hash());
+        assert_eq!(job.block.block.transactions.len(), 2);
+        assert_eq!(status(&app), Some(ProposerAideStatus::Publishing(header.hash())));
+        assert!(reply.try_recv().is_err());
+        app.handle(JsonRpcServerMsg::Published(job, Ok("published".into())));
+        assert!(reply.recv().unwrap().unwrap());
+        assert_eq!(status(&app), Some(ProposerAideStatus::Published(header.hash())));
+
+        // committing twice does not broadcast twice
+        assert!(commit_header(&app, &proposer, &header).recv().unwrap().unwrap());
+        assert!(jobs.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_proposer_aide_failed_publish() {
+        let proposer = SecretKey::key_gen(&[1; 32], &[]).unwrap();
+        let (app, jobs) = proposer_aide(&proposer);
+
+        let header = submit_inclusion_list(&app, &proposer).unwrap();
+        let reply = commit_header(&app, &proposer, &header);
+        app.handle(JsonRpcServerMsg::Published(jobs.try_recv().unwrap(), Err(MevBooTeeError::Publish("rejected".into()))));
+        assert!(reply.recv().unwrap().is_err());
+        assert_eq!(status(&app), Some(ProposerAideStatus::HeaderDelivered(header.hash())));
+
+        // the proposer may commit again
+        let reply = commit_header(&app, &proposer, &header);
+        app.handle(JsonRpcServerMsg::Published(jobs.try_recv().unwrap(), Ok("published".into())));
+        assert!(reply.recv().unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_proposer_aide_rejections() {
+        let proposer = SecretKey::key_gen(&[1; 32], &[]).unwrap();
+        let other = SecretKey::key_gen(&[2; 32], &[]).unwrap();
+        let (app, jobs) = proposer_aide(&proposer);
+
+        // only the proposer of the block gets a header
+        assert!(submit_inclusion_list(&app, &other).is_err());
+        assert_eq!(status(&app), None);
+
+        let mut header = submit_inclusion_list(&app, &proposer).unwrap();
+        // the same signed request is not served twice
+        assert!(submit_inclusion_list(&app, &proposer).is_err());
+        assert!(commit_header(&app, &other, &header).recv().unwrap().is_err());
+
+        // a header the proposer aide did not deliver
+        header.gas_used = 1.into();
+        assert!(commit_header(&app, &proposer, &header).recv().unwrap().is_err());
+        assert!(jobs.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_read_while_state_held() {
+        let proposer = SecretKey::key_gen(&[1; 32], &[]).unwrap();
+        let (app, _jobs) = proposer_aide(&proposer);
+        app.attestation.set(AttestationReport { quote: Default::default(), signing_key: Default::default(), encryption_key: Default::default() });
+        let header = submit_inclusion_list(&app, &proposer).unwrap();
+
+        // the dispatcher holds the state, as when building a block
+        let _state = app.state.lock().unwrap();
+        let reader = app.reader();
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || reader.handle(JsonRpcServerMsg::GetSlotStatus(BLOCK, sender)));
+        let status = receiver.recv_timeout(Duration::from_secs(5)).expect("the read waited for the state");
+        assert_eq!(status, Some(ProposerAideStatus::HeaderDelivered(header.hash())));
     }
 }
@@ -0,0 +1,155 @@
+use std::prelude::v1::*;
+
+use eth_types::{HexBytes, SH256};
+use serde::{Deserialize, Serialize};
+
+use crate::{EnclaveKey, EncryptionKey, MevBooTeeError};
+
+// layout of an SGX quote: a 48 bytes header followed by the 384 bytes report body
+const QUOTE_HEADER_LEN: usize = 48;
+const QUOTE_MR_ENCLAVE_OFFSET: usize = QUOTE_HEADER_LEN + 64;
+const QUOTE_REPORT_DATA_OFFSET: usize = QUOTE_HEADER_LEN + 320;
+const QUOTE_MIN_LEN: usize = QUOTE_HEADER_LEN + 384;
+// user data of the header of the quotes made up by `MockQuoteProvider`
+const MOCK_QUOTE_TAG: &[u8] = b"mev-bootee/mock";
+
+// produces a quote of the enclave over the report data
+pub trait QuoteProvider: Send + Sync {
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, MevBooTeeError>;
+}
+
+// a DCAP quote through the quoting enclave of the host
+#[cfg(feature = "sgx")]
+pub struct DcapQuoteProvider;
+
+#[cfg(feature = "sgx")]
+impl QuoteProvider for DcapQuoteProvider {
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, MevBooTeeError> {
+        sgxlib_ra::dcap_generate_quote(*report_data)
+            .map(|quote| quote.to_bytes())
+            .map_err(|err| MevBooTeeError::Attestation(format!("unable to generate quote: {:?}", err)))
+    }
+}
+
+// a quote with the layout of an SGX one but signed by nobody, for running without SGX hardware
+#[derive(Default)]
+pub struct MockQuoteProvider {
+    pub mr_enclave: SH256,
+}
+
+impl QuoteProvider for MockQuoteProvider {
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, MevBooTeeError> {
+        let mut quote = vec![0_u8; QUOTE_MIN_LEN];
+        quote[..2].copy_from_slice(&3_u16.to_le_bytes()); // version
+        quote[2..4].copy_from_slice(&2_u16.to_le_bytes()); // ECDSA P-256 attestation key
+        quote[28..28 + MOCK_QUOTE_TAG.len()].copy_from_slice(MOCK_QUOTE_TAG);
+        quote[QUOTE_MR_ENCLAVE_OFFSET..QUOTE_MR_ENCLAVE_OFFSET + 32].copy_from_slice(self.mr_enclave.as_bytes());
+        quote[QUOTE_REPORT_DATA_OFFSET..QUOTE_REPORT_DATA_OFFSET + 64].copy_from_slice(report_data);
+        Ok(quote)
+    }
+}
+
+// made up by `MockQuoteProvider`, such a quote proves nothing
+pub fn is_mock_quote(quote: &[u8]) -> bool {
+    quote.len() == QUOTE_MIN_LEN && quote[28..].starts_with(MOCK_QUOTE_TAG)
+}
+
+#[cfg(feature = "sgx")]
+pub fn default_quote_provider() -> Box<dyn QuoteProvider> {
+    Box::new(DcapQuoteProvider)
+}
+
+#[cfg(not(feature = "sgx"))]
+pub fn default_quote_provider() -> Box<dyn QuoteProvider> {
+    glog::warn!("built without SGX support, the attestation report carries a mock quote");
+    Box::new(MockQuoteProvider::default())
+}
+
+// the hash of the signing key followed by the hash of the encryption key
+pub fn report_data(signing_key: &[u8], encryption_key: &[u8]) -> [u8; 64] {
+    let mut data = [0_u8; 64];
+    data[..32].copy_from_slice(&crypto::keccak_hash(signing_key));
+    data[32..].copy_from_slice(&crypto::keccak_hash(encryption_key));
+    data
+}
+
+pub fn quote_report_data(quote: &[u8]) -> Result<[u8; 64], MevBooTeeError> {
+    check_quote_len(quote)?;
+    let mut data = [0_u8; 64];
+    data.copy_from_slice(&quote[QUOTE_REPORT_DATA_OFFSET..QUOTE_REPORT_DATA_OFFSET + 64]);
+    Ok(data)
+}
+
+pub fn quote_mr_enclave(quote: &[u8]) -> Result<SH256, MevBooTeeError> {
+    check_quote_len(quote)?;
+    let mut mr_enclave = [0_u8; 32];
+    mr_enclave.copy_from_slice(&quote[QUOTE_MR_ENCLAVE_OFFSET..QUOTE_MR_ENCLAVE_OFFSET + 32]);
+    Ok(mr_enclave.into())
+}
+
+fn check_quote_len(quote: &[u8]) -> Result<(), MevBooTeeError> {
+    match quote.len() >= QUOTE_MIN_LEN {
+        true => Ok(()),
+        false => Err(MevBooTeeError::Attestation(format!("quote too short: {} bytes", quote.len()))),
+    }
+}
+
+// what `attestation_report` answers: the quote of the enclave binding both of its keys
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AttestationReport {
+    pub quote: HexBytes,
+    pub signing_key: HexBytes,    // checks the signatures of the enclave responses
+    pub encryption_key: HexBytes, // to derive a secret shared with the enclave
+}
+
+impl AttestationReport {
+    pub fn new(provider: &dyn QuoteProvider, signing_key: &EnclaveKey, encryption_key: &EncryptionKey) -> Result<Self, MevBooTeeError> {
+        let data = report_data(signing_key.public_key(), encryption_key.public_key());
+        Ok(Self {
+            quote: provider.quote(&data)?.into(),
+            signing_key: signing_key.public_key().clone(),
+            encryption_key: encryption_key.public_key().clone(),
+        })
+    }
+
+    pub fn report_data(&self) -> [u8; 64] {
+        report_data(&self.signing_key, &self.encryption_key)
+    }
+
+    // the quote claims the `mr_enclave` build and its report data binds both keys
+    // this says nothing of whether the quote is genuine: the crate does not check the quote signature, the QE report
+    // nor the PCK certificate chain, a client verifies the quote with a DCAP library before relying on this
+    pub fn check_binding(&self, mr_enclave: &SH256) -> Result<(), MevBooTeeError> {
+        let quoted = quote_mr_enclave(&self.quote)?;
+        if quoted != *mr_enclave {
+            return Err(MevBooTeeError::Attestation(format!("mr_enclave mismatch: expected {:?}, quoted {:?}", mr_enclave, quoted)));
+        }
+        if quote_report_data(&self.quote)? != self.report_data() {
+            return Err(MevBooTeeError::Attestation("the report data does not match the enclave keys".into()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attestation_report() {
+        let provider = MockQuoteProvider { mr_enclave: SH256::from([7; 32]) };
+        let (signing_key, encryption_key) = (EnclaveKey::from_ikm(&[1; 32]), EncryptionKey::from_ikm(&[2; 32]));
+        let report = AttestationReport::new(&provider, &signing_key, &encryption_key).unwrap();
+        assert!(report.check_binding(&SH256::from([7; 32])).is_ok());
+        assert!(report.check_binding(&SH256::default()).is_err());
+        assert!(is_mock_quote(&report.quote));
+
+        let mut swapped = report.clone();
+        swapped.signing_key = EnclaveKey::from_ikm(&[3; 32]).public_key().clone();
+        assert!(swapped.check_binding(&SH256::from([7; 32])).is_err());
+        let mut truncated = report;
+        truncated.quote = truncated.quote[..QUOTE_MIN_LEN - 1].to_vec().into();
+        assert!(truncated.check_binding(&SH256::from([7; 32])).is_err());
+        assert!(!is_mock_quote(&truncated.quote));
+    }
+}
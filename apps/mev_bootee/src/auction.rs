@@ -0,0 +1,346 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeMap;
+
+use eth_types::{HexBytes, SH160, SH256, SU256};
+use serde::{Deserialize, Serialize};
+
+use crate::{verify_enclave_signature, EnclaveKey, MevBooTeeError, SigningData, WrappedBundle};
+
+pub const SECONDS_PER_SLOT: u64 = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Payment {
+    FirstPrice,  // the winner pays its bid
+    SecondPrice, // the winner pays the second highest bid, or its own bid without competition
+}
+
+impl std::str::FromStr for Payment {
+    type Err = MevBooTeeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "first-price" => Ok(Payment::FirstPrice),
+            "second-price" => Ok(Payment::SecondPrice),
+            _ => Err(MevBooTeeError::Config(format!("unknown payment {:?}, expected one of first-price, second-price", s))),
+        }
+    }
+}
+
+impl Payment {
+    // the least a ToB bidding `bid` has to pay the fee recipient, `others` are the other bids of its slot
+    // at second price, only the winner pays less than its bid
+    pub fn price<I: IntoIterator<Item = SU256>>(&self, bid: SU256, others: I) -> SU256 {
+        match self {
+            Payment::FirstPrice => bid,
+            Payment::SecondPrice => others.into_iter().max().map_or(bid, |runner_up| runner_up.min(bid)),
+        }
+    }
+
+    // lower the bid of every bundle to its price, the block building then keeps a bundle only when it pays that much
+    pub fn apply(&self, bundles: &mut [(String, WrappedBundle)]) {
+        let bids: Vec<SU256> = bundles.iter().map(|(_, bundle)| bundle.bid).collect();
+        for (index, (_, bundle)) in bundles.iter_mut().enumerate() {
+            let others = bids.iter().enumerate().filter(|(other, _)| *other != index).map(|(_, bid)| *bid);
+            bundle.bid = self.price(bundle.bid, others);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AuctionConfig {
+    pub deadline_ms: i64, // bid deadline relative to the slot start, negative for before it
+    pub payment: Payment,
+}
+
+impl Default for AuctionConfig {
+    fn default() -> Self {
+        Self {
+            deadline_ms: 0,
+            payment: Payment::FirstPrice,
+        }
+    }
+}
+
+impl AuctionConfig {
+    // unix time in milliseconds after which the slot starting at `slot_start` takes no more bids
+    pub fn deadline(&self, slot_start: u64) -> u64 {
+        (slot_start as i64 * 1000 + self.deadline_ms).max(0) as u64
+    }
+}
+
+// what the enclave hands back for a recorded bid, signed with the enclave key
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BidReceipt {
+    pub tob_id: String,
+    pub block_number: u64,
+    pub bid: SU256,
+    pub submitter: SH160,
+    pub received_at: u64, // unix time in milliseconds, at or before the deadline
+    pub deadline: u64,
+    pub signature: HexBytes,
+}
+
+impl BidReceipt {
+    pub fn new(key: &EnclaveKey, tob_id: String, bundle: &WrappedBundle, received_at: u64, deadline: u64) -> Self {
+        let mut receipt = Self::unsigned(tob_id, bundle.block_number, bundle.bid, bundle.submitter, received_at, deadline);
+        receipt.signature = key.sign(&receipt.signing_root());
+        receipt
+    }
+
+    fn unsigned(tob_id: String, block_number: u64, bid: SU256, submitter: SH160, received_at: u64, deadline: u64) -> Self {
+        Self { tob_id, block_number, bid, submitter, received_at, deadline, signature: HexBytes::default() }
+    }
+
+    pub fn signing_root(&self) -> SH256 {
+        let mut data = b"mev-bootee/bid_receipt".to_vec();
+        data.extend_from_slice(&crypto::keccak_hash(self.tob_id.as_bytes()));
+        data.extend_from_slice(&self.block_number.to_be_bytes());
+        let mut bid = [0_u8; 32];
+        self.bid.to_big_endian(&mut bid);
+        data.extend_from_slice(&bid);
+        data.extend_from_slice(self.submitter.as_bytes());
+        data.extend_from_slice(&self.received_at.to_be_bytes());
+        data.extend_from_slice(&self.deadline.to_be_bytes());
+        crypto::keccak_hash(&data).into()
+    }
+
+    // the receipt was signed by the enclave key `pubkey` and the bid made the deadline
+    pub fn verify(&self, pubkey: &[u8]) -> Result<(), MevBooTeeError> {
+        if self.received_at > self.deadline {
+            return Err(MevBooTeeError::Slot(format!("bid received at {} after the deadline {}", self.received_at, self.deadline)));
+        }
+        verify_enclave_signature(pubkey, &self.signing_root(), &self.signature)
+    }
+}
+
+// what `retract_tob` answers
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Retraction {
+    pub tob_id: String,
+    pub retracted: bool, // false when unknown or past the deadline
+    pub timestamp: u64,  // unix time in milliseconds
+}
+
+impl SigningData for Retraction {
+    fn signing_data(&self) -> Vec<u8> {
+        let mut data = crypto::keccak_hash(self.tob_id.as_bytes()).to_vec();
+        data.push(self.retracted as u8);
+        data.extend_from_slice(&self.timestamp.to_be_bytes());
+        data
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AuctionOutcome {
+    pub block_number: u64,
+    pub tob_id: String, // the winning ToB, the one paying the fee recipient the most in the block built for the slot
+    pub bid: SU256,
+    pub value: SU256, // what the winner pays the fee recipient in that block
+    pub payment: SU256, // the least the winner had to pay the fee recipient for its ToB to stay in the block
+    pub bids: usize,
+}
+
+struct SealedBid {
+    bid: SU256,
+    submitter: SH160,
+    received_at: u64,
+}
+
+struct SlotAuction {
+    deadline: u64,
+    bids: BTreeMap<String, SealedBid>,
+    built: Option<Vec<(String, SU256)>>, // the ToBs of the last block built for the slot in order, with what each pays
+}
+
+// the sealed-bid auction of every slot: bids are taken until the deadline and nothing about them
+// leaves the enclave before it
+#[derive(Default)]
+pub struct Auctions {
+    slots: BTreeMap<u64, SlotAuction>,
+}
+
+impl Auctions {
+    // record a bid received at `now`, returns when it was first recorded and the deadline of its slot
+    pub fn record(&mut self, tob_id: &str, bundle: &WrappedBundle, deadline: u64, now: u64) -> Result<(u64, u64), MevBooTeeError> {
+        let block_number = bundle.block_number;
+        let slot = self.slots.entry(block_number).or_insert_with(|| SlotAuction { deadline, bids: BTreeMap::new(), built: None });
+        if now > slot.deadline {
+            return Err(MevBooTeeError::Slot(format!("bids for block {} closed at {}", block_number, slot.deadline)));
+        }
+        let bid = slot.bids.entry(tob_id.to_owned()).or_insert(SealedBid { bid: bundle.bid, submitter: bundle.submitter, received_at: now });
+        Ok((bid.received_at, slot.deadline))
+    }
+
+    // a bid can only be withdrawn before the deadline
+    pub fn retract(&mut self, block_number: u64, tob_id: &str, now: u64) -> bool {
+        match self.slots.get_mut(&block_number) {
+            Some(slot) if now <= slot.deadline => slot.bids.remove(tob_id).is_some(),
+            _ => false,
+        }
+    }
+
+    // record the ToBs of a block built for `block_number`, in block order with the simulated payment of each
+    pub fn record_block(&mut self, block_number: u64, payments: Vec<(String, SU256)>) {
+        if let Some(slot) = self.slots.get_mut(&block_number) {
+            slot.built = Some(payments);
+        }
+    }
+
+    // the winner of the slot, sealed until the deadline and known once a block has been built out of the bids
+    pub fn outcome(&self, block_number: u64, now: u64, payment: Payment) -> Result<Option<AuctionOutcome>, MevBooTeeError> {
+        let slot = match self.slots.get(&block_number) {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        if now <= slot.deadline {
+            return Err(MevBooTeeError::Slot(format!("bids for block {} are sealed until {}", block_number, slot.deadline)));
+        }
+        let values: Vec<(&String, SU256, u64)> = slot.built.iter().flatten()
+            .filter(|(tob_id, _)| slot.bids.contains_key(tob_id))
+            .enumerate()
+            .map(|(position, (tob_id, value))| (tob_id, *value, position as u64))
+            .collect();
+        let (tob_id, value) = match settle(&values) {
+            Some(winner) => winner,
+            None => return Ok(None),
+        };
+        let bid = slot.bids[tob_id].bid;
+        let others = slot.bids.iter().filter(|(other, _)| *other != tob_id).map(|(_, other)| other.bid);
+        Ok(Some(AuctionOutcome {
+            block_number,
+            tob_id: tob_id.clone(),
+            bid,
+            value,
+            payment: payment.price(bid, others),
+            bids: slot.bids.len(),
+        }))
+    }
+
+    // close the auctions of every block before `block_number`, returns the receipts of their bids
+    pub fn seal(&mut self, block_number: u64) -> Vec<ClosedAuction> {
+        let open = self.slots.split_off(&block_number);
+        let sealed = std::mem::replace(&mut self.slots, open);
+        sealed.into_iter()
+            .map(|(block_number, slot)| ClosedAuction {
+                block_number,
+                deadline: slot.deadline,
+                receipts: slot.bids.into_iter()
+                    .map(|(tob_id, bid)| {
+                        let receipt = BidReceipt::unsigned(tob_id.clone(), block_number, bid.bid, bid.submitter, bid.received_at, slot.deadline);
+                        (tob_id, receipt.signing_root())
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+pub struct ClosedAuction {
+    pub block_number: u64,
+    pub deadline: u64,
+    pub receipts: Vec<(String, SH256)>, // ToB id and signing root of its receipt
+}
+
+// the highest value wins, ties go to the earliest one, returns the winner and its value
+// the values are the simulated payments of the ToBs in the built block, the order they were ranked by
+pub fn settle<'a>(values: &[(&'a String, SU256, u64)]) -> Option<(&'a String, SU256)> {
+    values.iter()
+        .min_by(|a, b| b.1.cmp(&a.1).then(a.2.cmp(&b.2)).then(a.0.cmp(b.0)))
+        .map(|winner| (winner.0, winner.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::block_building::tests::mock_bundle;
+
+    fn bundle(block_number: u64, bid: u64) -> WrappedBundle {
+        WrappedBundle { bid: bid.into(), block_number, ..mock_bundle(Vec::new(), Vec::new()) }
+    }
+
+    #[test]
+    fn test_settle() {
+        let (a, b, c) = ("a".to_owned(), "b".to_owned(), "c".to_owned());
+        let bids = vec![(&a, SU256::from(5), 1), (&b, SU256::from(9), 3), (&c, SU256::from(9), 2)];
+        assert_eq!(settle(&bids), Some((&c, 9.into())));
+        assert_eq!(settle(&bids[..2]), Some((&b, 9.into())));
+        assert_eq!(settle(&bids[..1]), Some((&a, 5.into())));
+        assert_eq!(settle(&[]), None);
+    }
+
+    #[test]
+    fn test_second_price_enforced() {
+        let mut bundles = vec![("a".to_owned(), bundle(10, 5)), ("b".to_owned(), bundle(10, 9)), ("c".to_owned(), bundle(10, 7))];
+        Payment::FirstPrice.apply(&mut bundles);
+        assert_eq!(bundles.iter().map(|(_, bundle)| bundle.bid).collect::<Vec<SU256>>(), vec![5.into(), 9.into(), 7.into()]);
+        // only the winner has to pay less than its bid: the runner-up bid
+        Payment::SecondPrice.apply(&mut bundles);
+        assert_eq!(bundles.iter().map(|(_, bundle)| bundle.bid).collect::<Vec<SU256>>(), vec![5.into(), 7.into(), 7.into()]);
+    }
+
+    #[test]
+    fn test_sealed_until_deadline() {
+        let mut auctions = Auctions::default();
+        assert_eq!(auctions.record("a", &bundle(10, 5), 1000, 100).unwrap(), (100, 1000));
+        assert_eq!(auctions.record("b", &bundle(10, 7), 1000, 200).unwrap(), (200, 1000));
+        // a resubmission keeps its first receipt time, and the deadline is set by the first bid
+        assert_eq!(auctions.record("a", &bundle(10, 5), 2000, 300).unwrap(), (100, 1000));
+        assert!(auctions.outcome(10, 1000, Payment::FirstPrice).is_err());
+
+        assert!(auctions.retract(10, "b", 1000));
+        assert!(auctions.record("b", &bundle(10, 7), 1000, 1001).is_err());
+        assert!(!auctions.retract(10, "a", 1001));
+
+        // nothing to tell before a block is built out of the bids
+        assert_eq!(auctions.outcome(10, 1001, Payment::FirstPrice).unwrap(), None);
+        auctions.record_block(10, vec![("a".to_owned(), 6.into())]);
+        let outcome = auctions.outcome(10, 1001, Payment::SecondPrice).unwrap().unwrap();
+        assert_eq!((outcome.tob_id.as_str(), outcome.bid, outcome.value, outcome.payment, outcome.bids), ("a", 5.into(), 6.into(), 5.into(), 1));
+        assert_eq!(auctions.outcome(11, 1001, Payment::FirstPrice).unwrap(), None);
+
+        // the receipt of every remaining bid ends up in the transcript
+        let key = EnclaveKey::from_ikm(&[1; 32]);
+        let receipt = BidReceipt::new(&key, "a".into(), &bundle(10, 5), 100, 1000);
+        let closed = auctions.seal(11);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].receipts, vec![("a".to_owned(), receipt.signing_root())]);
+        assert!(auctions.seal(11).is_empty());
+    }
+
+    #[test]
+    fn test_outcome_from_built_block() {
+        let mut auctions = Auctions::default();
+        auctions.record("a", &bundle(10, 9), 1000, 100).unwrap();
+        auctions.record("b", &bundle(10, 7), 1000, 200).unwrap();
+        auctions.record("c", &bundle(10, 4), 1000, 300).unwrap();
+        // b declared less than a but pays the fee recipient more, so it tops the block, c was left out
+        auctions.record_block(10, vec![("b".to_owned(), 12.into()), ("a".to_owned(), 9.into())]);
+
+        let outcome = auctions.outcome(10, 1001, Payment::FirstPrice).unwrap().unwrap();
+        assert_eq!((outcome.tob_id.as_str(), outcome.bid, outcome.value, outcome.payment, outcome.bids), ("b", 7.into(), 12.into(), 7.into(), 3));
+        let outcome = auctions.outcome(10, 1001, Payment::SecondPrice).unwrap().unwrap();
+        assert_eq!((outcome.tob_id.as_str(), outcome.payment), ("b", 7.into()));
+
+        // a later build replaces the outcome
+        auctions.record_block(10, vec![("a".to_owned(), 9.into())]);
+        let outcome = auctions.outcome(10, 1001, Payment::SecondPrice).unwrap().unwrap();
+        assert_eq!((outcome.tob_id.as_str(), outcome.value, outcome.payment), ("a", 9.into(), 7.into()));
+    }
+
+    #[test]
+    fn test_receipt() {
+        let key = EnclaveKey::from_ikm(&[1; 32]);
+        let receipt = BidReceipt::new(&key, "a".into(), &bundle(10, 5), 100, 1000);
+        assert!(receipt.verify(key.public_key()).is_ok());
+
+        let mut forged = receipt.clone();
+        forged.received_at = 999;
+        assert!(forged.verify(key.public_key()).is_err());
+        let other = EnclaveKey::from_ikm(&[2; 32]);
+        assert!(receipt.verify(other.public_key()).is_err());
+    }
+}
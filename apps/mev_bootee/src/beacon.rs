@@ -0,0 +1,122 @@
+use std::prelude::v1::*;
+
+use std::time::Duration;
+
+use net_http::{HttpClient, HttpMethod, HttpRequestBuilder, Uri};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::MevBooTeeError;
+
+const BEACON_TIMEOUT: Duration = Duration::from_secs(5);
+// how long an event subscription stays open, one slot
+const EVENTS_WINDOW: Duration = Duration::from_secs(12);
+
+// minimal client for the beacon node REST API
+#[derive(Clone)]
+pub struct BeaconClient {
+    endpoint: String,
+    client: HttpClient,
+}
+
+#[derive(Deserialize)]
+pub struct BeaconResponse<T> {
+    pub data: T,
+}
+
+// an event of the beacon node event stream
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeaconEvent {
+    pub event: String,
+    pub data: String,
+}
+
+impl BeaconClient {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            client: HttpClient::new(),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, MevBooTeeError> {
+        self.send(HttpMethod::Get, path, None)
+    }
+
+    pub fn post<B: Serialize, T: DeserializeOwned>(&self, path: &str, body: &B) -> Result<T, MevBooTeeError> {
+        let body = serde_json::to_vec(body).map_err(|err| MevBooTeeError::Beacon(format!("{:?}", err)))?;
+        self.send(HttpMethod::Post, path, Some(body))
+    }
+
+    // the events of `topics` emitted during the next EVENTS_WINDOW
+    pub fn events(&self, topics: &str) -> Result<Vec<BeaconEvent>, MevBooTeeError> {
+        let path = format!("/eth/v1/events?topics={}", topics);
+        let body = self.request(HttpMethod::Get, &path, None, "text/event-stream", EVENTS_WINDOW)?;
+        Ok(parse_events(&String::from_utf8_lossy(&body)))
+    }
+
+    fn send<T: DeserializeOwned>(&self, method: HttpMethod, path: &str, body: Option<Vec<u8>>) -> Result<T, MevBooTeeError> {
+        let url = format!("{}{}", self.endpoint, path);
+        let body = self.request(method, path, body, "application/json", BEACON_TIMEOUT)?;
+        if body.is_empty() {
+            // some endpoints answer with an empty body, let `()` and `Option` absorb it
+            return serde_json::from_slice(b"null").map_err(|err| MevBooTeeError::Beacon(format!("{}: {:?}", url, err)));
+        }
+        serde_json::from_slice(&body).map_err(|err| MevBooTeeError::Beacon(format!("{}: {:?}", url, err)))
+    }
+
+    fn request(&self, method: HttpMethod, path: &str, body: Option<Vec<u8>>, accept: &str, timeout: Duration) -> Result<Vec<u8>, MevBooTeeError> {
+        let url = format!("{}{}", self.endpoint, path);
+        let uri: Uri = url.parse().map_err(|err| MevBooTeeError::Beacon(format!("invalid url {}: {:?}", url, err)))?;
+        let mut req = HttpRequestBuilder::new_ex(uri, body, |req| {
+            req.method(method);
+            req.header("Content-Type", "application/json");
+            req.header("Accept", accept);
+        });
+        let resp = self.client.send(&mut req, Some(timeout))
+            .map_err(|err| MevBooTeeError::Beacon(format!("{}: {:?}", url, err)))?;
+        if !resp.status.is_success() {
+            return Err(MevBooTeeError::Beacon(format!("{}: status {}: {}", url, resp.status, String::from_utf8_lossy(&resp.body))));
+        }
+        Ok(resp.body)
+    }
+}
+
+// server-sent events: `event:` and `data:` lines, separated by a blank line
+pub fn parse_events(stream: &str) -> Vec<BeaconEvent> {
+    let mut events = Vec::new();
+    let mut event = String::new();
+    let mut data = Vec::new();
+    for line in stream.lines().chain(std::iter::once("")) {
+        if line.is_empty() {
+            if !data.is_empty() {
+                events.push(BeaconEvent { event: std::mem::take(&mut event), data: data.join("\n") });
+            }
+            event.clear();
+            data.clear();
+        } else if let Some(value) = line.strip_prefix("event:") {
+            event = value.trim().to_owned();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.trim().to_owned());
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_events() {
+        let stream = "event: head\ndata: {\"slot\": \"10\"}\n\n: keep-alive\n\nevent: payload_attributes\ndata: {\"a\":\ndata: 1}\n";
+        let events = parse_events(stream);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], BeaconEvent { event: "head".into(), data: "{\"slot\": \"10\"}".into() });
+        assert_eq!(events[1].event, "payload_attributes");
+        assert_eq!(events[1].data, "{\"a\":\n1}");
+    }
+}
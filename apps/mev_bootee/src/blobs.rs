@@ -0,0 +1,513 @@
+use std::prelude::v1::*;
+
+use blst::*;
+use eth_types::{BlockHeader, HexBytes, SH256, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::{MevBooTeeError, TxRejectReason, TxRejection};
+
+pub const FIELD_ELEMENTS_PER_BLOB: usize = 4096;
+pub const BYTES_PER_FIELD_ELEMENT: usize = 32;
+pub const BYTES_PER_BLOB: usize = FIELD_ELEMENTS_PER_BLOB * BYTES_PER_FIELD_ELEMENT;
+const BYTES_PER_G1: usize = 48;
+const BYTES_PER_G2: usize = 96;
+
+pub const GAS_PER_BLOB: u64 = 1 << 17;
+pub const TARGET_BLOB_GAS_PER_BLOCK: u64 = 3 * GAS_PER_BLOB;
+pub const MAX_BLOB_GAS_PER_BLOCK: u64 = 6 * GAS_PER_BLOB;
+
+const BLOB_TX_TYPE: u64 = 3;
+const VERSIONED_HASH_VERSION_KZG: u8 = 0x01;
+const FIAT_SHAMIR_PROTOCOL_DOMAIN: &[u8] = b"FSBLOBVERIFY_V1_";
+// 7^((r - 1) / 4096) mod r, a primitive 4096th root of unity of the BLS12-381 scalar field, big endian
+const ROOT_OF_UNITY: [u8; 32] = [
+    0x56, 0x4c, 0x0a, 0x11, 0xa0, 0xf7, 0x04, 0xf4, 0xfc, 0x3e, 0x8a, 0xcf, 0xe0, 0xf8, 0x24, 0x5f,
+    0x0a, 0xd1, 0x34, 0x7b, 0x37, 0x8f, 0xbf, 0x96, 0xe2, 0x06, 0xda, 0x11, 0xa5, 0xd3, 0x63, 0x06,
+];
+
+// the blobs of a type 3 transaction, which only travel next to the block
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlobSidecar {
+    pub tx_hash: SH256,
+    pub blobs: Vec<HexBytes>,
+    pub commitments: Vec<HexBytes>,
+    pub proofs: Vec<HexBytes>,
+}
+
+pub fn is_blob_transaction(txn: &Transaction) -> bool {
+    txn.r#type.as_u64() == BLOB_TX_TYPE
+}
+
+pub fn blob_gas_used(txn: &Transaction) -> u64 {
+    txn.blob_versioned_hashes.as_ref().map(|hashes| hashes.len() as u64).unwrap_or_default() * GAS_PER_BLOB
+}
+
+pub fn calc_excess_blob_gas(parent_excess_blob_gas: u64, parent_blob_gas_used: u64) -> u64 {
+    (parent_excess_blob_gas + parent_blob_gas_used).saturating_sub(TARGET_BLOB_GAS_PER_BLOCK)
+}
+
+// the header fields of Cancun, a block has a parent beacon block root from Cancun on. they stay unset before it
+pub fn set_cancun_fields(header: &mut BlockHeader, parent: &BlockHeader, transactions: &[Transaction], parent_beacon_block_root: Option<SH256>) {
+    match parent_beacon_block_root {
+        Some(root) => {
+            let excess_blob_gas = calc_excess_blob_gas(
+                parent.excess_blob_gas.map(|gas| gas.as_u64()).unwrap_or_default(),
+                parent.blob_gas_used.map(|gas| gas.as_u64()).unwrap_or_default(),
+            );
+            header.blob_gas_used = Some(transactions.iter().map(blob_gas_used).sum::<u64>().into());
+            header.excess_blob_gas = Some(excess_blob_gas.into());
+            header.parent_beacon_block_root = Some(root);
+        },
+        None => {
+            header.blob_gas_used = None;
+            header.excess_blob_gas = None;
+            header.parent_beacon_block_root = None;
+        },
+    }
+}
+
+pub fn kzg_to_versioned_hash(commitment: &[u8]) -> SH256 {
+    let mut hash = sha256(commitment);
+    hash[0] = VERSIONED_HASH_VERSION_KZG;
+    SH256::from(hash)
+}
+
+// every blob transaction of `txns` comes with the sidecar of its versioned hashes, and every sidecar belongs to one of them
+// the proofs are not checked here, see KzgSettings::verify_sidecar
+pub fn match_sidecars(txns: &[Transaction], sidecars: &[BlobSidecar]) -> Result<(), TxRejection> {
+    for (index, txn) in txns.iter().enumerate() {
+        if !is_blob_transaction(txn) {
+            continue;
+        }
+        let reject = |reason: String| TxRejection { index, reason: TxRejectReason::Blob(reason) };
+        let sidecar = sidecars.iter().find(|sidecar| sidecar.tx_hash == txn.hash)
+            .ok_or_else(|| reject("missing sidecar".into()))?;
+        let versioned_hashes = txn.blob_versioned_hashes.clone().unwrap_or_default();
+        if versioned_hashes.is_empty() {
+            return Err(reject("no blob".into()));
+        }
+        let count = versioned_hashes.len();
+        if sidecar.blobs.len() != count || sidecar.commitments.len() != count || sidecar.proofs.len() != count {
+            return Err(reject(format!("{} versioned hashes, sidecar with {} blobs, {} commitments and {} proofs",
+                count, sidecar.blobs.len(), sidecar.commitments.len(), sidecar.proofs.len())));
+        }
+        for (i, (hash, commitment)) in versioned_hashes.iter().zip(&sidecar.commitments).enumerate() {
+            if *hash != kzg_to_versioned_hash(commitment) {
+                return Err(reject(format!("commitment {} does not match versioned hash {:?}", i, hash)));
+            }
+        }
+    }
+    let unused = sidecars.iter()
+        .filter(|sidecar| !txns.iter().any(|txn| is_blob_transaction(txn) && txn.hash == sidecar.tx_hash))
+        .count();
+    if unused > 0 {
+        return Err(TxRejection { index: 0, reason: TxRejectReason::Blob(format!("{} sidecars without transaction", unused)) });
+    }
+    Ok(())
+}
+
+// the type 3 transaction in its network form: the signed payload followed by its blobs, commitments and proofs
+pub fn blob_network_transaction(raw: &[u8], sidecar: &BlobSidecar) -> Result<Vec<u8>, MevBooTeeError> {
+    match raw.split_first() {
+        Some((ty, payload)) if *ty as u64 == BLOB_TX_TYPE => {
+            let items = |items: &[HexBytes]| rlp_list(&items.iter().map(|item| rlp_bytes(item)).collect::<Vec<_>>().concat());
+            let body = [payload.to_vec(), items(&sidecar.blobs), items(&sidecar.commitments), items(&sidecar.proofs)].concat();
+            let mut out = vec![*ty];
+            out.extend_from_slice(&rlp_list(&body));
+            Ok(out)
+        },
+        _ => Err(MevBooTeeError::Blob(format!("txn {:?} is not a blob transaction", sidecar.tx_hash))),
+    }
+}
+
+fn rlp_length(offset: u8, len: usize) -> Vec<u8> {
+    if len <= 55 {
+        return vec![offset + len as u8];
+    }
+    let len = (len as u64).to_be_bytes();
+    let len = &len[len.iter().position(|b| *b != 0).unwrap_or(7)..];
+    let mut out = vec![offset + 55 + len.len() as u8];
+    out.extend_from_slice(len);
+    out
+}
+
+fn rlp_bytes(data: &[u8]) -> Vec<u8> {
+    if data.len() == 1 && data[0] < 0x80 {
+        return data.to_vec();
+    }
+    [rlp_length(0x80, data.len()), data.to_vec()].concat()
+}
+
+fn rlp_list(payload: &[u8]) -> Vec<u8> {
+    [rlp_length(0xc0, payload.len()), payload.to_vec()].concat()
+}
+
+// the part of the KZG trusted setup needed to verify blob proofs
+pub struct KzgSettings {
+    tau_g2: blst_p2_affine, // [τ]G2
+    roots_of_unity: Vec<blst_fr>, // in bit reversed order, as the blob field elements
+}
+
+impl KzgSettings {
+    // a trusted setup in the format of the c-kzg trusted_setup.txt
+    pub fn load(path: &str) -> Result<Self, MevBooTeeError> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|err| MevBooTeeError::Config(format!("unable to read trusted setup {}: {}", path, err)))?;
+        Self::parse(&data).map_err(|err| MevBooTeeError::Config(format!("invalid trusted setup {}: {}", path, err)))
+    }
+
+    pub fn parse(data: &str) -> Result<Self, String> {
+        let mut lines = data.split_whitespace();
+        let mut count = || -> Result<usize, String> {
+            let line = lines.next().ok_or("truncated")?;
+            line.parse().map_err(|_| format!("invalid point count {:?}", line))
+        };
+        let (g1_points, g2_points) = (count()?, count()?);
+        if g1_points != FIELD_ELEMENTS_PER_BLOB || g2_points < 2 {
+            return Err(format!("{} G1 and {} G2 points", g1_points, g2_points));
+        }
+        // the G1 points only serve to commit, skip to the second G2 point
+        let line = lines.nth(g1_points + 1).ok_or("truncated")?;
+        let bytes = decode_hex(line).ok_or_else(|| format!("invalid G2 point {:?}", line))?;
+        let tau_g2 = g2_from_bytes(&bytes).ok_or_else(|| format!("invalid G2 point {:?}", line))?;
+        Ok(Self::new(tau_g2))
+    }
+
+    fn new(tau_g2: blst_p2_affine) -> Self {
+        let root = fr_from_bytes(&ROOT_OF_UNITY).unwrap();
+        let mut powers = Vec::with_capacity(FIELD_ELEMENTS_PER_BLOB);
+        let mut power = fr_from_u64(1);
+        for _ in 0..FIELD_ELEMENTS_PER_BLOB {
+            powers.push(power);
+            power = fr_mul(&power, &root);
+        }
+        let bits = FIELD_ELEMENTS_PER_BLOB.trailing_zeros();
+        let roots_of_unity = (0..FIELD_ELEMENTS_PER_BLOB)
+            .map(|i| powers[i.reverse_bits() >> (usize::BITS - bits)])
+            .collect();
+        Self { tau_g2, roots_of_unity }
+    }
+
+    pub fn verify_sidecar(&self, sidecar: &BlobSidecar) -> Result<(), MevBooTeeError> {
+        let blobs = sidecar.blobs.iter().zip(&sidecar.commitments).zip(&sidecar.proofs);
+        for (i, ((blob, commitment), proof)) in blobs.enumerate() {
+            self.verify_blob_kzg_proof(blob, commitment, proof)
+                .map_err(|err| MevBooTeeError::Blob(format!("txn {:?} blob {}: {}", sidecar.tx_hash, i, err)))?;
+        }
+        Ok(())
+    }
+
+    // verify_blob_kzg_proof of the deneb polynomial commitments spec
+    pub fn verify_blob_kzg_proof(&self, blob: &[u8], commitment: &[u8], proof: &[u8]) -> Result<(), String> {
+        if blob.len() != BYTES_PER_BLOB {
+            return Err(format!("blob of {} bytes", blob.len()));
+        }
+        let commitment_point = g1_from_bytes(commitment).ok_or("invalid commitment")?;
+        let proof_point = g1_from_bytes(proof).ok_or("invalid proof")?;
+        let polynomial = blob.chunks(BYTES_PER_FIELD_ELEMENT)
+            .map(fr_from_bytes)
+            .collect::<Option<Vec<_>>>()
+            .ok_or("blob with a non canonical field element")?;
+        let z = compute_challenge(blob, commitment);
+        let y = self.evaluate(&polynomial, &z);
+        if !self.verify_kzg_proof(&commitment_point, &z, &y, &proof_point) {
+            return Err("invalid proof".into());
+        }
+        Ok(())
+    }
+
+    // the polynomial given by its values on the roots of unity, evaluated at `z` with the barycentric formula
+    fn evaluate(&self, polynomial: &[blst_fr], z: &blst_fr) -> blst_fr {
+        if let Some(i) = self.roots_of_unity.iter().position(|root| root == z) {
+            return polynomial[i];
+        }
+        let denominators: Vec<blst_fr> = self.roots_of_unity.iter().map(|root| fr_sub(z, root)).collect();
+        let inverses = fr_batch_inverse(&denominators);
+        let mut sum = blst_fr::default();
+        for ((value, root), inverse) in polynomial.iter().zip(&self.roots_of_unity).zip(&inverses) {
+            sum = fr_add(&sum, &fr_mul(&fr_mul(value, root), inverse));
+        }
+        // (z^N - 1) / N
+        let n = fr_from_u64(FIELD_ELEMENTS_PER_BLOB as u64);
+        let mut z_n = *z;
+        for _ in 0..FIELD_ELEMENTS_PER_BLOB.trailing_zeros() {
+            z_n = fr_mul(&z_n, &z_n);
+        }
+        let factor = fr_mul(&fr_sub(&z_n, &fr_from_u64(1)), &fr_inverse(&n));
+        fr_mul(&sum, &factor)
+    }
+
+    // e(C - [y]G1, G2) == e(proof, [τ - z]G2)
+    fn verify_kzg_proof(&self, commitment: &blst_p1_affine, z: &blst_fr, y: &blst_fr, proof: &blst_p1_affine) -> bool {
+        unsafe {
+            let mut minus_z = blst_p2::default();
+            blst_p2_mult(&mut minus_z, blst_p2_generator(), fr_to_scalar(z).b.as_ptr(), 255);
+            blst_p2_cneg(&mut minus_z, true);
+            let mut tau = blst_p2::default();
+            blst_p2_from_affine(&mut tau, &self.tau_g2);
+            let mut tau_minus_z = blst_p2::default();
+            blst_p2_add_or_double(&mut tau_minus_z, &tau, &minus_z);
+            let mut tau_minus_z_affine = blst_p2_affine::default();
+            blst_p2_to_affine(&mut tau_minus_z_affine, &tau_minus_z);
+
+            let mut minus_y = blst_p1::default();
+            blst_p1_mult(&mut minus_y, blst_p1_generator(), fr_to_scalar(y).b.as_ptr(), 255);
+            blst_p1_cneg(&mut minus_y, true);
+            let mut c = blst_p1::default();
+            blst_p1_from_affine(&mut c, commitment);
+            let mut c_minus_y = blst_p1::default();
+            blst_p1_add_or_double(&mut c_minus_y, &c, &minus_y);
+            let mut c_minus_y_affine = blst_p1_affine::default();
+            blst_p1_to_affine(&mut c_minus_y_affine, &c_minus_y);
+
+            let mut lhs = blst_fp12::default();
+            blst_miller_loop(&mut lhs, blst_p2_affine_generator(), &c_minus_y_affine);
+            let mut rhs = blst_fp12::default();
+            blst_miller_loop(&mut rhs, &tau_minus_z_affine, proof);
+            blst_fp12_finalverify(&lhs, &rhs)
+        }
+    }
+}
+
+// the Fiat-Shamir evaluation point of the blob
+fn compute_challenge(blob: &[u8], commitment: &[u8]) -> blst_fr {
+    let mut data = FIAT_SHAMIR_PROTOCOL_DOMAIN.to_vec();
+    data.extend_from_slice(&(FIELD_ELEMENTS_PER_BLOB as u128).to_be_bytes());
+    data.extend_from_slice(blob);
+    data.extend_from_slice(commitment);
+    let hash = sha256(&data);
+    let mut scalar = blst_scalar::default();
+    let mut fr = blst_fr::default();
+    unsafe {
+        // reduced modulo r
+        blst_scalar_from_be_bytes(&mut scalar, hash.as_ptr(), hash.len());
+        blst_fr_from_scalar(&mut fr, &scalar);
+    }
+    fr
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0_u8; 32];
+    unsafe { blst_sha256(hash.as_mut_ptr(), data.as_ptr(), data.len()) };
+    hash
+}
+
+fn decode_hex(data: &str) -> Option<Vec<u8>> {
+    let data = data.trim_start_matches("0x");
+    if data.len() % 2 != 0 {
+        return None;
+    }
+    (0..data.len()).step_by(2).map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn g1_from_bytes(bytes: &[u8]) -> Option<blst_p1_affine> {
+    if bytes.len() != BYTES_PER_G1 {
+        return None;
+    }
+    let mut point = blst_p1_affine::default();
+    unsafe {
+        if blst_p1_uncompress(&mut point, bytes.as_ptr()) != BLST_ERROR::BLST_SUCCESS || !blst_p1_affine_in_g1(&point) {
+            return None;
+        }
+    }
+    Some(point)
+}
+
+fn g2_from_bytes(bytes: &[u8]) -> Option<blst_p2_affine> {
+    if bytes.len() != BYTES_PER_G2 {
+        return None;
+    }
+    let mut point = blst_p2_affine::default();
+    unsafe {
+        if blst_p2_uncompress(&mut point, bytes.as_ptr()) != BLST_ERROR::BLST_SUCCESS || !blst_p2_affine_in_g2(&point) {
+            return None;
+        }
+    }
+    Some(point)
+}
+
+// a canonical big endian field element
+fn fr_from_bytes(bytes: &[u8]) -> Option<blst_fr> {
+    let mut scalar = blst_scalar::default();
+    let mut fr = blst_fr::default();
+    unsafe {
+        blst_scalar_from_bendian(&mut scalar, bytes.as_ptr());
+        if !blst_scalar_fr_check(&scalar) {
+            return None;
+        }
+        blst_fr_from_scalar(&mut fr, &scalar);
+    }
+    Some(fr)
+}
+
+fn fr_to_scalar(fr: &blst_fr) -> blst_scalar {
+    let mut scalar = blst_scalar::default();
+    unsafe { blst_scalar_from_fr(&mut scalar, fr) };
+    scalar
+}
+
+fn fr_from_u64(value: u64) -> blst_fr {
+    let limbs = [value, 0, 0, 0];
+    let mut fr = blst_fr::default();
+    unsafe { blst_fr_from_uint64(&mut fr, limbs.as_ptr()) };
+    fr
+}
+
+fn fr_add(a: &blst_fr, b: &blst_fr) -> blst_fr {
+    let mut out = blst_fr::default();
+    unsafe { blst_fr_add(&mut out, a, b) };
+    out
+}
+
+fn fr_sub(a: &blst_fr, b: &blst_fr) -> blst_fr {
+    let mut out = blst_fr::default();
+    unsafe { blst_fr_sub(&mut out, a, b) };
+    out
+}
+
+fn fr_mul(a: &blst_fr, b: &blst_fr) -> blst_fr {
+    let mut out = blst_fr::default();
+    unsafe { blst_fr_mul(&mut out, a, b) };
+    out
+}
+
+fn fr_inverse(a: &blst_fr) -> blst_fr {
+    let mut out = blst_fr::default();
+    unsafe { blst_fr_eucl_inverse(&mut out, a) };
+    out
+}
+
+// one inversion for all the elements, none of them is zero
+fn fr_batch_inverse(elements: &[blst_fr]) -> Vec<blst_fr> {
+    let mut products = Vec::with_capacity(elements.len());
+    let mut product = fr_from_u64(1);
+    for element in elements {
+        products.push(product);
+        product = fr_mul(&product, element);
+    }
+    let mut inverse = fr_inverse(&product);
+    let mut inverses = vec![blst_fr::default(); elements.len()];
+    for i in (0..elements.len()).rev() {
+        inverses[i] = fr_mul(&inverse, &products[i]);
+        inverse = fr_mul(&inverse, &elements[i]);
+    }
+    inverses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn g1_mul(scalar: &blst_fr) -> Vec<u8> {
+        let mut point = blst_p1::default();
+        let mut bytes = [0_u8; BYTES_PER_G1];
+        unsafe {
+            blst_p1_mult(&mut point, blst_p1_generator(), fr_to_scalar(scalar).b.as_ptr(), 255);
+            blst_p1_compress(bytes.as_mut_ptr(), &point);
+        }
+        bytes.to_vec()
+    }
+
+    // a setup whose secret is known, to commit and prove without the ceremony points
+    fn insecure_setup(tau: &blst_fr) -> KzgSettings {
+        let mut point = blst_p2::default();
+        let mut affine = blst_p2_affine::default();
+        unsafe {
+            blst_p2_mult(&mut point, blst_p2_generator(), fr_to_scalar(tau).b.as_ptr(), 255);
+            blst_p2_to_affine(&mut affine, &point);
+        }
+        KzgSettings::new(affine)
+    }
+
+    #[test]
+    fn test_verify_blob_kzg_proof() {
+        let tau = fr_from_u64(0x1234_5678);
+        let kzg = insecure_setup(&tau);
+        let mut blob = vec![0_u8; BYTES_PER_BLOB];
+        for (i, element) in blob.chunks_mut(BYTES_PER_FIELD_ELEMENT).enumerate() {
+            element[24..].copy_from_slice(&(i as u64 * 7919).to_be_bytes());
+        }
+        let polynomial: Vec<blst_fr> = blob.chunks(BYTES_PER_FIELD_ELEMENT).map(|e| fr_from_bytes(e).unwrap()).collect();
+
+        // C = [p(τ)]G1 and proof = [(p(τ) - y) / (τ - z)]G1
+        let p_tau = kzg.evaluate(&polynomial, &tau);
+        let commitment = g1_mul(&p_tau);
+        let z = compute_challenge(&blob, &commitment);
+        let y = kzg.evaluate(&polynomial, &z);
+        let proof = g1_mul(&fr_mul(&fr_sub(&p_tau, &y), &fr_inverse(&fr_sub(&tau, &z))));
+        assert!(kzg.verify_blob_kzg_proof(&blob, &commitment, &proof).is_ok());
+
+        let mut tampered = blob.clone();
+        tampered[BYTES_PER_BLOB - 1] ^= 1;
+        assert!(kzg.verify_blob_kzg_proof(&tampered, &commitment, &proof).is_err());
+        assert!(kzg.verify_blob_kzg_proof(&blob, &commitment, &commitment).is_err());
+        // not a field element
+        tampered[0] = 0xff;
+        assert!(kzg.verify_blob_kzg_proof(&tampered, &commitment, &proof).is_err());
+    }
+
+    #[test]
+    fn test_excess_blob_gas() {
+        assert_eq!(calc_excess_blob_gas(0, TARGET_BLOB_GAS_PER_BLOCK), 0);
+        assert_eq!(calc_excess_blob_gas(0, MAX_BLOB_GAS_PER_BLOCK), TARGET_BLOB_GAS_PER_BLOCK);
+        assert_eq!(calc_excess_blob_gas(TARGET_BLOB_GAS_PER_BLOCK, 0), 0);
+        assert_eq!(calc_excess_blob_gas(2 * TARGET_BLOB_GAS_PER_BLOCK, GAS_PER_BLOB), TARGET_BLOB_GAS_PER_BLOCK + GAS_PER_BLOB);
+    }
+
+    #[test]
+    fn test_cancun_fields() {
+        let mut parent = BlockHeader::default();
+        parent.excess_blob_gas = Some(TARGET_BLOB_GAS_PER_BLOCK.into());
+        parent.blob_gas_used = Some(MAX_BLOB_GAS_PER_BLOCK.into());
+        let mut txn = Transaction::default();
+        txn.blob_versioned_hashes = Some(vec![SH256::default(); 2]);
+
+        let mut header = BlockHeader::default();
+        set_cancun_fields(&mut header, &parent, &[txn.clone()], Some(SH256::from([1; 32])));
+        assert_eq!(header.blob_gas_used, Some((2 * GAS_PER_BLOB).into()));
+        assert_eq!(header.excess_blob_gas, Some(MAX_BLOB_GAS_PER_BLOCK.into()));
+        assert_eq!(header.parent_beacon_block_root, Some(SH256::from([1; 32])));
+        // before Cancun the header keeps its pre-Cancun hash
+        set_cancun_fields(&mut header, &parent, &[txn], None);
+        assert_eq!(header.hash(), BlockHeader::default().hash());
+    }
+
+    #[test]
+    fn test_match_sidecars() {
+        let commitment = vec![0xc0; BYTES_PER_G1];
+        let mut txn = Transaction::default();
+        txn.r#type = BLOB_TX_TYPE.into();
+        txn.hash = SH256::from([1; 32]);
+        txn.blob_versioned_hashes = Some(vec![kzg_to_versioned_hash(&commitment)]);
+        let sidecar = BlobSidecar {
+            tx_hash: txn.hash,
+            blobs: vec![vec![0; BYTES_PER_BLOB].into()],
+            commitments: vec![commitment.into()],
+            proofs: vec![vec![0xc0; BYTES_PER_G1].into()],
+        };
+        assert!(match_sidecars(&[txn.clone()], &[sidecar.clone()]).is_ok());
+        assert!(match_sidecars(&[txn.clone()], &[]).is_err());
+        assert!(match_sidecars(&[], &[sidecar.clone()]).is_err());
+
+        let mut other = sidecar;
+        other.commitments = vec![vec![0xc1; BYTES_PER_G1].into()];
+        assert!(match_sidecars(&[txn], &[other]).is_err());
+    }
+
+    #[test]
+    fn test_blob_network_transaction() {
+        let sidecar = BlobSidecar {
+            tx_hash: SH256::default(),
+            blobs: vec![vec![1, 2].into()],
+            commitments: vec![vec![3].into()],
+            proofs: vec![vec![0x80].into()],
+        };
+        // 0x03 || rlp([[0x01], [0x82 01 02], [0x03], [0x81 80]])
+        let raw = [0x03, 0xc1, 0x01];
+        assert_eq!(
+            blob_network_transaction(&raw, &sidecar).unwrap(),
+            vec![0x03, 0xcb, 0xc1, 0x01, 0xc3, 0x82, 0x01, 0x02, 0xc1, 0x03, 0xc2, 0x81, 0x80],
+        );
+        assert!(blob_network_transaction(&[0x02, 0xc0], &sidecar).is_err());
+    }
+}
@@ -1,172 +1,624 @@
 use std::prelude::v1::*;
 
-use std::{collections::BTreeMap, sync::{Arc, Mutex}};
+use std::collections::{BTreeMap, BTreeSet};
 
-use eth_tools::{ExecutionClient, MixRpcClient};
-use mpt::{Database, TrieState, BlockStateFetcher};
-use evm_executor::{BlockBuilder, ConsensusBlockInfo, Engine, Ethereum, BlockHashGetter};
-use statedb::StateDB;
+use evm_executor::ConsensusBlockInfo;
 
-use crate::{WrappedBundle, MevBooTEEError};
+use crate::{blob_gas_used, match_sidecars, set_cancun_fields, BlobSidecar, MAX_BLOB_GAS_PER_BLOCK};
+use crate::{balance_of, commit_transaction, new_block_builder, recover_sender, unix_timestamp, ElClient, EvmBlockBuilder, MevBooTeeError, StateKey, WrappedBundle};
 
-use eth_types::{SH256, Transaction, EthereumEngineTypes};
+use eth_types::{BlockHeader, Signer, SH160, SH256, SU256, Transaction, Withdrawal};
 
-pub trait BlockBuildingStrategy {
-    fn new(el: Arc<ExecutionClient<Arc<MixRpcClient>>>, block_number: u64) -> Self;
-    fn add_bundle(&mut self, bundle_id: String, bundle: WrappedBundle);
-    fn remove_bundle(&mut self, bundle_id: &String) -> bool;
-    fn add_inclusion_list(&mut self, inclusion_list: Vec<Transaction>);
-    fn get_block(&self) -> eth_types::Block;
-    fn get_block_header(&self) -> eth_types::BlockHeader;
-    fn create_bundle(&self, transactions: Vec<Transaction>) -> Result<WrappedBundle, MevBooTEEError>; // create and verify the bundle against the starting state of the order flow
+// how many times a rebuild may re-rank the bundles by the value they pay in the block
+const MAX_RERANK_ROUNDS: usize = 3;
+
+pub trait BlockBuildingStrategy: Sized {
+    // build on top of `parent`, `withdrawals` are credited after the transactions from Shanghai on
+    // and the parent beacon block root is stored before them from Cancun on
+    fn new(
+        el: ElClient, parent: BlockHeader, info: ConsensusBlockInfo, withdrawals: Option<Vec<Withdrawal>>, parent_beacon_block_root: Option<SH256>,
+    ) -> Result<Self, MevBooTeeError>;
+    fn add_bundle(&mut self, bundle_id: String, bundle: WrappedBundle) -> Result<(), MevBooTeeError>; // rejects the bundles paying less than their bid
+    fn remove_bundle(&mut self, bundle_id: &String) -> Result<bool, MevBooTeeError>; // false for an unknown bundle
+    fn add_inclusion_list(&mut self, inclusion_list: Vec<Transaction>) -> Result<(), MevBooTeeError>;
+    fn get_block(&mut self) -> Result<eth_types::Block, MevBooTeeError>; // the sealed block: state, receipts and transactions roots, logs bloom
+    fn get_block_header(&mut self) -> Result<eth_types::BlockHeader, MevBooTeeError>;
+    fn get_blob_sidecars(&self) -> Vec<BlobSidecar>; // the sidecars of the blob transactions of the block
+    fn get_bid(&self) -> SU256; // what the block is worth to the proposer: what the included bundles and the inclusion list pay to the fee recipient
+    fn get_payments(&self) -> Vec<(String, SU256)>; // the included bundles in block order, with what each pays to the fee recipient
+    fn create_bundle(&mut self, transactions: Vec<Transaction>, reverting_tx_hashes: Vec<SH256>) -> Result<WrappedBundle, MevBooTeeError>; // create and verify the bundle against the starting state of the order flow
 }
 
 pub struct GreedyBlockBuildingStrategy {
-    pub builder: BlockBuilder<
-        Ethereum,
-        TrieState<BlockStateFetcher<Arc<MixRpcClient>, EthereumEngineTypes, Arc<ExecutionClient<Arc<MixRpcClient>>>>, Database>,
-        BuilderFetcher
-    >,
+    pub builder: EvmBlockBuilder,
+    simulator: EvmBlockBuilder, // executes bundles alone on top of the parent state
+    simulator_root: SH256,
+    chain_id: u64,
+    block_number: u64,
+    parent: BlockHeader,
+    pub order: GreedyOrder<(usize, SH256)>, // the bundles and the selection made out of them
+    state_root: SH256,
+    coinbase: SH160,
+    inclusion_list_fees: SU256, // paid to the coinbase by self.order.inclusion_list when executed after the bundles of self.order.block
+    withdrawals: Option<Vec<Withdrawal>>, // None before Shanghai
+    parent_beacon_block_root: Option<SH256>, // None before Cancun
+}
+
+// what a bundle did when executed on top of the block being built
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BundleExecution {
+    pub gas_used: u64,
+    pub coinbase_delta: SU256, // what the fee recipient earned, priority fees and direct payments
+    pub reads: BTreeSet<StateKey>, // accounts and storage slots the bundle depends on, a superset of writes
+    pub writes: BTreeSet<StateKey>, // accounts and storage slots the bundle modifies
+}
+
+impl BundleExecution {
+    // the outcome of one bundle may depend on whether the other one ran before
+    pub fn conflicts_with(&self, other: &BundleExecution) -> bool {
+        !self.writes.is_disjoint(&other.reads) || !other.writes.is_disjoint(&self.reads)
+    }
+}
+
+// the execution the bundle selection relies on
+pub trait BundleExecutor {
+    type Checkpoint;
+    fn checkpoint(&mut self) -> Result<Self::Checkpoint, MevBooTeeError>;
+    fn revert(&mut self, checkpoint: Self::Checkpoint);
+    // on failure, the state is what it was before the call
+    fn execute(&mut self, txns: &[Transaction], may_revert: &[SH256]) -> Result<BundleExecution, MevBooTeeError>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    pub block: Vec<String>, // bundle ids as they appear in the block
+    pub inclusion_list: Vec<Transaction>, // the proposer txns which are not part of a selected bundle
+    pub executions: BTreeMap<String, BundleExecution>,
+}
+
+// go through `bundles` in order and keep each one which executes on top of the previous ones
+// without preventing the remaining proposer txns from executing after it
+// the executor is left with the selected bundles applied
+pub fn select_bundles<E: BundleExecutor>(executor: &mut E, bundles: &[(String, WrappedBundle)], proposer_requested_txns: &[Transaction]) -> Selection
+where E::Checkpoint: Clone {
+    let mut selection = IncrementalSelection::new(proposer_requested_txns.to_vec());
+    selection.extend(executor, bundles);
+    selection.selection()
+}
+
+struct SelectionStep<C> {
+    bundle_id: String,
+    before: C, // the state before the bundle
+    inclusion_list: Vec<Transaction>, // the remaining proposer txns before the bundle
+    execution: Option<BundleExecution>, // set when the bundle is selected
+    blob_gas_used: u64,
+}
+
+// the selection of select_bundles, resumable from any position of the bundle order
+pub struct IncrementalSelection<C> {
+    steps: Vec<SelectionStep<C>>,
+    inclusion_list: Vec<Transaction>, // the remaining proposer txns after the last step
+}
+
+impl<C: Clone> IncrementalSelection<C> {
+    pub fn new(proposer_requested_txns: Vec<Transaction>) -> Self {
+        Self {
+            steps: Vec::new(),
+            inclusion_list: proposer_requested_txns,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    // the blob gas of the selected bundles and the remaining proposer txns
+    fn blob_gas_used(&self) -> u64 {
+        let bundles: u64 = self.steps.iter().filter(|step| step.execution.is_some()).map(|step| step.blob_gas_used).sum();
+        bundles + self.inclusion_list.iter().map(blob_gas_used).sum::<u64>()
+    }
+
+    // forget the bundles from `index` onward, the executor goes back to the state before them
+    pub fn rewind<E: BundleExecutor<Checkpoint = C>>(&mut self, executor: &mut E, index: usize) {
+        if index >= self.steps.len() {
+            return;
+        }
+        let step = self.steps.drain(index..).next().unwrap();
+        executor.revert(step.before);
+        self.inclusion_list = step.inclusion_list;
+    }
+
+    // run the selection over `bundles`, which come after the bundles already processed
+    pub fn extend<E: BundleExecutor<Checkpoint = C>>(&mut self, executor: &mut E, bundles: &[(String, WrappedBundle)]) {
+        for (bundle_id, bundle) in bundles {
+            // if the bundle and inclusion list conflict, we need to return to the state before applying the bundle
+            let before_bundle = match executor.checkpoint() {
+                Ok(checkpoint) => checkpoint,
+                Err(err) => {
+                    glog::error!("unable to checkpoint before bundle {}: {}", bundle_id, err);
+                    return;
+                }
+            };
+            let mut step = SelectionStep {
+                bundle_id: bundle_id.clone(),
+                before: before_bundle.clone(),
+                inclusion_list: self.inclusion_list.clone(),
+                execution: None,
+                blob_gas_used: bundle.txns.iter().map(blob_gas_used).sum(),
+            };
+            if self.blob_gas_used() + step.blob_gas_used > MAX_BLOB_GAS_PER_BLOCK {
+                self.steps.push(step);
+                continue;
+            }
+            if let Ok(execution) = executor.execute(&bundle.txns, &bundle.reverting_tx_hashes) {
+                // apply the remainder of the inclusion list to ensure the current bundle does not conflict with it
+                let mut inclusion_list = self.inclusion_list.clone();
+                remove_common_txns(bundle, &mut inclusion_list);
+                let after_bundle = match executor.checkpoint() {
+                    Ok(checkpoint) => checkpoint,
+                    Err(err) => {
+                        glog::error!("unable to checkpoint after bundle {}: {}", bundle_id, err);
+                        executor.revert(before_bundle);
+                        return;
+                    }
+                };
+                // a proposer txn may revert, it is still valid and lands in the block: only failing to execute is a conflict
+                let may_revert: Vec<SH256> = inclusion_list.iter().map(|txn| txn.hash).collect();
+                if executor.execute(&inclusion_list, &may_revert).is_ok() {
+                    // all good, we keep the bundle
+                    executor.revert(after_bundle);
+                    step.execution = Some(execution);
+                    self.inclusion_list = inclusion_list;
+                } else {
+                    // bundle and inclusion list conflict, undo the transactions in the bundle
+                    executor.revert(before_bundle);
+                }
+            }
+            self.steps.push(step);
+        }
+    }
+
+    pub fn selection(&self) -> Selection {
+        let mut selection = Selection {
+            inclusion_list: self.inclusion_list.clone(),
+            ..Default::default()
+        };
+        for step in &self.steps {
+            if let Some(execution) = &step.execution {
+                selection.block.push(step.bundle_id.clone());
+                selection.executions.insert(step.bundle_id.clone(), execution.clone());
+            }
+        }
+        selection
+    }
+}
+
+// the bookkeeping of the greedy strategy: the bundles ordered by value and the selection made out of them
+// it runs on any BundleExecutor, the strategy runs it on its builder
+pub struct GreedyOrder<C> {
+    values: BTreeMap<String, SU256>, // what each bundle pays to the fee recipient, used to order the bundles
+    pub(crate) rerank: bool, // reorder the bundles paying less in the block than alone
     pub all_bundles: BTreeMap<String, WrappedBundle>, // all bundles
     pub ordered_bundle_ids: Vec<String>, // ordered bundle_ids (based on the value of the bundle)
     pub proposer_requested_txns: Vec<Transaction>, // transactions the proposer wants included
-    state_root: SH256,
     pub block: Vec<String>, // bundle ids as they appear in the final block
-    pub inclusion_list: Vec<Transaction> // last part of the block: subset of proposer_requested_txns (a txn may be included in a bundle and excluded from this subset)
+    pub inclusion_list: Vec<Transaction>, // last part of the block: subset of proposer_requested_txns (a txn may be included in a bundle and excluded from this subset)
+    pub executions: BTreeMap<String, BundleExecution>, // how each bundle executed in the last rebuild
+    selection: IncrementalSelection<C>, // checkpoints of the last rebuild
+}
+
+impl<C: Clone> Default for GreedyOrder<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clone> GreedyOrder<C> {
+    pub fn new() -> Self {
+        Self {
+            values: BTreeMap::new(),
+            rerank: true,
+            all_bundles: BTreeMap::new(),
+            ordered_bundle_ids: Vec::new(),
+            proposer_requested_txns: Vec::new(),
+            block: Vec::new(),
+            inclusion_list: Vec::new(),
+            executions: BTreeMap::new(),
+            selection: IncrementalSelection::new(Vec::new()),
+        }
+    }
+
+    // forget the last selection, the executor must be back to the starting state
+    pub fn restart(&mut self) {
+        self.selection = IncrementalSelection::new(self.proposer_requested_txns.clone());
+    }
+
+    // the bundles before `index` in self.ordered_bundle_ids are unchanged, only re-execute the ones after
+    pub fn rebuild_from<E: BundleExecutor<Checkpoint = C>>(&mut self, executor: &mut E, index: usize) {
+        let mut index = index;
+        for round in 0..MAX_RERANK_ROUNDS {
+            self.select_from(executor, index);
+            // the order of the last round is kept, the selection was made out of it
+            if round + 1 == MAX_RERANK_ROUNDS {
+                break;
+            }
+            match self.rerank() {
+                Some(changed) => index = changed,
+                None => break,
+            }
+        }
+    }
+
+    fn rerank(&mut self) -> Option<usize> {
+        if !self.rerank {
+            return None;
+        }
+        rerank_bundles(&mut self.ordered_bundle_ids, &mut self.values, &self.executions)
+    }
+
+    fn select_from<E: BundleExecutor<Checkpoint = C>>(&mut self, executor: &mut E, index: usize) {
+        self.selection.rewind(executor, index);
+        let bundles: Vec<(String, WrappedBundle)> = self.ordered_bundle_ids[self.selection.len()..].iter()
+            .map(|bundle_id| (bundle_id.clone(), self.all_bundles[bundle_id].clone()))
+            .collect();
+        self.selection.extend(executor, &bundles);
+
+        let selection = self.selection.selection();
+        self.block = selection.block;
+        self.inclusion_list = selection.inclusion_list;
+        self.executions = selection.executions;
+    }
+
+    pub fn contains(&self, bundle_id: &String) -> bool {
+        self.all_bundles.contains_key(bundle_id)
+    }
+
+    // keep the bundle paying `value`, it is not ranked yet
+    pub fn admit(&mut self, bundle_id: &String, bundle: WrappedBundle, value: SU256) {
+        self.values.insert(bundle_id.clone(), value);
+        self.all_bundles.insert(bundle_id.clone(), bundle);
+    }
+
+    // returns false for an unknown bundle
+    pub fn forget(&mut self, bundle_id: &String) -> bool {
+        self.values.remove(bundle_id);
+        self.all_bundles.remove(bundle_id).is_some()
+    }
+
+    // rank an admitted bundle by its value, returns the position of the bundle
+    pub fn add_bundle_id(&mut self, bundle_id: &String) -> usize {
+        let bundle_value = self.values[bundle_id];
+        for i in 0..self.ordered_bundle_ids.len() {
+            if self.values[&self.ordered_bundle_ids[i]] < bundle_value {
+                self.ordered_bundle_ids.insert(i, bundle_id.into());
+                return i
+            }
+        }
+        self.ordered_bundle_ids.push(bundle_id.into());
+        self.ordered_bundle_ids.len() - 1
+    }
+
+    // forget the bundle, returns the position it had or None for an unknown bundle
+    pub fn remove_bundle_id(&mut self, bundle_id: &String) -> Option<usize> {
+        if !self.forget(bundle_id) {
+            return None
+        }
+        let index = self.ordered_bundle_ids.iter().position(|id| id == bundle_id)?;
+        self.ordered_bundle_ids.remove(index);
+        Some(index)
+    }
+}
+
+// the bundle executor of the block being built
+struct BuilderExecutor<'a> {
+    builder: &'a mut EvmBlockBuilder,
+    coinbase: &'a SH160,
+}
+
+impl BundleExecutor for BuilderExecutor<'_> {
+    type Checkpoint = (usize, SH256);
+
+    fn checkpoint(&mut self) -> Result<Self::Checkpoint, MevBooTeeError> {
+        let state = self.builder.flush_state()
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to flush state: {:?}", err)))?;
+        Ok((self.builder.txs().len(), state))
+    }
+
+    fn revert(&mut self, (pos, state): Self::Checkpoint) {
+        self.builder.truncate_and_revert(pos, state);
+    }
+
+    // execute the txns in order on top of the current state, only the txns in `may_revert` are allowed to revert
+    // if bundle conflicts, it restores state to what it was
+    fn execute(&mut self, txns: &[Transaction], may_revert: &[SH256]) -> Result<BundleExecution, MevBooTeeError> {
+        execute_on(self.builder, self.coinbase, txns, may_revert)
+    }
 }
 
 impl GreedyBlockBuildingStrategy {
     // to be run:
     // 1. after we add new bundles or remove existing bundles to ensure the block is still maximized
     // 2. after an inclusion list is added to ensure the block is still valid
-    fn rebuild(&mut self) {
-        self.block = Vec::new();
-
+    pub(crate) fn rebuild(&mut self) -> Result<(), MevBooTeeError> {
         self.reset_builder();
-        let mut inclusion_list = self.proposer_requested_txns.clone();
-
-        for bundle_id in &self.ordered_bundle_ids.clone() {
-            let bundle = &self.all_bundles[bundle_id];
-            // for each bundle, we execute the txns in the bundle, followed by the txns in the inclusion list (after we removed any common txns)
-            let before_bundle_state = self.builder.flush_state().unwrap(); // if the bundle and inclusion list conflict, we need to return to the state before applying the bundle
-            let before_start_pos = self.builder.txs().len();
-            if self.execute_bundle(&bundle.txns) {
-                if inclusion_list.len() > 0 { // apply the remainder of the inclusion list to ensure the current bundle does not conflict with it
-                    let inclusion_list_backup = inclusion_list.clone();
-                    remove_common_txns(bundle, &mut inclusion_list);
-                    let after_bundle_state = self.builder.flush_state().unwrap(); // if there is no conflict, we want to return to the state before applying the inclusion list
-                    let after_start_pos = self.builder.txs().len();
-                    if self.execute_bundle(&self.inclusion_list) {
-                        // all good, we keep the bundle
-                        self.block.push(bundle_id.clone());
-                        self.builder.truncate_and_revert(after_start_pos, after_bundle_state);
-                    }
-                    else {
-                        // bundle and inclusion list conflict, undo the transactions in the bundle
-                        self.builder.truncate_and_revert(before_start_pos, before_bundle_state);
-                        // restore inclusion_list
-                        inclusion_list = inclusion_list_backup;
-                    }
-                }    
+        self.order.restart();
+        self.rebuild_from(0)
+    }
+
+    // the bundles before `index` in self.order.ordered_bundle_ids are unchanged, only re-execute the ones after
+    fn rebuild_from(&mut self, index: usize) -> Result<(), MevBooTeeError> {
+        let mut executor = BuilderExecutor {
+            builder: &mut self.builder,
+            coinbase: &self.coinbase,
+        };
+        self.order.rebuild_from(&mut executor, index);
+        // at the end, the block is formed from the transactions in self.order.block followed by the transactions in self.order.inclusion_list
+        self.inclusion_list_fees = self.measure_inclusion_list_fees()?;
+        Ok(())
+    }
+
+    fn executor(&mut self) -> BuilderExecutor<'_> {
+        BuilderExecutor {
+            builder: &mut self.builder,
+            coinbase: &self.coinbase,
+        }
+    }
+
+    // execute self.order.inclusion_list on top of the current state, then revert it
+    fn measure_inclusion_list_fees(&mut self) -> Result<SU256, MevBooTeeError> {
+        let state = self.builder.flush_state()
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to flush state: {:?}", err)))?;
+        let start_pos = self.builder.txs().len();
+        let before = balance_of(&mut self.builder, &self.coinbase)?;
+        for txn in &self.order.inclusion_list {
+            if let Err(err) = commit_transaction(&mut self.builder, txn) {
+                glog::warn!("inclusion list txn not counted in the bid: {}", err);
             }
         }
-        self.inclusion_list = inclusion_list;
-        // at the end, the block is formed from the transactions in self.block followed by the transactions in self.inclusion_list
+        let after = balance_of(&mut self.builder, &self.coinbase);
+        // the inclusion list is reverted before a state error goes up
+        self.builder.truncate_and_revert(start_pos, state);
+        let after = after?;
+        Ok(if after > before { after - before } else { SU256::default() })
     }
 
-    // return true if bundle is valid with the current state
-    // if bundle conflicts, it restores state to what it was
-    fn execute_bundle(&self, txns: &Vec<Transaction>) -> bool {
-        todo!()
+    // execute the txns alone on top of the parent state
+    fn simulate(&mut self, txns: &[Transaction], may_revert: &[SH256]) -> Result<BundleExecution, MevBooTeeError> {
+        let execution = execute_on(&mut self.simulator, &self.coinbase, txns, may_revert)?;
+        self.simulator.truncate_and_revert(0, self.simulator_root);
+        Ok(execution)
     }
 
-    fn reset_builder(&mut self) {
-        self.builder.truncate_and_revert(0, self.state_root);
+    // simulate the bundle and keep it when it pays at least its bid, returns false for a known bundle
+    pub(crate) fn admit(&mut self, bundle_id: &String, bundle: WrappedBundle) -> Result<bool, MevBooTeeError> {
+        if self.order.contains(bundle_id) {
+            return Ok(false)
+        }
+        match_sidecars(&bundle.txns, &bundle.blob_sidecars).map_err(|err| MevBooTeeError::Bundle(err.to_string()))?;
+        let execution = self.simulate(&bundle.txns, &bundle.reverting_tx_hashes)?;
+        if execution.coinbase_delta < bundle.value() {
+            return Err(MevBooTeeError::Bundle(format!(
+                "bundle {} pays {:?} to the fee recipient, less than its bid {:?}", bundle_id, execution.coinbase_delta, bundle.value(),
+            )));
+        }
+        self.order.admit(bundle_id, bundle, execution.coinbase_delta);
+        Ok(true)
     }
 
-    fn add_bundle_id(&mut self, bundle_id: &String, bundle_value: u32) {
-        for i in 0..self.ordered_bundle_ids.len() {
-            if self.all_bundles[&self.ordered_bundle_ids[i]].value() < bundle_value {
-                self.ordered_bundle_ids.insert(i, bundle_id.into());
-                return
-            }
+    fn seal(&mut self) -> Result<eth_types::Block, MevBooTeeError> {
+        for txn in &self.order.inclusion_list {
+            commit_transaction(&mut self.builder, txn)?;
         }
-        self.ordered_bundle_ids.push(bundle_id.into())
+        // withdrawals are credited after the transactions, and their root goes into the header
+        let mut block = self.builder.finalize(self.withdrawals.clone())
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to seal block: {:?}", err)))?;
+        set_cancun_fields(&mut block.header, &self.parent, &block.transactions, self.parent_beacon_block_root);
+        Ok(block)
+    }
+
+    pub(crate) fn reset_builder(&mut self) {
+        self.builder.truncate_and_revert(0, self.state_root);
+    }
+}
+
+impl BundleExecutor for GreedyBlockBuildingStrategy {
+    type Checkpoint = (usize, SH256);
+
+    fn checkpoint(&mut self) -> Result<Self::Checkpoint, MevBooTeeError> {
+        self.executor().checkpoint()
+    }
+
+    fn revert(&mut self, checkpoint: Self::Checkpoint) {
+        self.executor().revert(checkpoint)
+    }
+
+    fn execute(&mut self, txns: &[Transaction], may_revert: &[SH256]) -> Result<BundleExecution, MevBooTeeError> {
+        self.executor().execute(txns, may_revert)
     }
 }
 
 impl BlockBuildingStrategy for GreedyBlockBuildingStrategy {
-    fn new(el: Arc<ExecutionClient<Arc<MixRpcClient>>>, block_number: u64) -> Self {
-        let chain_id = el.chain_id().unwrap();
-        let prev_block = el.get_block_header(block_number.into()).unwrap();
-
-        let mut builder = {
-            // use the ethereum engine
-            let engine = Ethereum::new(chain_id.into());
-            let header = engine.new_block_header(
-                &prev_block,
-                ConsensusBlockInfo {
-                    gas_limit: todo!(),
-                    timestamp: todo!(),
-                    random: todo!(),
-                    extra: todo!(),
-                    coinbase: todo!(),
-                },
-            );
-            // a memory database which store the mpt nodes and codes
-            let db = Database::new(100000);
-            // state fetcher, fetch the states on demand.
-            let fetcher = mpt::BlockStateFetcher::new(el.clone(), prev_block.number.into());
-            // world state trie, use prev_block's state_root
-            let trie = mpt::TrieState::new(fetcher, prev_block.state_root, db);
-            let hash_getter = BuilderFetcher::new(el.as_ref().clone());
-            BlockBuilder::new(engine, trie, hash_getter, header).unwrap()
-        };
-        let starting_state = builder.flush_state().unwrap();
-        GreedyBlockBuildingStrategy {
+    fn new(
+        el: ElClient, parent: BlockHeader, info: ConsensusBlockInfo, withdrawals: Option<Vec<Withdrawal>>, parent_beacon_block_root: Option<SH256>,
+    ) -> Result<Self, MevBooTeeError> {
+        let chain_id = el.chain_id()
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to fetch chain id: {:?}", err)))?;
+        let coinbase = info.coinbase;
+        let mut simulator = new_block_builder(&el, chain_id, &parent, info.clone(), parent_beacon_block_root)?;
+        let simulator_root = simulator.flush_state()
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to flush state: {:?}", err)))?;
+        let mut builder = new_block_builder(&el, chain_id, &parent, info, parent_beacon_block_root)?;
+        let starting_state = builder.flush_state()
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to flush state: {:?}", err)))?;
+        Ok(GreedyBlockBuildingStrategy {
             builder,
-            all_bundles: BTreeMap::new(),
-            ordered_bundle_ids: Vec::new(),
-            proposer_requested_txns: Vec::new(),
-            block: Vec::new(),
-            inclusion_list: Vec::new(),
+            simulator,
+            simulator_root,
+            chain_id,
+            block_number: parent.number.as_u64() + 1,
+            parent,
+            order: GreedyOrder::new(),
             state_root: starting_state,
-        }
+            coinbase,
+            inclusion_list_fees: SU256::default(),
+            withdrawals,
+            parent_beacon_block_root,
+        })
     }
 
-    fn add_bundle(&mut self, bundle_id: String, bundle: WrappedBundle) {
-        self.add_bundle_id(&bundle_id, bundle.value());
-        self.rebuild()
+    fn add_bundle(&mut self, bundle_id: String, bundle: WrappedBundle) -> Result<(), MevBooTeeError> {
+        if !self.admit(&bundle_id, bundle)? {
+            return Ok(())
+        }
+        let index = self.order.add_bundle_id(&bundle_id);
+        self.rebuild_from(index)
     }
 
-    fn remove_bundle(&mut self, bundle_id: &String) -> bool {
-        self.all_bundles.remove(bundle_id);
-        for i in 0..self.ordered_bundle_ids.len() {
-            if self.ordered_bundle_ids[i] == bundle_id.to_string() {
-                self.ordered_bundle_ids.remove(i);
-                self.rebuild(); // TODO: we can rebuild from the index of the removed bundle
-                return true
+    fn remove_bundle(&mut self, bundle_id: &String) -> Result<bool, MevBooTeeError> {
+        match self.order.remove_bundle_id(bundle_id) {
+            Some(index) => {
+                self.rebuild_from(index)?;
+                Ok(true)
             }
+            None => Ok(false),
         }
-        return false
     }
 
-    fn add_inclusion_list(&mut self, inclusion_list: Vec<Transaction>) {
-        self.proposer_requested_txns = inclusion_list;
+    fn add_inclusion_list(&mut self, inclusion_list: Vec<Transaction>) -> Result<(), MevBooTeeError> {
+        self.order.proposer_requested_txns = inclusion_list;
         self.rebuild()
     }
 
-    fn get_block(&self) -> eth_types::Block {
-        todo!()
+    fn get_block(&mut self) -> Result<eth_types::Block, MevBooTeeError> {
+        // the builder holds the bundles of self.order.block, complete it with the inclusion list and seal it
+        let state = self.builder.flush_state()
+            .map_err(|err| MevBooTeeError::Execution(format!("unable to flush state: {:?}", err)))?;
+        let start_pos = self.builder.txs().len();
+        let result = self.seal();
+        // back to the bundles only, more of them may come
+        self.builder.truncate_and_revert(start_pos, state);
+        result
+    }
+
+    fn get_block_header(&mut self) -> Result<eth_types::BlockHeader, MevBooTeeError> {
+        Ok(self.get_block()?.header)
+    }
+
+    fn get_blob_sidecars(&self) -> Vec<BlobSidecar> {
+        self.order.block.iter().flat_map(|bundle_id| self.order.all_bundles[bundle_id].blob_sidecars.clone()).collect()
+    }
+
+    fn get_bid(&self) -> SU256 {
+        let mut bid = self.inclusion_list_fees;
+        for bundle_id in &self.order.block {
+            bid = bid + self.order.executions[bundle_id].coinbase_delta;
+        }
+        bid
     }
 
-    fn get_block_header(&self) -> eth_types::BlockHeader {
-        todo!()
+    fn get_payments(&self) -> Vec<(String, SU256)> {
+        self.order.block.iter().map(|bundle_id| (bundle_id.clone(), self.order.executions[bundle_id].coinbase_delta)).collect()
+    }
+
+    fn create_bundle(&mut self, transactions: Vec<Transaction>, reverting_tx_hashes: Vec<SH256>) -> Result<WrappedBundle, MevBooTeeError> {
+        let first = transactions.first().ok_or_else(|| MevBooTeeError::Bundle("the bundle has no transaction".into()))?;
+        let submitter = recover_sender(&Signer::new(self.chain_id.into()), first).map_err(MevBooTeeError::Bundle)?;
+        let execution = self.simulate(&transactions, &reverting_tx_hashes)?;
+        Ok(WrappedBundle {
+            txns: transactions,
+            bid: execution.coinbase_delta,
+            block_number: self.block_number,
+            submitter,
+            timestamp: unix_timestamp(),
+            reverting_tx_hashes,
+            blob_sidecars: Vec::new(),
+        })
+    }
+}
+
+// execute the txns in order on top of the state of `builder`, only the txns in `may_revert` are allowed to revert
+// if bundle conflicts, it restores state to what it was
+fn execute_on(builder: &mut EvmBlockBuilder, coinbase: &SH160, txns: &[Transaction], may_revert: &[SH256]) -> Result<BundleExecution, MevBooTeeError> {
+    let before_state = builder.flush_state()
+        .map_err(|err| MevBooTeeError::Execution(format!("unable to flush state: {:?}", err)))?;
+    let before_pos = builder.txs().len();
+    let before_balance = balance_of(builder, coinbase)?;
+    // start recording with the first txn
+    builder.statedb().take_access();
+
+    let mut execution = BundleExecution::default();
+    for txn in txns {
+        let receipt = match commit_transaction(builder, txn) {
+            Ok(receipt) => receipt,
+            Err(err) => {
+                builder.truncate_and_revert(before_pos, before_state);
+                return Err(err);
+            }
+        };
+        if receipt.status.as_u64() != 1 && !may_revert.contains(&txn.hash) {
+            builder.truncate_and_revert(before_pos, before_state);
+            return Err(MevBooTeeError::Execution(format!("txn {:?} reverted", txn.hash)));
+        }
+        execution.gas_used += receipt.gas_used.as_u64();
+    }
+
+    // what the txns accessed through the state, internal calls included
+    let access = builder.statedb().take_access();
+    execution.reads = access.reads;
+    execution.writes = access.writes;
+    let after_balance = match balance_of(builder, coinbase) {
+        Ok(balance) => balance,
+        Err(err) => {
+            builder.truncate_and_revert(before_pos, before_state);
+            return Err(err);
+        }
+    };
+    // every txn pays the coinbase, it is not a conflict
+    execution.writes.remove(&StateKey::Account(*coinbase));
+    execution.reads.remove(&StateKey::Account(*coinbase));
+    let writes = execution.writes.clone();
+    execution.reads.extend(writes);
+    execution.coinbase_delta = if after_balance > before_balance { after_balance - before_balance } else { SU256::default() };
+    Ok(execution)
+}
+
+// a bundle may pay less once the bundles before it are applied, it then gets ranked by what it actually pays
+// returns the first position of `ordered` which changed
+fn rerank_bundles(
+    ordered: &mut Vec<String>, values: &mut BTreeMap<String, SU256>, executions: &BTreeMap<String, BundleExecution>,
+) -> Option<usize> {
+    let mut dropped = false;
+    for (bundle_id, execution) in executions {
+        if let Some(value) = values.get_mut(bundle_id) {
+            if execution.coinbase_delta < *value {
+                *value = execution.coinbase_delta;
+                dropped = true;
+            }
+        }
+    }
+    if !dropped {
+        return None;
     }
+    let before = ordered.clone();
+    ordered.sort_by(|a, b| values[b].cmp(&values[a]));
+    before.iter().zip(ordered.iter()).position(|(before, after)| before != after)
+}
 
-    fn create_bundle(&self, transactions: Vec<Transaction>) -> Result<WrappedBundle, MevBooTEEError> {
-        todo!()
+// build the block of `bundles` and `inclusion_list` with the strategy `S`
+// returns its bid, its blob sidecars and what each included bundle pays along with it
+pub fn build_block_with<S: BlockBuildingStrategy>(
+    el: ElClient, parent: BlockHeader, info: ConsensusBlockInfo, withdrawals: Option<Vec<Withdrawal>>, parent_beacon_block_root: Option<SH256>,
+    bundles: Vec<(String, WrappedBundle)>, inclusion_list: &[Transaction],
+) -> Result<(SU256, eth_types::Block, Vec<BlobSidecar>, Vec<(String, SU256)>), MevBooTeeError> {
+    let mut strategy = S::new(el, parent, info, withdrawals, parent_beacon_block_root)?;
+    strategy.add_inclusion_list(inclusion_list.to_vec())?;
+    for (bundle_id, bundle) in bundles {
+        if let Err(err) = strategy.add_bundle(bundle_id.clone(), bundle) {
+            glog::info!("bundle {} left out: {}", bundle_id, err);
+        }
     }
+    let block = strategy.get_block()?;
+    Ok((strategy.get_bid(), block, strategy.get_blob_sidecars(), strategy.get_payments()))
 }
 
 fn remove_common_txns(bundle: &WrappedBundle, inclusion_list: &mut Vec<Transaction>) {
@@ -182,50 +634,410 @@ fn remove_common_txns(bundle: &WrappedBundle, inclusion_list: &mut Vec<Transacti
     }
 }
 
-#[derive(Clone)]
-pub struct BuilderFetcher {
-    client: ExecutionClient<Arc<MixRpcClient>>,
-    cache: Arc<Mutex<BTreeMap<u64, SH256>>>,
-}
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
 
-impl BuilderFetcher {
-    pub fn new(client: ExecutionClient<Arc<MixRpcClient>>) -> Self {
-        Self {
-            client,
-            cache: Default::default(),
+    fn txn(n: u8) -> Transaction {
+        let mut txn = Transaction::default();
+        txn.hash = SH256::from([n; 32]);
+        txn
+    }
+
+    #[test]
+    fn test_remove_common_txns() {
+        let bundle = mock_bundle(vec![txn(1), txn(2)], Vec::new());
+        let mut inclusion_list = vec![txn(2), txn(3), txn(1), txn(4)];
+        remove_common_txns(&bundle, &mut inclusion_list);
+        assert_eq!(inclusion_list.iter().map(|txn| txn.hash).collect::<Vec<_>>(), vec![txn(3).hash, txn(4).hash]);
+    }
+
+    const REVERT: &[u8] = &[0xfd];
+
+    // a chain where a txn only executes with the next nonce of its sender, and reverts when its input is REVERT
+    // the value of a txn goes to the coinbase
+    #[derive(Default)]
+    pub(crate) struct MockExecutor {
+        nonces: BTreeMap<SH160, u64>,
+        txns: Vec<SH256>,
+        executed: usize, // txns executed so far, reverted or not
+    }
+
+    impl BundleExecutor for MockExecutor {
+        type Checkpoint = (BTreeMap<SH160, u64>, usize);
+
+        fn checkpoint(&mut self) -> Result<Self::Checkpoint, MevBooTeeError> {
+            Ok((self.nonces.clone(), self.txns.len()))
+        }
+
+        fn revert(&mut self, (nonces, len): Self::Checkpoint) {
+            self.nonces = nonces;
+            self.txns.truncate(len);
+        }
+
+        fn execute(&mut self, txns: &[Transaction], may_revert: &[SH256]) -> Result<BundleExecution, MevBooTeeError> {
+            let checkpoint = self.checkpoint()?;
+            let mut execution = BundleExecution::default();
+            for txn in txns {
+                self.executed += 1;
+                let expected = self.nonces.get(&txn.from).cloned().unwrap_or_default();
+                let reverted = txn.input.as_ref() == REVERT && !may_revert.contains(&txn.hash);
+                if txn.nonce.as_u64() != expected || reverted {
+                    self.revert(checkpoint);
+                    return Err(MevBooTeeError::Execution(format!("txn {:?} failed", txn.hash)));
+                }
+                self.nonces.insert(txn.from, expected + 1);
+                self.txns.push(txn.hash);
+                execution.gas_used += 21000;
+                execution.coinbase_delta = execution.coinbase_delta + txn.value;
+                execution.writes.insert(StateKey::Account(txn.from));
+                execution.writes.extend(txn.to.map(StateKey::Account));
+            }
+            execution.reads = execution.writes.clone();
+            Ok(execution)
         }
     }
-}
 
-impl BlockHashGetter for BuilderFetcher {
-    fn get_hash(&self, current: u64, target: u64) -> SH256 {
-        if target >= current || target < current.saturating_sub(256) {
-            return Default::default();
+    pub(crate) fn mock_txn(sender: u8, nonce: u64, reverts: bool) -> Transaction {
+        let mut txn = Transaction::default();
+        txn.from = SH160::from([sender; 20]);
+        txn.nonce = nonce.into();
+        if reverts {
+            txn.input = REVERT.to_vec().into();
+        }
+        let mut hash = [0_u8; 32];
+        hash[0] = sender;
+        hash[1..9].copy_from_slice(&nonce.to_be_bytes());
+        hash[9] = reverts as u8;
+        txn.hash = SH256::from(hash);
+        txn
+    }
+
+    pub(crate) fn mock_bundle(txns: Vec<Transaction>, reverting_tx_hashes: Vec<SH256>) -> WrappedBundle {
+        WrappedBundle {
+            txns,
+            bid: 1.into(),
+            block_number: 1,
+            submitter: Default::default(),
+            timestamp: 0,
+            reverting_tx_hashes,
+            blob_sidecars: Vec::new(),
+        }
+    }
+
+    fn hashes(txns: &[Transaction]) -> Vec<SH256> {
+        txns.iter().map(|txn| txn.hash).collect()
+    }
+
+    #[test]
+    fn test_bundles_kept_without_inclusion_list() {
+        let bundles = vec![
+            ("a".to_owned(), mock_bundle(vec![mock_txn(1, 0, false)], Vec::new())),
+            ("b".to_owned(), mock_bundle(vec![mock_txn(2, 0, false)], Vec::new())),
+        ];
+        let selection = select_bundles(&mut MockExecutor::default(), &bundles, &[]);
+        assert_eq!(selection.block, vec!["a".to_owned(), "b".to_owned()]);
+        assert!(selection.inclusion_list.is_empty());
+    }
+
+    #[test]
+    fn test_conflicting_bundle_dropped() {
+        let inclusion_list = vec![mock_txn(1, 0, false), mock_txn(1, 1, false)];
+        let bundles = vec![
+            // takes the nonce of the first proposer txn
+            ("conflict".to_owned(), mock_bundle(vec![mock_txn(1, 0, true)], vec![mock_txn(1, 0, true).hash])),
+            // includes the first proposer txn itself
+            ("overlap".to_owned(), mock_bundle(vec![mock_txn(1, 0, false), mock_txn(2, 0, false)], Vec::new())),
+            ("reverts".to_owned(), mock_bundle(vec![mock_txn(3, 0, true)], Vec::new())),
+        ];
+        let mut executor = MockExecutor::default();
+        let selection = select_bundles(&mut executor, &bundles, &inclusion_list);
+        assert_eq!(selection.block, vec!["overlap".to_owned()]);
+        assert_eq!(hashes(&selection.inclusion_list), vec![mock_txn(1, 1, false).hash]);
+        // only the selected bundle is left applied
+        assert_eq!(executor.txns, hashes(&bundles[1].1.txns));
+    }
+
+    #[test]
+    fn test_reverting_inclusion_list() {
+        // the proposer txn reverts whatever comes before it, no bundle conflicts with it
+        let inclusion_list = vec![mock_txn(1, 0, true)];
+        let bundles = vec![
+            ("a".to_owned(), mock_bundle(vec![mock_txn(2, 0, false)], Vec::new())),
+            ("conflict".to_owned(), mock_bundle(vec![mock_txn(1, 0, false)], Vec::new())),
+        ];
+        let selection = select_bundles(&mut MockExecutor::default(), &bundles, &inclusion_list);
+        assert_eq!(selection.block, vec!["a".to_owned()]);
+        assert_eq!(hashes(&selection.inclusion_list), hashes(&inclusion_list));
+    }
+
+    #[test]
+    fn test_slot_level_conflicts() {
+        let pool = SH160::from([9; 20]);
+        let touching = |reads: Vec<StateKey>, writes: Vec<StateKey>| BundleExecution {
+            reads: reads.into_iter().chain(writes.iter().cloned()).collect(),
+            writes: writes.into_iter().collect(),
+            ..Default::default()
+        };
+        let slot = |n: u8| StateKey::Slot(pool, SH256::from([n; 32]));
+        // two bundles calling into the same contract only conflict when they share a storage slot
+        let a = touching(vec![StateKey::Account(pool)], vec![slot(1)]);
+        let b = touching(vec![StateKey::Account(pool)], vec![slot(2)]);
+        let c = touching(vec![slot(1)], Vec::new());
+        assert!(!a.conflicts_with(&b));
+        assert!(a.conflicts_with(&c) && c.conflicts_with(&a));
+        assert!(!b.conflicts_with(&c));
+        // a balance change of the contract conflicts with whoever reads the account
+        let d = touching(Vec::new(), vec![StateKey::Account(pool)]);
+        assert!(d.conflicts_with(&a) && !d.conflicts_with(&c));
+    }
+
+    #[test]
+    fn test_rerank_bundles() {
+        let paid = |value: u64| BundleExecution { coinbase_delta: value.into(), ..Default::default() };
+        let mut ordered: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+        let mut values: BTreeMap<String, SU256> = ordered.iter().cloned().zip(vec![5.into(), 4.into(), 3.into()]).collect();
+        // b pays less once a is applied
+        let executions: BTreeMap<String, BundleExecution> = vec![("a".into(), paid(5)), ("b".into(), paid(1)), ("c".into(), paid(3))]
+            .into_iter()
+            .collect();
+        assert_eq!(rerank_bundles(&mut ordered, &mut values, &executions), Some(1));
+        assert_eq!(ordered, vec!["a", "c", "b"]);
+        assert_eq!(values["b"], 1.into());
+        assert_eq!(rerank_bundles(&mut ordered, &mut values, &executions), None);
+    }
+
+    #[test]
+    fn test_blob_gas_limit() {
+        let blob_txn = |sender: u8| {
+            let mut txn = mock_txn(sender, 0, false);
+            txn.blob_versioned_hashes = Some(vec![SH256::default(); 2]);
+            txn
+        };
+        let bundles: Vec<(String, WrappedBundle)> = (1..=4)
+            .map(|sender| (format!("blobs-{}", sender), mock_bundle(vec![blob_txn(sender)], Vec::new())))
+            .chain(std::iter::once(("plain".to_owned(), mock_bundle(vec![mock_txn(5, 0, false)], Vec::new()))))
+            .collect();
+        // room for 6 blobs, one of them taken by the inclusion list
+        let mut inclusion_list = vec![blob_txn(6)];
+        inclusion_list[0].blob_versioned_hashes = Some(vec![SH256::default()]);
+        let selection = select_bundles(&mut MockExecutor::default(), &bundles, &inclusion_list);
+        assert_eq!(selection.block, vec!["blobs-1", "blobs-2", "plain"]);
+    }
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            // xorshift64
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
         }
-        {
-            let cache = self.cache.lock().unwrap();
-            if let Some(hash) = cache.get(&target) {
-                return *hash;
+    }
+
+    fn random_case(rng: &mut Rng) -> (Vec<(String, WrappedBundle)>, Vec<Transaction>) {
+        let mut inclusion_list = Vec::new();
+        for sender in 0..2 {
+            for nonce in 0..rng.below(3) {
+                inclusion_list.push(mock_txn(sender, nonce, false));
             }
         }
-        match self.client.get_block_header(target.into()) {
-            Ok(header) => {
-                let hash = header.hash();
-                let mut cache = self.cache.lock().unwrap();
-                cache.insert(target, hash);
-                hash
+        let mut bundles = Vec::new();
+        for i in 0..rng.below(7) {
+            let mut txns = Vec::new();
+            let mut reverting_tx_hashes = Vec::new();
+            for _ in 0..1 + rng.below(3) {
+                let txn = if !inclusion_list.is_empty() && rng.below(4) == 0 {
+                    inclusion_list[rng.below(inclusion_list.len() as u64) as usize].clone()
+                } else {
+                    mock_txn(rng.below(4) as u8, rng.below(3), rng.below(6) == 0)
+                };
+                if rng.below(2) == 0 {
+                    reverting_tx_hashes.push(txn.hash);
+                }
+                txns.push(txn);
             }
-            Err(err) => Default::default(),
+            bundles.push((format!("bundle-{}", i), mock_bundle(txns, reverting_tx_hashes)));
         }
+        (bundles, inclusion_list)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_selection_properties() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for case in 0..2000 {
+            let (bundles, inclusion_list) = random_case(&mut rng);
+            let mut executor = MockExecutor::default();
+            let selection = select_bundles(&mut executor, &bundles, &inclusion_list);
+            let selected: Vec<&WrappedBundle> = selection.block.iter()
+                .map(|id| &bundles.iter().find(|(bundle_id, _)| bundle_id == id).unwrap().1)
+                .collect();
+
+            // the block executes from scratch: no selected bundle conflicts with the bundles before it or with the inclusion list
+            let mut replay = MockExecutor::default();
+            for bundle in &selected {
+                assert!(replay.execute(&bundle.txns, &bundle.reverting_tx_hashes).is_ok(), "case {}", case);
+            }
+            assert_eq!(replay.txns, executor.txns, "case {}", case);
+            assert!(replay.execute(&selection.inclusion_list, &hashes(&selection.inclusion_list)).is_ok(), "case {}", case);
+
+            // every proposer txn is in the block
+            for txn in &inclusion_list {
+                let in_bundle = selected.iter().any(|bundle| bundle.contains_transaction(txn));
+                let in_tail = selection.inclusion_list.iter().any(|tail| tail.hash == txn.hash);
+                assert!(in_bundle || in_tail, "case {}", case);
+            }
 
+            // the tail keeps the proposer order
+            let mut remaining = hashes(&inclusion_list).into_iter();
+            for txn in &selection.inclusion_list {
+                assert!(remaining.any(|hash| hash == txn.hash), "case {}", case);
+            }
+        }
+    }
+
+    type MockOrder = GreedyOrder<(BTreeMap<SH160, u64>, usize)>;
+
+    // the bundle of `txns` paying `value`, its txns pay it to the coinbase
+    fn paying_bundle(txns: Vec<Transaction>, reverting_tx_hashes: Vec<SH256>, value: u64) -> WrappedBundle {
+        let mut bundle = mock_bundle(txns, reverting_tx_hashes);
+        if let Some(txn) = bundle.txns.last_mut() {
+            txn.value = value.into();
+        }
+        bundle.bid = value.into();
+        bundle
+    }
+
+    fn ordered_bundles(order: &MockOrder) -> Vec<(String, WrappedBundle)> {
+        order.ordered_bundle_ids.iter().map(|bundle_id| (bundle_id.clone(), order.all_bundles[bundle_id].clone())).collect()
+    }
+
+    #[test]
+    fn test_incremental_matches_full_rebuild() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for case in 0..500 {
+            let (bundles, inclusion_list) = random_case(&mut rng);
+            let mut executor = MockExecutor::default();
+            let mut order = MockOrder::new();
+            order.proposer_requested_txns = inclusion_list.clone();
+            order.restart();
+            for (bundle_id, bundle) in bundles {
+                // a bundle may pay less in the block than alone, the order then gets reranked
+                let value = 1 + rng.below(4);
+                let bundle = paying_bundle(bundle.txns, bundle.reverting_tx_hashes, rng.below(value + 1));
+                order.admit(&bundle_id, bundle, value.into());
+                let index = order.add_bundle_id(&bundle_id);
+                order.rebuild_from(&mut executor, index);
+                if !order.ordered_bundle_ids.is_empty() && rng.below(3) == 0 {
+                    let bundle_id = order.ordered_bundle_ids[rng.below(order.ordered_bundle_ids.len() as u64) as usize].clone();
+                    let index = order.remove_bundle_id(&bundle_id).unwrap();
+                    order.rebuild_from(&mut executor, index);
+                }
+
+                let mut replay = MockExecutor::default();
+                let full = select_bundles(&mut replay, &ordered_bundles(&order), &inclusion_list);
+                assert_eq!(order.block, full.block, "case {}", case);
+                assert_eq!(hashes(&order.inclusion_list), hashes(&full.inclusion_list), "case {}", case);
+                assert_eq!(order.executions, full.executions, "case {}", case);
+                assert_eq!(executor.txns, replay.txns, "case {}", case);
+            }
+        }
+    }
+
+    // compares the txns executed by the mock chain, not the cost of an evm execution
+    // with bundles landing anywhere in the order, the incremental rebuild is expected to re-execute about half of the txns
+    // cargo test bench_incremental_rebuild -- --ignored
     #[test]
-    fn test_add_bundle() {
-        let builder = GreedyBlockBuildingStrategy::new();
+    #[ignore]
+    fn bench_incremental_rebuild() {
+        const BUNDLES: u64 = 300;
+        let inclusion_list: Vec<Transaction> = (0..20).map(|nonce| mock_txn(0, nonce, false)).collect();
+        let mut rng = Rng(0x853c49e6748fea9b);
+        // distinct values, each bundle lands at a uniform position among the ones already in
+        let bundles: Vec<(String, WrappedBundle, u64)> = (0..BUNDLES)
+            .map(|i| (format!("bundle-{}", i), mock_bundle(vec![mock_txn(1 + (i % 200) as u8, 0, false)], Vec::new()), rng.below(1 << 40) * BUNDLES + i + 1))
+            .collect();
+
+        let mut executor = MockExecutor::default();
+        let mut order = MockOrder::new();
+        // the mock bundles pay nothing, they are kept in the order of their values
+        order.rerank = false;
+        order.proposer_requested_txns = inclusion_list.clone();
+        order.restart();
+        let mut full_executed = 0;
+        for (bundle_id, bundle, value) in &bundles {
+            order.admit(bundle_id, bundle.clone(), (*value).into());
+            let index = order.add_bundle_id(bundle_id);
+            order.rebuild_from(&mut executor, index);
+
+            let mut replay = MockExecutor::default();
+            select_bundles(&mut replay, &ordered_bundles(&order), &inclusion_list);
+            full_executed += replay.executed;
+        }
+        assert!(executor.executed < full_executed, "incremental rebuild {} txns, full rebuild {} txns", executor.executed, full_executed);
+    }
+
+    // times both rebuild paths of the greedy strategy on the EVM executor, on top of the head of an execution client
+    // every raw transaction of the JSON array in MEV_BOOTEE_BENCH_TXNS becomes a bundle, they must be valid on the head
+    // MEV_BOOTEE_BENCH_EL=http://localhost:8545 MEV_BOOTEE_BENCH_TXNS=txns.json cargo test bench_evm_rebuild -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_evm_rebuild() {
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        use base::trace::Alive;
+        use eth_tools::{ExecutionClient, MixRpcClient};
+
+        use crate::decode_transaction;
+
+        let endpoint = std::env::var("MEV_BOOTEE_BENCH_EL").expect("MEV_BOOTEE_BENCH_EL is not set");
+        let txns_file = std::env::var("MEV_BOOTEE_BENCH_TXNS").expect("MEV_BOOTEE_BENCH_TXNS is not set");
+        let raw: Vec<String> = serde_json::from_str(&std::fs::read_to_string(&txns_file).unwrap()).unwrap();
+        let txns: Vec<Transaction> = raw.iter().map(|txn| decode_transaction(txn).unwrap()).collect();
+
+        let mut client = MixRpcClient::new(None);
+        client.add_endpoint(&Alive::new(), &[endpoint]).unwrap();
+        let el: ElClient = Arc::new(ExecutionClient::new(Arc::new(client)));
+        let parent = el.get_block_header(el.head().unwrap().into()).unwrap();
+        let info = ConsensusBlockInfo {
+            gas_limit: parent.gas_limit.as_u64(),
+            timestamp: parent.timestamp.as_u64() + 12,
+            random: parent.mix_hash,
+            extra: Vec::new().into(),
+            coinbase: parent.miner,
+        };
+        let withdrawals = parent.withdrawals_root.map(|_| Vec::new());
+        let beacon_root = parent.excess_blob_gas.map(|_| SH256::default());
+        let mut strategy = GreedyBlockBuildingStrategy::new(el, parent, info, withdrawals, beacon_root).unwrap();
+
+        // each bundle goes through the incremental rebuild the strategy uses, then through a full one,
+        // which ends in the same state, so the next bundle lands on the same order either way
+        let (mut incremental, mut full) = (Duration::default(), Duration::default());
+        let mut bundles = 0;
+        for txn in txns {
+            let bundle_id = format!("{:?}", txn.hash);
+            let bundle = mock_bundle(vec![txn], Vec::new());
+            match strategy.admit(&bundle_id, WrappedBundle { bid: SU256::default(), ..bundle }) {
+                Ok(true) => bundles += 1,
+                _ => continue,
+            }
+            let index = strategy.order.add_bundle_id(&bundle_id);
+            let start = Instant::now();
+            strategy.rebuild_from(index).unwrap();
+            incremental += start.elapsed();
+
+            let start = Instant::now();
+            strategy.rebuild().unwrap();
+            full += start.elapsed();
+        }
+        println!(
+            "{} bundles, {} selected: incremental rebuild {:?}, full rebuild {:?}, full / incremental {:.2}",
+            bundles, strategy.order.block.len(), incremental, full, full.as_secs_f64() / incremental.as_secs_f64().max(f64::EPSILON),
+        );
+        assert!(bundles > 0, "no transaction of {} executes on the head", txns_file);
+        assert!(incremental < full);
     }
 }
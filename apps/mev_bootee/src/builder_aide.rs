@@ -0,0 +1,338 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeMap;
+
+use eth_types::{Block, BlockHeader, SH160, SH256, SU256};
+use evm_executor::ConsensusBlockInfo;
+use serde::{Deserialize, Serialize};
+
+use crate::{balance_of, commit_transaction, new_block_builder, ChainHead, ElClient, MevBooTeeError};
+use crate::{match_sidecars, set_cancun_fields, BlobSidecar, KzgSettings, MAX_BLOB_GAS_PER_BLOCK};
+
+// integrated builder-relayer: an external builder streams full candidate blocks, the enclave
+// re-executes them and plays the relay, handing the best header to the proposer.
+
+#[derive(Clone, Deserialize)]
+pub struct SubmitBlockRequest {
+    pub block: Block,
+    pub builder: SH160,
+    pub fee_recipient: SH160, // the proposer's fee recipient the block pays
+    pub value: SU256,         // payment claimed by the builder
+    #[serde(default)]
+    pub blob_sidecars: Vec<BlobSidecar>, // one per blob transaction of the block
+}
+
+impl SubmitBlockRequest {
+    // every blob transaction of the block comes with its sidecar, blobs are only accepted with a trusted setup
+    pub fn verify_blobs(&self, kzg: Option<&KzgSettings>) -> Result<(), MevBooTeeError> {
+        match_sidecars(&self.block.transactions, &self.blob_sidecars)
+            .map_err(|err| MevBooTeeError::BuilderAide(err.to_string()))?;
+        if self.blob_sidecars.is_empty() {
+            return Ok(());
+        }
+        let kzg = kzg.ok_or_else(|| MevBooTeeError::BuilderAide("blob transactions are not accepted".into()))?;
+        for sidecar in &self.blob_sidecars {
+            kzg.verify_sidecar(sidecar)?;
+        }
+        Ok(())
+    }
+}
+
+// what the enclave observed when re-executing a candidate block
+#[derive(Clone, Debug, Serialize)]
+pub struct BlockAttestation {
+    pub block_number: u64,
+    pub block_hash: SH256,
+    pub builder: SH160,
+    pub fee_recipient: SH160,
+    pub value: SU256, // measured balance increase of the fee recipient
+    pub gas_used: u64,
+}
+
+// candidates kept per slot, once full a candidate has to outbid the lowest one to get in
+pub const MAX_CANDIDATES_PER_SLOT: usize = 64;
+
+pub struct BuilderAideSlot {
+    pub candidates: BTreeMap<SH256, (Block, BlockAttestation)>,
+    pub best: Option<SH256>,
+    pub delivered: Option<SH256>, // set once the header went to the proposer, no more candidates are taken afterwards
+}
+
+#[derive(Default)]
+pub struct BuilderAide {
+    slots: BTreeMap<u64, BuilderAideSlot>,
+}
+
+impl BuilderAide {
+    // store a candidate block that passed `verify_candidate`
+    pub fn accept(&mut self, block: Block, attestation: BlockAttestation) -> Result<(), MevBooTeeError> {
+        let slot = self.slots.entry(attestation.block_number).or_insert_with(|| BuilderAideSlot {
+            candidates: BTreeMap::new(),
+            best: None,
+            delivered: None,
+        });
+        if slot.delivered.is_some() {
+            return Err(MevBooTeeError::BuilderAide(format!("auction for block {} is closed", attestation.block_number)));
+        }
+
+        if slot.candidates.len() >= MAX_CANDIDATES_PER_SLOT && !slot.candidates.contains_key(&attestation.block_hash) {
+            let (lowest, value) = slot.candidates.iter()
+                .min_by(|a, b| (a.1).1.value.cmp(&(b.1).1.value))
+                .map(|(hash, (_, lowest))| (*hash, lowest.value))
+                .unwrap();
+            if value >= attestation.value {
+                return Err(MevBooTeeError::BuilderAide(format!(
+                    "too many candidates for block {}, bid more than {:?}", attestation.block_number, value,
+                )));
+            }
+            slot.candidates.remove(&lowest);
+        }
+
+        let better = match &slot.best {
+            Some(best) => slot.candidates[best].1.value < attestation.value,
+            None => true,
+        };
+        if better {
+            slot.best = Some(attestation.block_hash);
+        }
+        slot.candidates.insert(attestation.block_hash, (block, attestation));
+        Ok(())
+    }
+
+    pub fn best_bid(&self, block_number: u64) -> Option<BlockAttestation> {
+        let slot = self.slots.get(&block_number)?;
+        slot.best.map(|best| slot.candidates[&best].1.clone())
+    }
+
+    // hand the header of the best candidate to the proposer, this seals the slot
+    pub fn deliver_best(&mut self, block_number: u64) -> Result<(SU256, BlockHeader), MevBooTeeError> {
+        let slot = self.slots.get_mut(&block_number)
+            .ok_or_else(|| MevBooTeeError::BuilderAide(format!("no candidate for block {}", block_number)))?;
+        let hash = match (slot.delivered, slot.best) {
+            (Some(delivered), _) => delivered,
+            (None, Some(best)) => best,
+            (None, None) => return Err(MevBooTeeError::BuilderAide(format!("no candidate for block {}", block_number))),
+        };
+        slot.delivered = Some(hash);
+        let (block, attestation) = &slot.candidates[&hash];
+        Ok((attestation.value, block.header.clone()))
+    }
+
+    // the full block behind a header that has been delivered to the proposer
+    pub fn delivered_block(&self, header: &BlockHeader) -> Result<Block, MevBooTeeError> {
+        let hash = header.hash();
+        match self.slots.get(&header.number.as_u64()) {
+            Some(slot) if slot.delivered == Some(hash) => Ok(slot.candidates[&hash].0.clone()),
+            _ => Err(MevBooTeeError::BuilderAide("Unknown header".into())),
+        }
+    }
+
+    // drop every slot before `block_number`
+    pub fn prune(&mut self, block_number: u64) {
+        self.slots = self.slots.split_off(&block_number);
+    }
+
+    // after a reorg only the candidates built on the new head are kept for the open slot
+    pub fn rebase(&mut self, head: &ChainHead) {
+        let slot = match self.slots.get_mut(&head.open_slot()) {
+            Some(slot) => slot,
+            None => return,
+        };
+        slot.candidates.retain(|_, (block, _)| head.is_candidate(block));
+        if slot.delivered.map(|delivered| !slot.candidates.contains_key(&delivered)).unwrap_or(false) {
+            slot.delivered = None;
+        }
+        slot.best = slot.candidates.iter()
+            .max_by(|a, b| (a.1).1.value.cmp(&(b.1).1.value))
+            .map(|(hash, _)| *hash);
+    }
+}
+
+// the block pays the registered fee recipient, either as its coinbase or through a transfer
+// from the builder, which then has to be the coinbase
+fn check_fee_recipient(fee_recipient: SH160, req: &SubmitBlockRequest) -> Result<(), MevBooTeeError> {
+    if req.fee_recipient != fee_recipient {
+        return Err(MevBooTeeError::BuilderAide(format!("fee recipient {:?} is not the registered {:?}", req.fee_recipient, fee_recipient)));
+    }
+    let coinbase = req.block.header.miner;
+    if coinbase != fee_recipient && coinbase != req.builder {
+        return Err(MevBooTeeError::BuilderAide(format!("coinbase {:?} is neither the fee recipient nor the builder", coinbase)));
+    }
+    Ok(())
+}
+
+// from Cancun on the candidate commits to the parent beacon block root, `expected` when the payload attributes gave one
+fn check_beacon_root(parent: &BlockHeader, header: &BlockHeader, expected: Option<SH256>) -> Result<(), MevBooTeeError> {
+    match (header.parent_beacon_block_root, expected) {
+        (Some(root), Some(expected)) if root != expected => Err(MevBooTeeError::BuilderAide(format!(
+            "parent beacon block root {:?}, expected {:?}", root, expected,
+        ))),
+        (None, _) if parent.excess_blob_gas.is_some() || expected.is_some() => {
+            Err(MevBooTeeError::BuilderAide("missing parent beacon block root".into()))
+        },
+        (Some(_), None) if parent.excess_blob_gas.is_none() => {
+            Err(MevBooTeeError::BuilderAide("parent beacon block root before Cancun".into()))
+        },
+        _ => Ok(()),
+    }
+}
+
+// re-execute the candidate block on top of its parent and measure what it pays to `fee_recipient`,
+// the one the proposer registered. the rebuilt block must hash to the same header as the candidate
+pub fn verify_candidate(
+    el: &ElClient, chain_id: u64, fee_recipient: SH160, parent_beacon_block_root: Option<SH256>, req: &SubmitBlockRequest,
+) -> Result<BlockAttestation, MevBooTeeError> {
+    let header = &req.block.header;
+    let block_number = header.number.as_u64();
+    check_fee_recipient(fee_recipient, req)?;
+    let parent_number = block_number.checked_sub(1)
+        .ok_or_else(|| MevBooTeeError::BuilderAide("invalid block number".into()))?;
+    let parent = el.get_block_header(parent_number.into())
+        .map_err(|err| MevBooTeeError::Execution(format!("unable to fetch parent of block {}: {:?}", block_number, err)))?;
+    if parent.hash() != header.parent_hash {
+        return Err(MevBooTeeError::BuilderAide(format!("block {} is not built on the canonical parent", block_number)));
+    }
+    check_beacon_root(&parent, header, parent_beacon_block_root)?;
+
+    let mut builder = new_block_builder(el, chain_id, &parent, ConsensusBlockInfo {
+        gas_limit: header.gas_limit.as_u64(),
+        timestamp: header.timestamp.as_u64(),
+        random: header.mix_hash,
+        extra: header.extra_data.clone(),
+        coinbase: header.miner,
+    }, header.parent_beacon_block_root)?;
+
+    let balance_before = balance_of(&mut builder, &req.fee_recipient)?;
+    for (idx, txn) in req.block.transactions.iter().enumerate() {
+        commit_transaction(&mut builder, txn)
+            .map_err(|err| MevBooTeeError::BuilderAide(format!("txn {} is invalid: {}", idx, err)))?;
+    }
+    let balance_after = balance_of(&mut builder, &req.fee_recipient)?;
+
+    let mut rebuilt = builder.finalize(req.block.withdrawals.clone())
+        .map_err(|err| MevBooTeeError::Execution(format!("unable to seal block {}: {:?}", block_number, err)))?;
+    set_cancun_fields(&mut rebuilt.header, &parent, &rebuilt.transactions, header.parent_beacon_block_root);
+    if rebuilt.header.blob_gas_used.map(|gas| gas.as_u64() > MAX_BLOB_GAS_PER_BLOCK).unwrap_or(false) {
+        return Err(MevBooTeeError::BuilderAide(format!("block {} uses too much blob gas", block_number)));
+    }
+    if rebuilt.header.hash() != header.hash() {
+        return Err(MevBooTeeError::BuilderAide(format!(
+            "header mismatch: state_root {:?} != {:?}, receipts_root {:?} != {:?}",
+            rebuilt.header.state_root, header.state_root, rebuilt.header.receipts_root, header.receipts_root,
+        )));
+    }
+
+    let value = if balance_after > balance_before { balance_after - balance_before } else { SU256::zero() };
+    if value < req.value {
+        return Err(MevBooTeeError::BuilderAide(format!("block pays {:?}, claimed {:?}", value, req.value)));
+    }
+
+    Ok(BlockAttestation {
+        block_number,
+        block_hash: header.hash(),
+        builder: req.builder,
+        fee_recipient: req.fee_recipient,
+        value,
+        gas_used: rebuilt.header.gas_used.as_u64(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(block_number: u64, value: u64, salt: u8) -> (Block, BlockAttestation) {
+        let mut block = Block::default();
+        block.header.number = block_number.into();
+        block.header.extra_data = vec![salt].into();
+        let attestation = BlockAttestation {
+            block_number,
+            block_hash: block.header.hash(),
+            builder: SH160::default(),
+            fee_recipient: SH160::default(),
+            value: value.into(),
+            gas_used: 0,
+        };
+        (block, attestation)
+    }
+
+    fn submit(req_fee_recipient: SH160, builder: SH160, coinbase: SH160) -> SubmitBlockRequest {
+        let mut block = Block::default();
+        block.header.miner = coinbase;
+        SubmitBlockRequest { block, builder, fee_recipient: req_fee_recipient, value: SU256::zero(), blob_sidecars: Vec::new() }
+    }
+
+    #[test]
+    fn test_best_bid() {
+        let mut aide = BuilderAide::default();
+        assert!(aide.best_bid(10).is_none());
+        for (value, salt) in [(5, 1), (9, 2), (7, 3)] {
+            let (block, attestation) = candidate(10, value, salt);
+            aide.accept(block, attestation).unwrap();
+        }
+        assert_eq!(aide.best_bid(10).unwrap().value, 9.into());
+
+        let (value, header) = aide.deliver_best(10).unwrap();
+        assert_eq!(value, 9.into());
+        assert!(aide.delivered_block(&header).is_ok());
+        // the auction is closed once the header went to the proposer
+        let (block, attestation) = candidate(10, 100, 4);
+        assert!(aide.accept(block, attestation).is_err());
+        assert_eq!(aide.deliver_best(10).unwrap().0, 9.into());
+    }
+
+    #[test]
+    fn test_candidate_cap() {
+        let mut aide = BuilderAide::default();
+        for salt in 0..MAX_CANDIDATES_PER_SLOT as u8 {
+            let (block, attestation) = candidate(10, salt as u64 + 10, salt);
+            aide.accept(block, attestation).unwrap();
+        }
+        let (block, attestation) = candidate(10, 10, 200);
+        assert!(aide.accept(block, attestation).is_err());
+        let (block, attestation) = candidate(10, 11, 201);
+        aide.accept(block, attestation).unwrap();
+        let slot = &aide.slots[&10];
+        assert_eq!(slot.candidates.len(), MAX_CANDIDATES_PER_SLOT);
+        assert!(slot.candidates.values().all(|(_, attestation)| attestation.value >= 11.into()));
+        assert_eq!(aide.best_bid(10).unwrap().value, (MAX_CANDIDATES_PER_SLOT as u64 + 9).into());
+    }
+
+    #[test]
+    fn test_check_fee_recipient() {
+        let (proposer, builder, other) = (SH160::from([1; 20]), SH160::from([2; 20]), SH160::from([3; 20]));
+        assert!(check_fee_recipient(proposer, &submit(proposer, builder, proposer)).is_ok());
+        assert!(check_fee_recipient(proposer, &submit(proposer, builder, builder)).is_ok());
+        assert!(check_fee_recipient(proposer, &submit(builder, builder, builder)).is_err());
+        assert!(check_fee_recipient(proposer, &submit(proposer, builder, other)).is_err());
+    }
+
+    #[test]
+    fn test_check_beacon_root() {
+        let (root, other) = (SH256::from([1; 32]), SH256::from([2; 32]));
+        let shanghai = BlockHeader::default();
+        let mut cancun = BlockHeader::default();
+        cancun.excess_blob_gas = Some(0.into());
+        let mut header = BlockHeader::default();
+
+        assert!(check_beacon_root(&shanghai, &header, None).is_ok());
+        assert!(check_beacon_root(&cancun, &header, None).is_err());
+        header.parent_beacon_block_root = Some(root);
+        assert!(check_beacon_root(&shanghai, &header, None).is_err());
+        assert!(check_beacon_root(&cancun, &header, None).is_ok());
+        assert!(check_beacon_root(&cancun, &header, Some(root)).is_ok());
+        assert!(check_beacon_root(&cancun, &header, Some(other)).is_err());
+    }
+
+    #[test]
+    fn test_blob_sidecars() {
+        let mut req = submit(SH160::default(), SH160::default(), SH160::default());
+        assert!(req.verify_blobs(None).is_ok());
+        // a blob transaction without its sidecar
+        let mut txn = eth_types::Transaction::default();
+        txn.r#type = 3.into();
+        txn.blob_versioned_hashes = Some(vec![SH256::default()]);
+        req.block.transactions.push(txn);
+        assert!(req.verify_blobs(None).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::prelude::v1::*;
+
+use eth_types::{Transaction, SH160, SH256, SU256};
+
+use crate::BlobSidecar;
+
+// a ToB bundle as the bundle pool and the block building strategies see it
+#[derive(Clone, Debug)]
+pub struct WrappedBundle {
+    pub txns: Vec<Transaction>,
+    pub bid: SU256,
+    pub block_number: u64,
+    pub submitter: SH160,
+    pub timestamp: u64, // unix time at which the enclave received the bundle
+    pub reverting_tx_hashes: Vec<SH256>, // transactions allowed to revert without invalidating the bundle
+    pub blob_sidecars: Vec<BlobSidecar>, // the blobs of its type 3 transactions
+}
+
+impl WrappedBundle {
+    // the ToB id: keccak(block_number || bid || submitter || hash of every transaction), hex encoded
+    // submitters can compute it on their own, and submitting the same bundle twice yields the same id
+    pub fn id(&self) -> String {
+        let mut data = self.block_number.to_be_bytes().to_vec();
+        let mut bid = [0_u8; 32];
+        self.bid.to_big_endian(&mut bid);
+        data.extend_from_slice(&bid);
+        data.extend_from_slice(self.submitter.as_bytes());
+        for txn in &self.txns {
+            data.extend_from_slice(txn.hash.as_bytes());
+        }
+        to_hex(&crypto::keccak_hash(&data))
+    }
+
+    // what the submitter declares the bundle is worth, the bundle has to pay at least that much to the fee recipient
+    pub fn value(&self) -> SU256 {
+        self.bid
+    }
+
+    pub fn contains_transaction(&self, txn: &Transaction) -> bool {
+        self.txns.iter().any(|included| included.hash == txn.hash)
+    }
+
+    pub fn may_revert(&self, txn: &Transaction) -> bool {
+        self.reverting_tx_hashes.contains(&txn.hash)
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    let mut out = String::with_capacity(2 + data.len() * 2);
+    out.push_str("0x");
+    for b in data {
+        out.push_str(&format!("{:02x}", b));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::block_building::tests::mock_bundle;
+
+    fn bundle(bid: u64) -> WrappedBundle {
+        let mut txn = Transaction::default();
+        txn.hash = SH256::from([1; 32]);
+        WrappedBundle { bid: bid.into(), block_number: 10, submitter: SH160::from([2; 20]), ..mock_bundle(vec![txn], Vec::new()) }
+    }
+
+    #[test]
+    fn test_id() {
+        let id = bundle(5).id();
+        assert_eq!(id.len(), 66);
+        assert!(id.starts_with("0x"));
+        assert!(id[2..].chars().all(|c| c.is_ascii_hexdigit()));
+
+        let mut resubmitted = bundle(5);
+        resubmitted.timestamp = 100;
+        assert_eq!(resubmitted.id(), id);
+        assert_ne!(bundle(6).id(), id);
+    }
+}
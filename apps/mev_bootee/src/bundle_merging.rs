@@ -0,0 +1,366 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeMap;
+
+use eth_types::{Block, BlockHeader, Transaction, Withdrawal, SH256, SU256};
+use evm_executor::ConsensusBlockInfo;
+use serde::Deserialize;
+
+use crate::{BlobSidecar, BlockBuildingStrategy, BundleExecution, BundleExecutor, ElClient, GreedyBlockBuildingStrategy, MevBooTeeError, WrappedBundle};
+
+// conflicting groups up to this size are simulated in every order, larger ones go through a search by pairwise swaps
+const MAX_PERMUTED_BUNDLES: usize = 4;
+// how many times the search goes through every pair of a large group
+const MAX_SWAP_ROUNDS: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StrategyKind {
+    Greedy,  // by simulated coinbase payment, include or skip
+    Merging, // by simulated coinbase payment, reordering conflicting bundles
+}
+
+impl std::str::FromStr for StrategyKind {
+    type Err = MevBooTeeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "greedy" => Ok(StrategyKind::Greedy),
+            "merging" => Ok(StrategyKind::Merging),
+            _ => Err(MevBooTeeError::Config(format!("unknown strategy {:?}, expected one of greedy, merging", s))),
+        }
+    }
+}
+
+// bundles are simulated alone to record the accounts and storage slots they touch
+// bundles which do not conflict are merged as they are, each group of conflicting bundles is simulated in alternate
+// orders and the order paying the coinbase the most wins
+// the final order then goes through the greedy selection, which keeps the inclusion list valid
+pub struct MergingBlockBuildingStrategy {
+    greedy: GreedyBlockBuildingStrategy,
+}
+
+impl MergingBlockBuildingStrategy {
+    fn reorder(&mut self) -> Result<(), MevBooTeeError> {
+        self.greedy.reset_builder();
+        let bundles: Vec<(String, WrappedBundle)> = self.greedy.order.all_bundles.iter()
+            .map(|(bundle_id, bundle)| (bundle_id.clone(), bundle.clone()))
+            .collect();
+        self.greedy.order.ordered_bundle_ids = merge_order(&mut self.greedy, &bundles);
+        self.greedy.rebuild()
+    }
+}
+
+impl BlockBuildingStrategy for MergingBlockBuildingStrategy {
+    fn new(
+        el: ElClient, parent: BlockHeader, info: ConsensusBlockInfo, withdrawals: Option<Vec<Withdrawal>>, parent_beacon_block_root: Option<SH256>,
+    ) -> Result<Self, MevBooTeeError> {
+        let mut greedy = GreedyBlockBuildingStrategy::new(el, parent, info, withdrawals, parent_beacon_block_root)?;
+        // the merged order is kept as it is
+        greedy.order.rerank = false;
+        Ok(Self { greedy })
+    }
+
+    fn add_bundle(&mut self, bundle_id: String, bundle: WrappedBundle) -> Result<(), MevBooTeeError> {
+        if self.greedy.admit(&bundle_id, bundle)? {
+            self.reorder()?;
+        }
+        Ok(())
+    }
+
+    fn remove_bundle(&mut self, bundle_id: &String) -> Result<bool, MevBooTeeError> {
+        if !self.greedy.order.forget(bundle_id) {
+            return Ok(false)
+        }
+        self.reorder()?;
+        Ok(true)
+    }
+
+    fn add_inclusion_list(&mut self, inclusion_list: Vec<Transaction>) -> Result<(), MevBooTeeError> {
+        self.greedy.add_inclusion_list(inclusion_list)
+    }
+
+    fn get_block(&mut self) -> Result<Block, MevBooTeeError> {
+        self.greedy.get_block()
+    }
+
+    fn get_block_header(&mut self) -> Result<BlockHeader, MevBooTeeError> {
+        self.greedy.get_block_header()
+    }
+
+    fn get_blob_sidecars(&self) -> Vec<BlobSidecar> {
+        self.greedy.get_blob_sidecars()
+    }
+
+    fn get_bid(&self) -> SU256 {
+        self.greedy.get_bid()
+    }
+
+    fn get_payments(&self) -> Vec<(String, SU256)> {
+        self.greedy.get_payments()
+    }
+
+    fn create_bundle(&mut self, transactions: Vec<Transaction>, reverting_tx_hashes: Vec<SH256>) -> Result<WrappedBundle, MevBooTeeError> {
+        self.greedy.create_bundle(transactions, reverting_tx_hashes)
+    }
+}
+
+// the order of `bundles` paying the coinbase the most, the executor is left in the state it was given
+// bundles which fail on their own may depend on another one, they come last
+pub fn merge_order<E: BundleExecutor>(executor: &mut E, bundles: &[(String, WrappedBundle)]) -> Vec<String>
+where E::Checkpoint: Clone {
+    let base = match executor.checkpoint() {
+        Ok(checkpoint) => checkpoint,
+        Err(err) => {
+            glog::error!("unable to checkpoint before merging bundles: {}", err);
+            return bundles.iter().map(|(bundle_id, _)| bundle_id.clone()).collect();
+        }
+    };
+
+    let mut simulated = Vec::new();
+    let mut failed = Vec::new();
+    for (index, (_, bundle)) in bundles.iter().enumerate() {
+        match executor.execute(&bundle.txns, &bundle.reverting_tx_hashes) {
+            Ok(execution) => {
+                executor.revert(base.clone());
+                simulated.push((index, execution));
+            },
+            Err(_) => failed.push(index),
+        }
+    }
+
+    let mut groups: Vec<(Vec<usize>, SU256)> = conflict_groups(&simulated)
+        .into_iter()
+        .map(|group| best_order(executor, &base, bundles, group))
+        .collect();
+    // most valuable groups first
+    groups.sort_by(|a, b| b.1.cmp(&a.1));
+
+    groups.into_iter()
+        .flat_map(|(order, _)| order)
+        .chain(failed)
+        .map(|index| bundles[index].0.clone())
+        .collect()
+}
+
+// partition the simulated bundles into groups of transitively conflicting bundles
+// each group is ordered by simulated value
+fn conflict_groups(simulated: &[(usize, BundleExecution)]) -> Vec<Vec<(usize, SU256)>> {
+    let mut group_of: Vec<usize> = (0..simulated.len()).collect();
+    fn root(group_of: &mut Vec<usize>, mut i: usize) -> usize {
+        while group_of[i] != i {
+            group_of[i] = group_of[group_of[i]];
+            i = group_of[i];
+        }
+        i
+    }
+    for i in 0..simulated.len() {
+        for j in i + 1..simulated.len() {
+            if simulated[i].1.conflicts_with(&simulated[j].1) {
+                let (a, b) = (root(&mut group_of, i), root(&mut group_of, j));
+                group_of[a.max(b)] = a.min(b);
+            }
+        }
+    }
+    let mut groups: BTreeMap<usize, Vec<(usize, SU256)>> = BTreeMap::new();
+    for i in 0..simulated.len() {
+        let group = root(&mut group_of, i);
+        groups.entry(group).or_default().push((simulated[i].0, simulated[i].1.coinbase_delta));
+    }
+    groups.into_values()
+        .map(|mut group| {
+            group.sort_by(|a, b| b.1.cmp(&a.1));
+            group
+        })
+        .collect()
+}
+
+// simulate the candidate orders of a group of conflicting bundles, returns the best one and its value
+// the best order pays the most, and fails the fewest bundles among the ones paying as much
+fn best_order<E: BundleExecutor>(
+    executor: &mut E, base: &E::Checkpoint, bundles: &[(String, WrappedBundle)], group: Vec<(usize, SU256)>,
+) -> (Vec<usize>, SU256)
+where E::Checkpoint: Clone {
+    let by_value: Vec<usize> = group.iter().map(|(index, _)| *index).collect();
+    if by_value.len() == 1 {
+        return (by_value, group[0].1);
+    }
+    let best = if by_value.len() <= MAX_PERMUTED_BUNDLES {
+        permutations(&by_value).into_iter()
+            .map(|order| simulate_order(executor, base, bundles, order))
+            .fold(None, |best: Option<SimulatedOrder>, candidate| match best {
+                Some(best) if !candidate.better_than(&best) => Some(best),
+                _ => Some(candidate),
+            })
+            .unwrap()
+    } else {
+        swap_search(executor, base, bundles, by_value)
+    };
+    (best.order, best.value)
+}
+
+struct SimulatedOrder {
+    order: Vec<usize>, // the bundles which executed in order, followed by the ones which failed
+    value: SU256,
+    failed: usize,
+}
+
+impl SimulatedOrder {
+    fn better_than(&self, other: &SimulatedOrder) -> bool {
+        self.value > other.value || (self.value == other.value && self.failed < other.failed)
+    }
+}
+
+// execute `order` on top of `base` and go back to it
+// a bundle failing in this order is left out of its value and moved after the ones which executed
+fn simulate_order<E: BundleExecutor>(executor: &mut E, base: &E::Checkpoint, bundles: &[(String, WrappedBundle)], order: Vec<usize>) -> SimulatedOrder
+where E::Checkpoint: Clone {
+    let mut executed = Vec::with_capacity(order.len());
+    let mut failed = Vec::new();
+    let mut value = SU256::default();
+    for index in order {
+        let bundle = &bundles[index].1;
+        match executor.execute(&bundle.txns, &bundle.reverting_tx_hashes) {
+            Ok(execution) => {
+                value = value + execution.coinbase_delta;
+                executed.push(index);
+            },
+            Err(_) => failed.push(index),
+        }
+    }
+    executor.revert(base.clone());
+    let failed_count = failed.len();
+    executed.extend(failed);
+    SimulatedOrder { order: executed, value, failed: failed_count }
+}
+
+// start from the better of the order by value and its reverse, then swap every pair of bundles in turn and keep
+// the swaps which improve the order, until a round brings nothing or MAX_SWAP_ROUNDS
+fn swap_search<E: BundleExecutor>(executor: &mut E, base: &E::Checkpoint, bundles: &[(String, WrappedBundle)], by_value: Vec<usize>) -> SimulatedOrder
+where E::Checkpoint: Clone {
+    let mut reversed = by_value.clone();
+    reversed.reverse();
+    let mut best = simulate_order(executor, base, bundles, by_value);
+    let candidate = simulate_order(executor, base, bundles, reversed);
+    if candidate.better_than(&best) {
+        best = candidate;
+    }
+    let len = best.order.len();
+    for _ in 0..MAX_SWAP_ROUNDS {
+        let mut improved = false;
+        for i in 0..len {
+            for j in i + 1..len {
+                let mut order = best.order.clone();
+                order.swap(i, j);
+                let candidate = simulate_order(executor, base, bundles, order);
+                if candidate.better_than(&best) {
+                    best = candidate;
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+    best
+}
+
+fn permutations(items: &[usize]) -> Vec<Vec<usize>> {
+    if items.len() <= 1 {
+        return vec![items.to_vec()];
+    }
+    let mut result = Vec::new();
+    for i in 0..items.len() {
+        let mut rest = items.to_vec();
+        let first = rest.remove(i);
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, first);
+            result.push(permutation);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::block_building::tests::{mock_bundle, mock_txn, MockExecutor};
+    use crate::select_bundles;
+
+    fn paying(sender: u8, nonce: u64, value: u64) -> Transaction {
+        let mut txn = mock_txn(sender, nonce, false);
+        txn.value = value.into();
+        txn
+    }
+
+    #[test]
+    fn test_permutations() {
+        assert_eq!(permutations(&[1, 2, 3]).len(), 6);
+        assert_eq!(permutations(&[1]), vec![vec![1]]);
+    }
+
+    // bundles of one txn per sender, all with the first nonce
+    fn senders(senders: &[u8], value: u64) -> WrappedBundle {
+        let mut txns: Vec<Transaction> = senders.iter().map(|sender| paying(*sender, 0, 0)).collect();
+        txns[0].value = value.into();
+        mock_bundle(txns, Vec::new())
+    }
+
+    #[test]
+    fn test_failing_bundles_last() {
+        // a and b both pay 5 but b takes the nonces of a and c, and comes first by value
+        let bundles = vec![("a".to_owned(), senders(&[1], 5)), ("b".to_owned(), senders(&[1, 2], 5)), ("c".to_owned(), senders(&[2], 0))];
+        let mut executor = MockExecutor::default();
+        let base = executor.checkpoint().unwrap();
+        let group = vec![(1, 5.into()), (0, 5.into()), (2, SU256::default())];
+        // as much is paid with a first, and only b fails then, it goes after the bundles which executed
+        assert_eq!(best_order(&mut executor, &base, &bundles, group), (vec![0, 2, 1], 5.into()));
+        assert!(executor.txns.is_empty());
+    }
+
+    #[test]
+    fn test_swap_search() {
+        // a pays the most alone but b and c pay more together, e takes every nonce
+        let bundles = vec![
+            ("a".to_owned(), senders(&[1, 2], 10)),
+            ("b".to_owned(), senders(&[1], 6)),
+            ("c".to_owned(), senders(&[2], 6)),
+            ("d".to_owned(), senders(&[2, 3], 2)),
+            ("e".to_owned(), senders(&[1, 2], 0)),
+        ];
+        let mut executor = MockExecutor::default();
+        let base = executor.checkpoint().unwrap();
+        let group: Vec<(usize, SU256)> = vec![(0, 10.into()), (1, 6.into()), (2, 6.into()), (3, 2.into()), (4, SU256::default())];
+        // neither the order by value (10) nor its reverse (0) finds it, swapping a and b does
+        let value = |order: Vec<usize>| simulate_order(&mut MockExecutor::default(), &base, &bundles, order).value;
+        assert_eq!((value(vec![0, 1, 2, 3, 4]), value(vec![4, 3, 2, 1, 0])), (10.into(), SU256::default()));
+        assert_eq!(best_order(&mut executor, &base, &bundles, group), (vec![1, 2, 0, 3, 4], 12.into()));
+        assert!(executor.txns.is_empty());
+    }
+
+    #[test]
+    fn test_reorder_conflicting_bundles() {
+        // a declares more than b but pays less, and both use the first nonce of sender 1
+        let mut a = mock_bundle(vec![paying(1, 0, 3)], Vec::new());
+        a.bid = 10.into();
+        let b = mock_bundle(vec![paying(1, 0, 2), paying(1, 1, 2)], Vec::new());
+        let c = mock_bundle(vec![paying(2, 0, 1)], Vec::new());
+        // depends on a
+        let d = mock_bundle(vec![paying(1, 1, 1)], Vec::new());
+        let bundles = vec![("a".to_owned(), a), ("b".to_owned(), b), ("c".to_owned(), c), ("d".to_owned(), d)];
+
+        let mut executor = MockExecutor::default();
+        let order = merge_order(&mut executor, &bundles);
+        assert_eq!(order, vec!["b", "a", "c", "d"]);
+        // back to the base state
+        assert!(executor.txns.is_empty());
+
+        let ordered: Vec<(String, WrappedBundle)> = order.iter()
+            .map(|id| bundles.iter().find(|(bundle_id, _)| bundle_id == id).unwrap().clone())
+            .collect();
+        let selection = select_bundles(&mut executor, &ordered, &[]);
+        assert_eq!(selection.block, vec!["b", "c"]);
+    }
+}
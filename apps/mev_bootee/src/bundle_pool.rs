@@ -0,0 +1,121 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeMap;
+
+use crate::{MevBooTeeError, WrappedBundle};
+
+// ToB bundles waiting for their block, indexed by target block number
+#[derive(Default)]
+pub struct BundlePool {
+    blocks: BTreeMap<u64, BTreeMap<String, WrappedBundle>>,
+    index: BTreeMap<String, u64>, // bundle id -> target block number
+    head: u64, // latest block on chain, nothing at or below it is accepted
+}
+
+impl BundlePool {
+    // returns false if a bundle with the same id is already in the pool
+    pub fn insert(&mut self, bundle_id: String, bundle: WrappedBundle) -> Result<bool, MevBooTeeError> {
+        if bundle.block_number <= self.head {
+            return Err(MevBooTeeError::Bundle(format!("block {} is already on chain (head: {})", bundle.block_number, self.head)));
+        }
+        if self.index.contains_key(&bundle_id) {
+            return Ok(false);
+        }
+        self.index.insert(bundle_id.clone(), bundle.block_number);
+        self.blocks.entry(bundle.block_number).or_default().insert(bundle_id, bundle);
+        Ok(true)
+    }
+
+    pub fn remove(&mut self, bundle_id: &str) -> Option<WrappedBundle> {
+        let block_number = self.index.remove(bundle_id)?;
+        let bundles = self.blocks.get_mut(&block_number)?;
+        let bundle = bundles.remove(bundle_id);
+        if bundles.is_empty() {
+            self.blocks.remove(&block_number);
+        }
+        bundle
+    }
+
+    pub fn get(&self, bundle_id: &str) -> Option<&WrappedBundle> {
+        let block_number = self.index.get(bundle_id)?;
+        self.blocks.get(block_number)?.get(bundle_id)
+    }
+
+    pub fn bundles(&self, block_number: u64) -> Vec<(String, WrappedBundle)> {
+        match self.blocks.get(&block_number) {
+            Some(bundles) => bundles.iter().map(|(id, bundle)| (id.clone(), bundle.clone())).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // highest bid for `block_number`, ties go to the earliest submission
+    pub fn highest_bid(&self, block_number: u64) -> Option<(&String, &WrappedBundle)> {
+        self.blocks.get(&block_number)?
+            .iter()
+            .max_by(|a, b| a.1.bid.cmp(&b.1.bid).then(b.1.timestamp.cmp(&a.1.timestamp)))
+    }
+
+    // the chain moved to `head`: evict every bundle that targets `head` or an earlier block
+    // after a reorg to a lower head, the slots above it take bundles again
+    pub fn on_new_head(&mut self, head: u64) {
+        if head <= self.head {
+            self.head = head;
+            return;
+        }
+        self.head = head;
+        let future = self.blocks.split_off(&(head + 1));
+        for bundles in self.blocks.values() {
+            for bundle_id in bundles.keys() {
+                self.index.remove(bundle_id);
+            }
+        }
+        self.blocks = future;
+    }
+
+    pub fn head(&self) -> u64 {
+        self.head
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use eth_types::SU256;
+
+    use crate::block_building::tests::mock_bundle;
+
+    fn bundle(block_number: u64, bid: u64, timestamp: u64) -> WrappedBundle {
+        WrappedBundle { bid: SU256::from(bid), block_number, timestamp, ..mock_bundle(Vec::new(), Vec::new()) }
+    }
+
+    #[test]
+    fn test_highest_bid() {
+        let mut pool = BundlePool::default();
+        assert!(pool.highest_bid(10).is_none());
+        pool.insert("a".into(), bundle(10, 5, 1)).unwrap();
+        pool.insert("b".into(), bundle(10, 7, 3)).unwrap();
+        pool.insert("c".into(), bundle(10, 7, 2)).unwrap();
+        pool.insert("d".into(), bundle(11, 100, 1)).unwrap();
+        assert_eq!(pool.highest_bid(10).unwrap().0, "c");
+        assert!(!pool.insert("a".into(), bundle(10, 9, 4)).unwrap());
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut pool = BundlePool::default();
+        pool.insert("a".into(), bundle(10, 5, 1)).unwrap();
+        pool.insert("b".into(), bundle(11, 5, 1)).unwrap();
+        pool.on_new_head(10);
+        assert!(pool.get("a").is_none());
+        assert!(pool.get("b").is_some());
+        assert_eq!(pool.len(), 1);
+        assert!(pool.insert("c".into(), bundle(10, 5, 1)).is_err());
+        assert!(pool.remove("b").is_some());
+        assert!(pool.remove("b").is_none());
+    }
+}
@@ -0,0 +1,336 @@
+use std::prelude::v1::*;
+
+use std::net::SocketAddr;
+
+use apps::getargs::{Opt, Options};
+use serde::Deserialize;
+
+use crate::{AuctionConfig, MevBooTeeError, MevBooTeeMode, PublisherKind, StrategyKind};
+
+const USAGE: &str = "usage: mev-bootee [--config <file>] [--mode <mode>] [--listen <addr>] [--el <url>]... [--chain-id <id>] [--beacon <url>] [--duties <file>] [--kzg-setup <file>] [--publisher <kind>] [--public-mempool] [--publish <url>]... [--strategy <strategy>] [--bid-deadline <ms>] [--payment <rule>] [--verify]";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MevBooTeeConfig {
+    pub mode: MevBooTeeMode,
+    pub server: ServerConfig,
+    pub el_endpoints: Vec<String>,
+    pub chain_id: Option<u64>, // fetched from the execution client when missing
+    pub beacon_endpoint: Option<String>,
+    pub duty_file: Option<String>, // proposer duties supplied locally, takes precedence over the beacon node
+    pub kzg_setup: Option<String>, // the KZG trusted setup, blob transactions are rejected without it
+    pub publisher: PublisherConfig,
+    pub strategy: StrategyKind, // how the bundles are put together into blocks
+    pub auction: AuctionConfig,
+    pub do_verification: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PublisherConfig {
+    pub kind: PublisherKind,
+    pub endpoints: Vec<String>, // the beacon endpoint or the execution endpoints when empty
+    pub rounds: usize, // how many times every endpoint is tried
+    pub public_mempool: bool, // the execution publisher hands the sealed transactions to the public mempool, off unless asked for
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub listen_addr: String,
+}
+
+impl Default for MevBooTeeConfig {
+    fn default() -> Self {
+        Self {
+            mode: MevBooTeeMode::Assembler,
+            server: ServerConfig::default(),
+            el_endpoints: vec!["http://localhost:8545".into()],
+            chain_id: None,
+            beacon_endpoint: None,
+            duty_file: None,
+            kzg_setup: None,
+            publisher: PublisherConfig::default(),
+            strategy: StrategyKind::Greedy,
+            auction: AuctionConfig::default(),
+            do_verification: false,
+        }
+    }
+}
+
+impl Default for PublisherConfig {
+    fn default() -> Self {
+        Self {
+            kind: PublisherKind::Beacon,
+            endpoints: Vec::new(),
+            rounds: 3,
+            public_mempool: false,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: "0.0.0.0:1234".into(),
+        }
+    }
+}
+
+impl MevBooTeeConfig {
+    // args[0] is the executable, the options on the command line take precedence over the config file
+    pub fn from_args(args: &[String]) -> Result<Self, MevBooTeeError> {
+        let mut opts = Options::new(args.iter().skip(1).map(String::as_str));
+        let mut config_file = None;
+        let mut mode = None;
+        let mut listen_addr = None;
+        let mut el_endpoints = Vec::new();
+        let mut chain_id = None;
+        let mut beacon_endpoint = None;
+        let mut duty_file = None;
+        let mut kzg_setup = None;
+        let mut publisher_kind = None;
+        let mut publish_endpoints = Vec::new();
+        let mut public_mempool = false;
+        let mut strategy = None;
+        let mut bid_deadline = None;
+        let mut payment = None;
+        let mut do_verification = false;
+        while let Some(opt) = opts.next_opt().map_err(|err| config_err(format!("{}, {}", err, USAGE)))? {
+            match opt {
+                Opt::Short('c') | Opt::Long("config") => config_file = Some(opt_value(&mut opts, "--config")?),
+                Opt::Short('m') | Opt::Long("mode") => mode = Some(opt_value(&mut opts, "--mode")?.parse()?),
+                Opt::Short('l') | Opt::Long("listen") => listen_addr = Some(opt_value(&mut opts, "--listen")?),
+                Opt::Long("el") => el_endpoints.extend(opt_value(&mut opts, "--el")?.split(',').map(|url| url.trim().to_owned())),
+                Opt::Long("chain-id") => {
+                    let value = opt_value(&mut opts, "--chain-id")?;
+                    chain_id = Some(value.parse().map_err(|_| config_err(format!("invalid chain id: {:?}", value)))?);
+                },
+                Opt::Long("beacon") => beacon_endpoint = Some(opt_value(&mut opts, "--beacon")?.to_owned()),
+                Opt::Long("duties") => duty_file = Some(opt_value(&mut opts, "--duties")?.to_owned()),
+                Opt::Long("kzg-setup") => kzg_setup = Some(opt_value(&mut opts, "--kzg-setup")?.to_owned()),
+                Opt::Long("publisher") => publisher_kind = Some(opt_value(&mut opts, "--publisher")?.parse()?),
+                Opt::Long("public-mempool") => public_mempool = true,
+                Opt::Long("publish") => publish_endpoints.extend(opt_value(&mut opts, "--publish")?.split(',').map(|url| url.trim().to_owned())),
+                Opt::Long("strategy") => strategy = Some(opt_value(&mut opts, "--strategy")?.parse()?),
+                Opt::Long("bid-deadline") => {
+                    let value = opt_value(&mut opts, "--bid-deadline")?;
+                    bid_deadline = Some(value.parse().map_err(|_| config_err(format!("invalid bid deadline: {:?}", value)))?);
+                },
+                Opt::Long("payment") => payment = Some(opt_value(&mut opts, "--payment")?.parse()?),
+                Opt::Long("verify") => do_verification = true,
+                opt => return Err(config_err(format!("unknown option {}, {}", opt, USAGE))),
+            }
+        }
+        if let Some(arg) = opts.positionals().next() {
+            return Err(config_err(format!("unexpected argument {:?}, {}", arg, USAGE)));
+        }
+
+        let mut cfg = match config_file {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        if let Some(mode) = mode {
+            cfg.mode = mode;
+        }
+        if let Some(listen_addr) = listen_addr {
+            cfg.server.listen_addr = listen_addr;
+        }
+        if !el_endpoints.is_empty() {
+            cfg.el_endpoints = el_endpoints;
+        }
+        if chain_id.is_some() {
+            cfg.chain_id = chain_id;
+        }
+        if beacon_endpoint.is_some() {
+            cfg.beacon_endpoint = beacon_endpoint;
+        }
+        if duty_file.is_some() {
+            cfg.duty_file = duty_file;
+        }
+        if kzg_setup.is_some() {
+            cfg.kzg_setup = kzg_setup;
+        }
+        if let Some(kind) = publisher_kind {
+            cfg.publisher.kind = kind;
+        }
+        if !publish_endpoints.is_empty() {
+            cfg.publisher.endpoints = publish_endpoints;
+        }
+        cfg.publisher.public_mempool |= public_mempool;
+        if let Some(strategy) = strategy {
+            cfg.strategy = strategy;
+        }
+        if let Some(deadline_ms) = bid_deadline {
+            cfg.auction.deadline_ms = deadline_ms;
+        }
+        if let Some(payment) = payment {
+            cfg.auction.payment = payment;
+        }
+        cfg.do_verification |= do_verification;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, MevBooTeeError> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|err| config_err(format!("unable to read {}: {}", path, err)))?;
+        Self::from_json(&data).map_err(|err| config_err(format!("{}: {}", path, err)))
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, MevBooTeeError> {
+        serde_json::from_str(data).map_err(|err| config_err(format!("invalid config: {}", err)))
+    }
+
+    pub fn validate(&self) -> Result<(), MevBooTeeError> {
+        self.server.listen_addr.parse::<SocketAddr>()
+            .map_err(|_| config_err(format!("invalid listen address: {:?}", self.server.listen_addr)))?;
+        if self.el_endpoints.is_empty() {
+            return Err(config_err("at least one execution endpoint is required"));
+        }
+        for url in &self.el_endpoints {
+            let known_scheme = ["http://", "https://", "ws://", "wss://"].iter().any(|scheme| url.starts_with(scheme));
+            if !known_scheme {
+                return Err(config_err(format!("invalid execution endpoint: {:?}", url)));
+            }
+        }
+        if let Some(url) = &self.beacon_endpoint {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(config_err(format!("invalid beacon endpoint: {:?}", url)));
+            }
+        }
+        for url in &self.publisher.endpoints {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(config_err(format!("invalid publish endpoint: {:?}", url)));
+            }
+        }
+        if self.publisher.kind == PublisherKind::Execution && !self.publisher.public_mempool {
+            return Err(config_err("the execution publisher sends the transactions to the public mempool, it requires --public-mempool"));
+        }
+        if self.chain_id == Some(0) {
+            return Err(config_err("chain id must not be 0"));
+        }
+        Ok(())
+    }
+
+    // where the signed blocks are sent
+    pub fn publish_endpoints(&self) -> Vec<String> {
+        if !self.publisher.endpoints.is_empty() {
+            return self.publisher.endpoints.clone();
+        }
+        match self.publisher.kind {
+            PublisherKind::Beacon => self.beacon_endpoint.iter().cloned().collect(),
+            PublisherKind::Execution => self.el_endpoints.clone(),
+        }
+    }
+}
+
+fn opt_value<'a, I: Iterator<Item = &'a str>>(opts: &mut Options<&'a str, I>, name: &str) -> Result<&'a str, MevBooTeeError> {
+    opts.value().map_err(|_| config_err(format!("missing value for {}", name)))
+}
+
+fn config_err<S: Into<String>>(msg: S) -> MevBooTeeError {
+    MevBooTeeError::Config(msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Payment;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        std::iter::once("mev-bootee").chain(list.iter().cloned()).map(String::from).collect()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let cfg = MevBooTeeConfig::from_args(&args(&[])).unwrap();
+        assert_eq!(cfg.mode, MevBooTeeMode::Assembler);
+        assert_eq!(cfg.server.listen_addr, "0.0.0.0:1234");
+        assert_eq!(cfg.el_endpoints, vec!["http://localhost:8545".to_owned()]);
+        assert!(!cfg.do_verification);
+    }
+
+    #[test]
+    fn test_args() {
+        let cfg = MevBooTeeConfig::from_args(&args(&[
+            "--mode", "proposer-aide", "--listen", "127.0.0.1:18545",
+            "--el", "http://a:8545,http://b:8545", "--el", "ws://c:8546",
+            "--chain-id", "11155111", "--beacon", "http://localhost:5052", "--verify",
+        ])).unwrap();
+        assert_eq!(cfg.mode, MevBooTeeMode::ProposerAide);
+        assert_eq!(cfg.server.listen_addr, "127.0.0.1:18545");
+        assert_eq!(cfg.el_endpoints.len(), 3);
+        assert_eq!(cfg.chain_id, Some(11155111));
+        assert_eq!(cfg.beacon_endpoint.as_deref(), Some("http://localhost:5052"));
+        assert!(cfg.do_verification);
+        assert_eq!(cfg.publish_endpoints(), vec!["http://localhost:5052".to_owned()]);
+    }
+
+    #[test]
+    fn test_publisher() {
+        // the execution publisher leaks the order flow, it has to be asked for
+        assert!(!MevBooTeeConfig::from_args(&args(&[])).unwrap().publisher.public_mempool);
+        assert!(MevBooTeeConfig::from_args(&args(&["--publisher", "execution"])).is_err());
+        assert!(MevBooTeeConfig::from_json(r#"{"publisher": {"kind": "execution"}}"#).unwrap().validate().is_err());
+        let cfg = MevBooTeeConfig::from_args(&args(&["--publisher", "execution", "--public-mempool"])).unwrap();
+        assert_eq!(cfg.publisher.kind, PublisherKind::Execution);
+        assert_eq!(cfg.publish_endpoints(), cfg.el_endpoints);
+
+        let cfg = MevBooTeeConfig::from_args(&args(&["--publish", "http://a:5052", "--publish", "http://b:5052"])).unwrap();
+        assert_eq!(cfg.publish_endpoints().len(), 2);
+        assert!(MevBooTeeConfig::from_args(&args(&["--publisher", "relay"])).is_err());
+    }
+
+    #[test]
+    fn test_strategy() {
+        assert_eq!(MevBooTeeConfig::from_args(&args(&[])).unwrap().strategy, StrategyKind::Greedy);
+        assert_eq!(MevBooTeeConfig::from_args(&args(&["--strategy", "merging"])).unwrap().strategy, StrategyKind::Merging);
+        assert_eq!(MevBooTeeConfig::from_json(r#"{"strategy": "merging"}"#).unwrap().strategy, StrategyKind::Merging);
+        assert!(MevBooTeeConfig::from_args(&args(&["--strategy", "random"])).is_err());
+    }
+
+    #[test]
+    fn test_auction() {
+        let cfg = MevBooTeeConfig::from_args(&args(&[])).unwrap();
+        assert_eq!((cfg.auction.deadline_ms, cfg.auction.payment), (0, Payment::FirstPrice));
+        let cfg = MevBooTeeConfig::from_args(&args(&["--bid-deadline", "-1500", "--payment", "second-price"])).unwrap();
+        assert_eq!((cfg.auction.deadline_ms, cfg.auction.payment), (-1500, Payment::SecondPrice));
+        assert_eq!(cfg.auction.deadline(12), 10_500);
+        assert!(MevBooTeeConfig::from_args(&args(&["--payment", "third-price"])).is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        let cfg = MevBooTeeConfig::from_json(r#"{"mode": "full-tee-builder", "server": {"listen_addr": "0.0.0.0:9000"}, "chain_id": 1}"#).unwrap();
+        assert_eq!(cfg.mode, MevBooTeeMode::FullTeeBuilder);
+        assert_eq!(cfg.server.listen_addr, "0.0.0.0:9000");
+        assert_eq!(cfg.el_endpoints, vec!["http://localhost:8545".to_owned()]);
+    }
+
+    #[test]
+    fn test_one_spelling() {
+        // the command line and the config file take the same names
+        for mode in ["proposer-aide", "builder-aide", "assembler", "full-tee-builder"] {
+            let json = MevBooTeeConfig::from_json(&format!(r#"{{"mode": "{}"}}"#, mode)).unwrap();
+            assert_eq!(json.mode, MevBooTeeConfig::from_args(&args(&["--mode", mode])).unwrap().mode);
+        }
+        let cfg = MevBooTeeConfig::from_json(r#"{"strategy": "merging", "publisher": {"kind": "beacon"}, "auction": {"payment": "second-price"}}"#).unwrap();
+        assert_eq!((cfg.strategy, cfg.publisher.kind, cfg.auction.payment), (StrategyKind::Merging, PublisherKind::Beacon, Payment::SecondPrice));
+        assert!(MevBooTeeConfig::from_json(r#"{"mode": "FullTeeBuilder"}"#).is_err());
+        assert!(MevBooTeeConfig::from_args(&args(&["--mode", "full_tee_builder"])).is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(MevBooTeeConfig::from_args(&args(&["--mode", "relay"])).is_err());
+        assert!(MevBooTeeConfig::from_args(&args(&["--listen", "localhost"])).is_err());
+        assert!(MevBooTeeConfig::from_args(&args(&["--el", "localhost:8545"])).is_err());
+        assert!(MevBooTeeConfig::from_args(&args(&["--chain-id", "sepolia"])).is_err());
+        assert!(MevBooTeeConfig::from_args(&args(&["--chain-id"])).is_err());
+        assert!(MevBooTeeConfig::from_args(&args(&["--beacon", "localhost:5052"])).is_err());
+        assert!(MevBooTeeConfig::from_args(&args(&["--unknown"])).is_err());
+        assert!(MevBooTeeConfig::from_json(r#"{"mode": 1}"#).is_err());
+    }
+}
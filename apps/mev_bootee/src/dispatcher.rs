@@ -0,0 +1,106 @@
+use std::prelude::v1::*;
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use jsonrpc::JsonrpcErrorObj;
+use serde::Serialize;
+
+use crate::JsonRpcServerMsg;
+
+// depth of a message queue, updated by both of its ends
+#[derive(Default)]
+pub struct QueueMetrics {
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    received: AtomicU64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct QueueStats {
+    pub depth: usize,     // messages waiting to be handled
+    pub max_depth: usize, // since startup
+    pub received: u64,
+}
+
+impl QueueMetrics {
+    pub fn push(&self) {
+        let depth = self.depth.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_depth.fetch_max(depth, Ordering::SeqCst);
+        self.received.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn pop(&self) {
+        let _ = self.depth.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |depth| Some(depth.saturating_sub(1)));
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            depth: self.depth.load(Ordering::SeqCst),
+            max_depth: self.max_depth.load(Ordering::SeqCst),
+            received: self.received.load(Ordering::SeqCst),
+        }
+    }
+}
+
+// the queue of every request, and the queue of the read-only ones handed to the reader threads
+#[derive(Default)]
+pub struct DispatcherMetrics {
+    pub requests: QueueMetrics,
+    pub reads: QueueMetrics,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DispatcherStats {
+    pub requests: QueueStats,
+    pub reads: QueueStats,
+}
+
+impl DispatcherMetrics {
+    pub fn stats(&self) -> DispatcherStats {
+        DispatcherStats {
+            requests: self.requests.stats(),
+            reads: self.reads.stats(),
+        }
+    }
+}
+
+// the sending end of the main loop, shared by the RPC server and the beacon listener
+pub struct RequestQueue {
+    sender: Mutex<Sender<JsonRpcServerMsg>>,
+    pub metrics: Arc<DispatcherMetrics>,
+}
+
+impl RequestQueue {
+    pub fn new(sender: Sender<JsonRpcServerMsg>, metrics: Arc<DispatcherMetrics>) -> Self {
+        Self { sender: Mutex::new(sender), metrics }
+    }
+
+    pub fn send(&self, msg: JsonRpcServerMsg) -> Result<(), JsonrpcErrorObj> {
+        self.metrics.requests.push();
+        self.sender.lock().unwrap().send(msg).map_err(|_| {
+            self.metrics.requests.pop();
+            JsonrpcErrorObj::unknown("unresponsive")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_metrics() {
+        let metrics = QueueMetrics::default();
+        metrics.push();
+        metrics.push();
+        metrics.pop();
+        metrics.push();
+        assert_eq!(metrics.stats(), QueueStats { depth: 2, max_depth: 2, received: 3 });
+        metrics.pop();
+        metrics.pop();
+        metrics.pop();
+        assert_eq!(metrics.stats().depth, 0);
+    }
+}
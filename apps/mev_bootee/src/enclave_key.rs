@@ -0,0 +1,210 @@
+use std::prelude::v1::*;
+
+use blst::min_pk::{PublicKey, SecretKey, Signature};
+use blst::{blst_p1, blst_p1_affine, blst_scalar, BLST_ERROR};
+use eth_types::{BlockHeader, HexBytes, SH256, SU256};
+use serde::{Deserialize, Serialize};
+
+use crate::MevBooTeeError;
+
+const ENCLAVE_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+// key material drawn inside the enclave
+fn random_ikm() -> [u8; 32] {
+    let mut ikm = [0_u8; 32];
+    crypto::read_rand(&mut ikm);
+    ikm
+}
+
+// the secret key of `ikm` and its compressed G1 public key
+fn bls_keypair(ikm: &[u8; 32]) -> (SecretKey, HexBytes) {
+    let secret = SecretKey::key_gen(ikm, &[]).expect("32 bytes of key material");
+    let public = secret.sk_to_pk().to_bytes().to_vec().into();
+    (secret, public)
+}
+
+// the key the enclave signs its responses with, generated at startup it never leaves the enclave
+// its public key is bound to the SGX quote through the report data
+pub struct EnclaveKey {
+    secret: SecretKey,
+    public: HexBytes,
+}
+
+impl EnclaveKey {
+    pub fn generate() -> Self {
+        Self::from_ikm(&random_ikm())
+    }
+
+    pub(crate) fn from_ikm(ikm: &[u8; 32]) -> Self {
+        let (secret, public) = bls_keypair(ikm);
+        Self { secret, public }
+    }
+
+    pub fn public_key(&self) -> &HexBytes {
+        &self.public
+    }
+
+    pub fn sign(&self, root: &SH256) -> HexBytes {
+        self.secret.sign(root.as_bytes(), ENCLAVE_DST, &[]).to_bytes().to_vec().into()
+    }
+}
+
+// the key clients derive a shared secret with to send data only the enclave can read, it is bound
+// to the SGX quote along with the signing key
+pub struct EncryptionKey {
+    secret: SecretKey,
+    public: HexBytes,
+}
+
+impl EncryptionKey {
+    pub fn generate() -> Self {
+        Self::from_ikm(&random_ikm())
+    }
+
+    pub(crate) fn from_ikm(ikm: &[u8; 32]) -> Self {
+        let (secret, public) = bls_keypair(ikm);
+        Self { secret, public }
+    }
+
+    // a compressed G1 point
+    pub fn public_key(&self) -> &HexBytes {
+        &self.public
+    }
+
+    // a symmetric key of the enclave for `purpose`, derived from the secret so it is gone along with it
+    pub fn derive_key(&self, purpose: &[u8]) -> [u8; 16] {
+        let mut data = b"mev-bootee/derive_key".to_vec();
+        data.extend_from_slice(&self.secret.to_bytes());
+        data.extend_from_slice(purpose);
+        let mut key = [0_u8; 16];
+        key.copy_from_slice(&crypto::keccak_hash(&data)[..16]);
+        key
+    }
+
+    // Diffie-Hellman over G1 with the compressed point `peer`, the secret is the hash of the shared point
+    pub fn shared_secret(&self, peer: &[u8]) -> Result<SH256, MevBooTeeError> {
+        if peer.len() != 48 {
+            return Err(MevBooTeeError::Enclave(format!("invalid peer key length {}", peer.len())));
+        }
+        let mut shared = [0_u8; 48];
+        unsafe {
+            let mut point = blst_p1_affine::default();
+            if blst::blst_p1_uncompress(&mut point, peer.as_ptr()) != BLST_ERROR::BLST_SUCCESS || !blst::blst_p1_affine_in_g1(&point) {
+                return Err(MevBooTeeError::Enclave("invalid peer key".into()));
+            }
+            let mut scalar = blst_scalar::default();
+            blst::blst_scalar_from_bendian(&mut scalar, self.secret.to_bytes().as_ptr());
+            let mut peer = blst_p1::default();
+            blst::blst_p1_from_affine(&mut peer, &point);
+            let mut out = blst_p1::default();
+            blst::blst_p1_mult(&mut out, &peer, scalar.b.as_ptr(), 255);
+            blst::blst_p1_compress(shared.as_mut_ptr(), &out);
+        }
+        Ok(crypto::keccak_hash(&shared).into())
+    }
+}
+
+// the fixed encoding of a response result the enclave signs
+pub trait SigningData {
+    fn signing_data(&self) -> Vec<u8>;
+}
+
+// the bid and the hash of the header it comes with
+impl SigningData for (SU256, BlockHeader) {
+    fn signing_data(&self) -> Vec<u8> {
+        let mut data = [0_u8; 64];
+        self.0.to_big_endian(&mut data[..32]);
+        data[32..].copy_from_slice(self.1.hash().as_bytes());
+        data.to_vec()
+    }
+}
+
+// the result of an RPC method along with the signature of the enclave key over it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedResponse<T> {
+    pub method: String,
+    pub result: T,
+    pub signature: HexBytes,
+}
+
+impl<T: SigningData> SignedResponse<T> {
+    pub fn new(key: &EnclaveKey, method: &str, result: T) -> Self {
+        let mut response = Self { method: method.into(), result, signature: HexBytes::default() };
+        response.signature = key.sign(&response.signing_root());
+        response
+    }
+
+    // over the method name and the signing data of the result
+    pub fn signing_root(&self) -> SH256 {
+        let mut data = b"mev-bootee/response".to_vec();
+        data.extend_from_slice(&crypto::keccak_hash(self.method.as_bytes()));
+        data.extend_from_slice(&self.result.signing_data());
+        crypto::keccak_hash(&data).into()
+    }
+
+    pub fn verify(&self, pubkey: &[u8]) -> Result<(), MevBooTeeError> {
+        verify_enclave_signature(pubkey, &self.signing_root(), &self.signature)
+    }
+}
+
+// check a signature of the enclave key `pubkey` over `root`
+pub fn verify_enclave_signature(pubkey: &[u8], root: &SH256, signature: &[u8]) -> Result<(), MevBooTeeError> {
+    let pubkey = PublicKey::from_bytes(pubkey)
+        .map_err(|err| MevBooTeeError::Enclave(format!("invalid enclave pubkey: {:?}", err)))?;
+    let signature = Signature::from_bytes(signature)
+        .map_err(|err| MevBooTeeError::Enclave(format!("invalid signature: {:?}", err)))?;
+    match signature.verify(true, root.as_bytes(), ENCLAVE_DST, &[], &pubkey, true) {
+        BLST_ERROR::BLST_SUCCESS => Ok(()),
+        err => Err(MevBooTeeError::Enclave(format!("signature verification failed: {:?}", err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Retraction;
+
+    #[test]
+    fn test_signed_response() {
+        let key = EnclaveKey::from_ikm(&[1; 32]);
+        let response = SignedResponse::new(&key, "retract_tob", Retraction { tob_id: "a".into(), retracted: true, timestamp: 100 });
+        assert!(response.verify(key.public_key()).is_ok());
+
+        let mut forged = response.clone();
+        forged.result.retracted = false;
+        assert!(forged.verify(key.public_key()).is_err());
+        let mut replayed = response;
+        replayed.method = "submit_tob".into();
+        assert!(replayed.verify(key.public_key()).is_err());
+    }
+
+    #[test]
+    fn test_signed_bid() {
+        let key = EnclaveKey::from_ikm(&[1; 32]);
+        let response = SignedResponse::new(&key, "get_highest_bid", (SU256::from(10), BlockHeader::default()));
+        assert!(response.verify(key.public_key()).is_ok());
+
+        let mut forged = response;
+        forged.result.0 = 11.into();
+        assert!(forged.verify(key.public_key()).is_err());
+    }
+
+    #[test]
+    fn test_derive_key() {
+        let key = EncryptionKey::from_ikm(&[1; 32]);
+        assert_eq!(key.derive_key(b"mempool"), EncryptionKey::from_ikm(&[1; 32]).derive_key(b"mempool"));
+        assert_ne!(key.derive_key(b"mempool"), key.derive_key(b"other"));
+        assert_ne!(key.derive_key(b"mempool"), EncryptionKey::from_ikm(&[2; 32]).derive_key(b"mempool"));
+    }
+
+    #[test]
+    fn test_shared_secret() {
+        let enclave = EncryptionKey::from_ikm(&[1; 32]);
+        let client = EncryptionKey::from_ikm(&[2; 32]);
+        let secret = enclave.shared_secret(client.public_key()).unwrap();
+        assert_eq!(client.shared_secret(enclave.public_key()).unwrap(), secret);
+        assert_ne!(EncryptionKey::from_ikm(&[3; 32]).shared_secret(enclave.public_key()).unwrap(), secret);
+        assert!(enclave.shared_secret(&[0; 48]).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+use std::prelude::v1::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crypto::{Aes128EncryptedMsg, Aes128Key};
+use eth_types::{Block, BlockHeader, Transaction, Withdrawal, SH160, SH256, SU256};
+use evm_executor::ConsensusBlockInfo;
+use serde::{Deserialize, Serialize};
+use statedb::StateDB;
+
+use crate::{decode_transaction, BlockBuildingStrategy, ChainHead, ElClient, MevBooTeeError, TxRejectReason, TxRejection, TxValidator};
+
+const MAX_MEMPOOL_ORDERS: usize = 4096;
+const MAX_ORDERS_PER_SENDER: usize = 16; // the sender of an order is the sender of its first transaction
+
+// full TEE builder: transactions and bundles go into a mempool that only the enclave can read,
+// blocks are built from it continuously and delivered straight to the proposer.
+
+#[derive(Deserialize)]
+pub struct SendBundleRequest {
+    pub txns: Vec<String>,
+    pub block_number: u64,
+    #[serde(default)]
+    pub reverting_tx_hashes: Vec<SH256>,
+}
+
+impl SendBundleRequest {
+    pub fn into_order(&self) -> Result<MempoolOrder, String> {
+        if self.txns.is_empty() {
+            return Err("empty bundle".into());
+        }
+        let txns = self.txns.iter().map(|txn| decode_transaction(txn)).collect::<Result<Vec<_>, _>>()?;
+        Ok(MempoolOrder::Bundle {
+            block_number: self.block_number,
+            txns,
+            reverting_tx_hashes: self.reverting_tx_hashes.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum MempoolOrder {
+    Transaction(Transaction),
+    Bundle {
+        block_number: u64,
+        txns: Vec<Transaction>,
+        reverting_tx_hashes: Vec<SH256>,
+    },
+}
+
+impl MempoolOrder {
+    pub fn id(&self) -> SH256 {
+        match self {
+            MempoolOrder::Transaction(txn) => txn.hash,
+            MempoolOrder::Bundle { block_number, txns, .. } => {
+                let mut data = block_number.to_be_bytes().to_vec();
+                for txn in txns {
+                    data.extend_from_slice(txn.hash.as_bytes());
+                }
+                crypto::keccak_hash(&data).into()
+            },
+        }
+    }
+
+    pub fn txns(&self) -> Vec<&Transaction> {
+        match self {
+            MempoolOrder::Transaction(txn) => vec![txn],
+            MempoolOrder::Bundle { txns, .. } => txns.iter().collect(),
+        }
+    }
+
+    pub fn reverting_tx_hashes(&self) -> Vec<SH256> {
+        match self {
+            MempoolOrder::Transaction(_) => Vec::new(),
+            MempoolOrder::Bundle { reverting_tx_hashes, .. } => reverting_tx_hashes.clone(),
+        }
+    }
+
+    // check chain id, signatures and nonces against `state`, the state of the chain head
+    // returns the sender of the order
+    pub fn verify<S: StateDB>(&self, chain_id: u64, state: &mut S) -> Result<SH160, TxRejection> {
+        let txns: Vec<Transaction> = self.txns().into_iter().cloned().collect();
+        let senders = TxValidator::new(chain_id, state).validate_all(&txns)?;
+        senders.first().cloned().ok_or(TxRejection { index: 0, reason: TxRejectReason::EmptyBundle })
+    }
+}
+
+// build the block of the mempool `orders` with the strategy `S`, every order is valued by simulating it on the parent state
+// the ids of the orders failing that simulation come back along with the block
+pub fn build_full_block_with<S: BlockBuildingStrategy>(
+    el: ElClient, parent: BlockHeader, info: ConsensusBlockInfo, withdrawals: Option<Vec<Withdrawal>>, parent_beacon_block_root: Option<SH256>,
+    orders: Vec<MempoolOrder>,
+) -> Result<(SU256, Block, Vec<SH256>), MevBooTeeError> {
+    let mut strategy = S::new(el, parent, info, withdrawals, parent_beacon_block_root)?;
+    let mut failed = Vec::new();
+    for order in orders {
+        let txns = order.txns().into_iter().cloned().collect();
+        let bundle = match strategy.create_bundle(txns, order.reverting_tx_hashes()) {
+            Ok(bundle) => bundle,
+            Err(err) => {
+                glog::info!("order {:?} fails on the parent state: {}", order.id(), err);
+                failed.push(order.id());
+                continue;
+            }
+        };
+        if let Err(err) = strategy.add_bundle(format!("{:?}", order.id()), bundle) {
+            glog::info!("order {:?} left out: {}", order.id(), err);
+        }
+    }
+    let block = strategy.get_block()?;
+    Ok((strategy.get_bid(), block, failed))
+}
+
+struct SealedOrder {
+    block_number: Option<u64>, // bundles target a single block, plain transactions stay until included
+    sender: SH160,
+    txn_hashes: Vec<SH256>,
+    msg: Aes128EncryptedMsg,
+}
+
+// orders are kept encrypted under a key generated inside the enclave and never exported
+// the pool holds at most MAX_MEMPOOL_ORDERS orders, and MAX_ORDERS_PER_SENDER of each sender
+pub struct EncryptedMempool {
+    key: Aes128Key,
+    orders: BTreeMap<SH256, SealedOrder>,
+}
+
+impl EncryptedMempool {
+    pub fn new() -> Self {
+        let mut key = [0_u8; 16];
+        crypto::read_rand(&mut key);
+        Self::with_key(key)
+    }
+
+    // e.g. a key derived from the attested encryption key of the enclave
+    pub fn with_key(key: [u8; 16]) -> Self {
+        Self {
+            key: Aes128Key::from(key),
+            orders: BTreeMap::new(),
+        }
+    }
+
+    // `sender` is the one returned by MempoolOrder::verify, returns false if the order is already in the pool
+    pub fn insert(&mut self, order: &MempoolOrder, sender: SH160) -> Result<bool, MevBooTeeError> {
+        let id = order.id();
+        if self.orders.contains_key(&id) {
+            return Ok(false);
+        }
+        if self.orders.len() >= MAX_MEMPOOL_ORDERS {
+            return Err(MevBooTeeError::FullTeeBuilder("the mempool is full".into()));
+        }
+        if self.orders.values().filter(|order| order.sender == sender).count() >= MAX_ORDERS_PER_SENDER {
+            return Err(MevBooTeeError::FullTeeBuilder(format!("sender {:?} has {} orders in the mempool already", sender, MAX_ORDERS_PER_SENDER)));
+        }
+        let plain = serde_json::to_vec(order).map_err(|err| MevBooTeeError::FullTeeBuilder(format!("{:?}", err)))?;
+        let block_number = match order {
+            MempoolOrder::Transaction(_) => None,
+            MempoolOrder::Bundle { block_number, .. } => Some(*block_number),
+        };
+        self.orders.insert(id, SealedOrder {
+            block_number,
+            sender,
+            txn_hashes: order.txns().iter().map(|txn| txn.hash).collect(),
+            msg: self.key.encrypt(&plain),
+        });
+        Ok(true)
+    }
+
+    // decrypt every order that can go into `block_number`
+    pub fn orders_for(&self, block_number: u64) -> Result<Vec<MempoolOrder>, MevBooTeeError> {
+        self.orders.values()
+            .filter(|order| order.block_number.map(|n| n == block_number).unwrap_or(true))
+            .map(|order| {
+                let plain = self.key.decrypt(&order.msg)
+                    .map_err(|err| MevBooTeeError::FullTeeBuilder(format!("corrupted mempool entry: {:?}", err)))?;
+                serde_json::from_slice(&plain).map_err(|err| MevBooTeeError::FullTeeBuilder(format!("{:?}", err)))
+            })
+            .collect()
+    }
+
+    // drop the orders that landed on chain with `block`
+    pub fn remove_included(&mut self, block: &Block) {
+        let included: BTreeSet<SH256> = block.transactions.iter().map(|txn| txn.hash).collect();
+        self.orders.retain(|_, order| !order.txn_hashes.iter().any(|hash| included.contains(hash)));
+    }
+
+    // drop the orders failing on the parent state of the block being built, see build_full_block_with
+    pub fn evict(&mut self, ids: &[SH256]) {
+        for id in ids {
+            self.orders.remove(id);
+        }
+    }
+
+    // drop the bundles targeting a block before `block_number`
+    pub fn prune(&mut self, block_number: u64) {
+        self.orders.retain(|_, order| order.block_number.map(|n| n >= block_number).unwrap_or(true));
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+}
+
+pub struct FullTeeSlot {
+    pub best: Option<(SU256, Block)>,
+    pub delivered: Option<SH256>, // once the header is out, the block of the slot is frozen
+}
+
+pub struct FullTeeBuilder {
+    pub mempool: EncryptedMempool,
+    slots: BTreeMap<u64, FullTeeSlot>,
+}
+
+impl FullTeeBuilder {
+    pub fn new() -> Self {
+        Self {
+            mempool: EncryptedMempool::new(),
+            slots: BTreeMap::new(),
+        }
+    }
+
+    // record the latest block built for `block_number`, returns false if the slot is already sealed
+    pub fn update_best(&mut self, block_number: u64, value: SU256, block: Block) -> bool {
+        let slot = self.slots.entry(block_number).or_insert_with(|| FullTeeSlot { best: None, delivered: None });
+        if slot.delivered.is_some() {
+            return false;
+        }
+        slot.best = Some((value, block));
+        true
+    }
+
+    // hand the header of the best block to the proposer, this seals the slot
+    pub fn deliver(&mut self, block_number: u64) -> Result<(SU256, BlockHeader), MevBooTeeError> {
+        let (value, block) = self.slots.get_mut(&block_number)
+            .and_then(|slot| {
+                if let Some((_, block)) = &slot.best {
+                    slot.delivered = Some(block.header.hash());
+                }
+                slot.best.as_ref()
+            })
+            .ok_or_else(|| MevBooTeeError::FullTeeBuilder(format!("no block for {}", block_number)))?;
+        Ok((*value, block.header.clone()))
+    }
+
+    pub fn is_sealed(&self, block_number: u64) -> bool {
+        self.slots.get(&block_number).map(|slot| slot.delivered.is_some()).unwrap_or(false)
+    }
+
+    // the full block behind a header that has been delivered to the proposer
+    pub fn delivered_block(&self, header: &BlockHeader) -> Result<Block, MevBooTeeError> {
+        let hash = header.hash();
+        match self.slots.get(&header.number.as_u64()) {
+            Some(FullTeeSlot { best: Some((_, block)), delivered: Some(delivered) }) if *delivered == hash => Ok(block.clone()),
+            _ => Err(MevBooTeeError::FullTeeBuilder("Unknown header".into())),
+        }
+    }
+
+    // drop every slot and bundle before `block_number`
+    pub fn prune(&mut self, block_number: u64) {
+        self.slots = self.slots.split_off(&block_number);
+        self.mempool.prune(block_number);
+    }
+
+    // after a reorg the block of the open slot is dropped if it was built on another parent, even once delivered
+    pub fn rebase(&mut self, head: &ChainHead) {
+        let stale = match self.slots.get(&head.open_slot()) {
+            Some(FullTeeSlot { best: Some((_, block)), .. }) => !head.is_candidate(block),
+            _ => false,
+        };
+        if stale {
+            self.slots.remove(&head.open_slot());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txn(sender: u8, nonce: u64) -> MempoolOrder {
+        let mut txn = Transaction::default();
+        txn.nonce = nonce.into();
+        let mut hash = [sender; 32];
+        hash[24..].copy_from_slice(&nonce.to_be_bytes());
+        txn.hash = SH256::from(hash);
+        MempoolOrder::Transaction(txn)
+    }
+
+    fn sender(n: u8) -> SH160 {
+        SH160::from([n; 20])
+    }
+
+    #[test]
+    fn test_mempool_orders() {
+        let mut pool = EncryptedMempool::with_key([1; 16]);
+        let bundle = MempoolOrder::Bundle { block_number: 10, txns: vec![txn(2, 0).txns()[0].clone()], reverting_tx_hashes: Vec::new() };
+        assert!(pool.insert(&txn(1, 0), sender(1)).unwrap());
+        assert!(!pool.insert(&txn(1, 0), sender(1)).unwrap());
+        assert!(pool.insert(&bundle, sender(2)).unwrap());
+        assert_eq!(pool.orders_for(10).unwrap().len(), 2);
+        assert_eq!(pool.orders_for(11).unwrap().len(), 1);
+
+        pool.prune(11);
+        assert_eq!(pool.len(), 1);
+        let mut block = Block::default();
+        block.transactions.push(txn(1, 0).txns()[0].clone());
+        pool.remove_included(&block);
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn test_mempool_limits() {
+        let mut pool = EncryptedMempool::with_key([1; 16]);
+        for nonce in 0..MAX_ORDERS_PER_SENDER as u64 {
+            pool.insert(&txn(1, nonce), sender(1)).unwrap();
+        }
+        assert!(pool.insert(&txn(1, MAX_ORDERS_PER_SENDER as u64), sender(1)).is_err());
+        // the limit is per sender
+        assert!(pool.insert(&txn(2, 0), sender(2)).unwrap());
+
+        for i in pool.len()..MAX_MEMPOOL_ORDERS {
+            let mut address = [3_u8; 20];
+            address[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            pool.insert(&txn(3, i as u64), SH160::from(address)).unwrap();
+        }
+        assert!(pool.insert(&txn(255, 0), sender(255)).is_err());
+    }
+
+    #[test]
+    fn test_mempool_evict() {
+        let mut pool = EncryptedMempool::with_key([1; 16]);
+        pool.insert(&txn(1, 0), sender(1)).unwrap();
+        pool.insert(&txn(2, 0), sender(2)).unwrap();
+        pool.evict(&[txn(1, 0).id()]);
+        let orders = pool.orders_for(1).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].id(), txn(2, 0).id());
+        // the evicted sender may send again
+        assert!(pool.insert(&txn(1, 0), sender(1)).unwrap());
+    }
+}
@@ -0,0 +1,158 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeMap;
+
+use eth_types::{Block, BlockHeader, SH256};
+
+// how many canonical hashes are kept to recognize a reorg
+const RECENT_BLOCKS: u64 = 64;
+
+// a block of the canonical chain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChainHead {
+    pub number: u64,
+    pub hash: SH256,
+    pub parent_hash: SH256,
+    pub timestamp: u64,
+}
+
+impl ChainHead {
+    pub fn from_header(header: &BlockHeader) -> Self {
+        Self {
+            number: header.number.as_u64(),
+            hash: header.hash(),
+            parent_hash: header.parent_hash,
+            timestamp: header.timestamp.as_u64(),
+        }
+    }
+
+    // the slot whose auction is open
+    pub fn open_slot(&self) -> u64 {
+        self.number + 1
+    }
+
+    // whether `block` can still land on top of this head, in the open slot or a later one
+    pub fn is_candidate(&self, block: &Block) -> bool {
+        let number = block.header.number.as_u64();
+        number > self.open_slot() || (number == self.open_slot() && block.header.parent_hash == self.hash)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeadEvent {
+    pub head: ChainHead,
+    pub previous: Option<ChainHead>,
+    pub reorg_from: Option<u64>, // the first block which is no longer canonical
+}
+
+impl HeadEvent {
+    // the slots which landed on chain and are sealed by this head
+    pub fn sealed(&self) -> std::ops::RangeInclusive<u64> {
+        let first = match (&self.previous, self.reorg_from) {
+            (_, Some(from)) => from,
+            (Some(previous), None) => previous.open_slot(),
+            (None, None) => self.head.number,
+        };
+        first..=self.head.number
+    }
+}
+
+// the recent canonical chain, fed with the latest head of the execution client
+#[derive(Default)]
+pub struct HeadTracker {
+    head: Option<ChainHead>,
+    recent: BTreeMap<u64, SH256>,
+}
+
+impl HeadTracker {
+    pub fn head(&self) -> Option<&ChainHead> {
+        self.head.as_ref()
+    }
+
+    pub fn open_slot(&self) -> Option<u64> {
+        self.head.map(|head| head.open_slot())
+    }
+
+    // returns None when `head` is already known
+    pub fn update(&mut self, head: ChainHead) -> Option<HeadEvent> {
+        let previous = self.head;
+        if previous.map(|previous| previous.hash == head.hash).unwrap_or(false) {
+            return None;
+        }
+
+        let mut reorg_from = None;
+        if let Some(previous) = &previous {
+            // a head at or below the previous one replaces it
+            if head.number <= previous.number {
+                reorg_from = Some(head.number);
+            }
+        }
+        if let Some(parent) = head.number.checked_sub(1) {
+            if let Some(known) = self.recent.get(&parent) {
+                if *known != head.parent_hash {
+                    reorg_from = Some(parent);
+                }
+            }
+            if reorg_from.is_some() || !self.recent.contains_key(&parent) {
+                self.recent.retain(|number, _| *number < parent);
+                self.recent.insert(parent, head.parent_hash);
+            }
+        }
+        self.recent.retain(|number, _| *number < head.number);
+        self.recent.insert(head.number, head.hash);
+        self.recent = self.recent.split_off(&head.number.saturating_sub(RECENT_BLOCKS));
+
+        self.head = Some(head);
+        Some(HeadEvent { head, previous, reorg_from })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u64, fork: u8) -> ChainHead {
+        let hash = |number: u64, fork: u8| {
+            let mut hash = [0; 32];
+            hash[..8].copy_from_slice(&number.to_be_bytes());
+            hash[31] = fork;
+            SH256::from(hash)
+        };
+        ChainHead { number, hash: hash(number, fork), parent_hash: hash(number - 1, fork), timestamp: number * 12 }
+    }
+
+    #[test]
+    fn test_new_heads() {
+        let mut tracker = HeadTracker::default();
+        let event = tracker.update(block(10, 0)).unwrap();
+        assert_eq!(event.reorg_from, None);
+        assert_eq!(event.sealed(), 10..=10);
+        assert!(tracker.update(block(10, 0)).is_none());
+
+        // a missed head seals every slot in between
+        let event = tracker.update(block(12, 0)).unwrap();
+        assert_eq!(event.reorg_from, None);
+        assert_eq!(event.sealed(), 11..=12);
+        assert_eq!(tracker.open_slot(), Some(13));
+    }
+
+    #[test]
+    fn test_reorg() {
+        let mut tracker = HeadTracker::default();
+        tracker.update(block(10, 0));
+        tracker.update(block(11, 0));
+
+        // same height, another parent
+        let event = tracker.update(block(11, 1)).unwrap();
+        assert_eq!(event.reorg_from, Some(10));
+        assert_eq!(event.sealed(), 10..=11);
+
+        // back to a lower head
+        let mut lower = block(10, 2);
+        lower.parent_hash = block(9, 0).hash;
+        tracker.update(block(10, 0));
+        let event = tracker.update(lower).unwrap();
+        assert_eq!(event.reorg_from, Some(10));
+        assert_eq!(tracker.open_slot(), Some(11));
+    }
+}
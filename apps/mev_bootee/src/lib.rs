@@ -13,5 +13,71 @@ pub use app::*;
 mod types;
 pub use types::*;
 
+mod config;
+pub use config::*;
+
 mod  apis;
 pub use apis::*;
+
+mod dispatcher;
+pub use dispatcher::*;
+
+mod tx_validation;
+pub use tx_validation::*;
+
+mod simulation;
+pub use simulation::*;
+
+mod state_access;
+pub use state_access::*;
+
+mod beacon;
+pub use beacon::*;
+
+mod proposer;
+pub use proposer::*;
+
+mod publisher;
+pub use publisher::*;
+
+mod head_tracker;
+pub use head_tracker::*;
+
+mod payload_attributes;
+pub use payload_attributes::*;
+
+mod blobs;
+pub use blobs::*;
+
+mod bundle;
+pub use bundle::*;
+
+mod enclave_key;
+pub use enclave_key::*;
+
+mod attestation;
+pub use attestation::*;
+
+mod auction;
+pub use auction::*;
+
+mod transcript;
+pub use transcript::*;
+
+mod bundle_pool;
+pub use bundle_pool::*;
+
+mod block_building;
+pub use block_building::*;
+
+mod bundle_merging;
+pub use bundle_merging::*;
+
+mod proposer_aide;
+pub use proposer_aide::*;
+
+mod builder_aide;
+pub use builder_aide::*;
+
+mod full_builder;
+pub use full_builder::*;
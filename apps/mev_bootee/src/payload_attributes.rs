@@ -0,0 +1,321 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeMap;
+
+use eth_types::{BlockHeader, HexBytes, Withdrawal, SH160, SH256};
+use evm_executor::ConsensusBlockInfo;
+use serde::{Deserialize, Serialize};
+
+use crate::{verify_bls, BeaconClient, BeaconResponse, MevBooTeeError, ProposerAuthenticator};
+
+const GAS_LIMIT_BOUND_DIVISOR: u64 = 1024;
+const MIN_GAS_LIMIT: u64 = 5000;
+
+// the consensus side of the payload of `block_number`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PayloadAttributes {
+    pub block_number: u64,
+    pub parent_hash: SH256,
+    pub timestamp: u64,
+    pub prev_randao: SH256,
+    pub suggested_fee_recipient: SH160,
+    #[serde(default)]
+    pub withdrawals: Option<Vec<Withdrawal>>, // credited after the transactions, unknown when missing
+    #[serde(default)]
+    pub parent_beacon_block_root: Option<SH256>, // from Cancun on
+}
+
+// payload attributes sent by the proposer itself, for when no beacon node is available
+#[derive(Clone, Debug, Deserialize)]
+pub struct SignedPayloadAttributes {
+    pub attributes: PayloadAttributes,
+    pub signature: HexBytes,
+}
+
+impl SignedPayloadAttributes {
+    pub fn signing_root(&self) -> SH256 {
+        let attributes = &self.attributes;
+        let mut data = b"mev-bootee/payload_attributes".to_vec();
+        data.extend_from_slice(&attributes.block_number.to_be_bytes());
+        data.extend_from_slice(attributes.parent_hash.as_bytes());
+        data.extend_from_slice(&attributes.timestamp.to_be_bytes());
+        data.extend_from_slice(attributes.prev_randao.as_bytes());
+        data.extend_from_slice(attributes.suggested_fee_recipient.as_bytes());
+        for withdrawal in attributes.withdrawals.iter().flatten() {
+            data.extend_from_slice(&withdrawal.index.as_u64().to_be_bytes());
+            data.extend_from_slice(&withdrawal.validator_index.as_u64().to_be_bytes());
+            data.extend_from_slice(withdrawal.address.as_bytes());
+            data.extend_from_slice(&withdrawal.amount.as_u64().to_be_bytes());
+        }
+        if let Some(root) = &attributes.parent_beacon_block_root {
+            data.extend_from_slice(root.as_bytes());
+        }
+        SH256::from(crypto::keccak_hash(&data))
+    }
+
+    pub fn validate_sender(&self, auth: &ProposerAuthenticator) -> Result<(), MevBooTeeError> {
+        auth.verify_request(self.attributes.block_number, self.signing_root(), &self.signature)
+    }
+
+    pub fn mark_served(&self, auth: &ProposerAuthenticator) {
+        auth.mark_served(self.attributes.block_number, self.signing_root())
+    }
+}
+
+// the preferences of a validator for the blocks it proposes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ValidatorRegistration {
+    pub fee_recipient: SH160,
+    pub gas_limit: u64, // target gas limit
+    pub timestamp: u64,
+    pub pubkey: HexBytes,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SignedValidatorRegistration {
+    pub message: ValidatorRegistration,
+    pub signature: HexBytes,
+}
+
+impl SignedValidatorRegistration {
+    pub fn signing_root(&self) -> SH256 {
+        let message = &self.message;
+        let mut data = b"mev-bootee/register_validator".to_vec();
+        data.extend_from_slice(message.fee_recipient.as_bytes());
+        data.extend_from_slice(&message.gas_limit.to_be_bytes());
+        data.extend_from_slice(&message.timestamp.to_be_bytes());
+        data.extend_from_slice(&message.pubkey);
+        SH256::from(crypto::keccak_hash(&data))
+    }
+
+    // signed by the registered validator key
+    pub fn verify(&self) -> Result<(), MevBooTeeError> {
+        verify_bls(&self.message.pubkey, self.signing_root().as_bytes(), &self.signature)
+    }
+}
+
+// payload attributes per block and the latest registration of every validator
+#[derive(Default)]
+pub struct PayloadAttributesStore {
+    attributes: BTreeMap<u64, PayloadAttributes>,
+    registrations: BTreeMap<Vec<u8>, ValidatorRegistration>,
+}
+
+impl PayloadAttributesStore {
+    pub fn insert(&mut self, attributes: PayloadAttributes) {
+        self.attributes.insert(attributes.block_number, attributes);
+    }
+
+    // returns false when a more recent registration is already known
+    pub fn register(&mut self, registration: ValidatorRegistration) -> bool {
+        let pubkey = registration.pubkey.to_vec();
+        if let Some(known) = self.registrations.get(&pubkey) {
+            if known.timestamp >= registration.timestamp {
+                return false;
+            }
+        }
+        self.registrations.insert(pubkey, registration);
+        true
+    }
+
+    pub fn get(&self, block_number: u64) -> Option<&PayloadAttributes> {
+        self.attributes.get(&block_number)
+    }
+
+    pub fn parent_beacon_block_root(&self, block_number: u64) -> Option<SH256> {
+        self.get(block_number).and_then(|attributes| attributes.parent_beacon_block_root)
+    }
+
+    pub fn withdrawals(&self, block_number: u64) -> Option<Vec<Withdrawal>> {
+        self.get(block_number).and_then(|attributes| attributes.withdrawals.clone())
+    }
+
+    pub fn registration(&self, pubkey: &[u8]) -> Option<&ValidatorRegistration> {
+        self.registrations.get(pubkey)
+    }
+
+    // where the block of `block_number` has to pay the proposer `pubkey`
+    pub fn fee_recipient(&self, block_number: u64, pubkey: &[u8]) -> Option<SH160> {
+        match self.registration(pubkey) {
+            Some(registration) => Some(registration.fee_recipient),
+            None => self.get(block_number).map(|attributes| attributes.suggested_fee_recipient),
+        }
+    }
+
+    // the consensus fields of the block built on `parent` for the proposer `pubkey`
+    pub fn consensus_info(&self, parent: &BlockHeader, pubkey: &[u8], extra: &[u8]) -> Result<ConsensusBlockInfo, MevBooTeeError> {
+        let block_number = parent.number.as_u64() + 1;
+        let attributes = self.get(block_number)
+            .ok_or_else(|| MevBooTeeError::Proposer(format!("no payload attributes for block {}", block_number)))?;
+        if attributes.parent_hash != parent.hash() {
+            return Err(MevBooTeeError::Proposer(format!(
+                "payload attributes of block {} built on {:?}, not on {:?}", block_number, attributes.parent_hash, parent.hash(),
+            )));
+        }
+        let registration = self.registration(pubkey);
+        let parent_gas_limit = parent.gas_limit.as_u64();
+        let target_gas_limit = registration.map(|r| r.gas_limit).unwrap_or(parent_gas_limit);
+        Ok(ConsensusBlockInfo {
+            gas_limit: next_gas_limit(parent_gas_limit, target_gas_limit).into(),
+            timestamp: attributes.timestamp,
+            random: attributes.prev_randao,
+            extra: extra.to_vec().into(),
+            coinbase: registration.map(|r| r.fee_recipient).unwrap_or(attributes.suggested_fee_recipient),
+        })
+    }
+
+    // drop the attributes of every block before `block_number`
+    pub fn prune(&mut self, block_number: u64) {
+        self.attributes = self.attributes.split_off(&block_number);
+    }
+}
+
+// move the gas limit towards `target`, by less than 1/1024 of the parent gas limit
+pub fn next_gas_limit(parent: u64, target: u64) -> u64 {
+    let delta = (parent / GAS_LIMIT_BOUND_DIVISOR).saturating_sub(1);
+    let target = target.max(MIN_GAS_LIMIT);
+    if parent < target {
+        (parent + delta).min(target)
+    } else {
+        parent.saturating_sub(delta).max(target)
+    }
+}
+
+#[derive(Deserialize)]
+struct PayloadAttributesEvent {
+    data: PayloadAttributesEventData,
+}
+
+#[derive(Deserialize)]
+struct PayloadAttributesEventData {
+    parent_block_number: String,
+    parent_block_hash: SH256,
+    payload_attributes: BeaconPayloadAttributes,
+}
+
+#[derive(Deserialize)]
+struct BeaconPayloadAttributes {
+    timestamp: String,
+    prev_randao: SH256,
+    suggested_fee_recipient: SH160,
+    #[serde(default)]
+    withdrawals: Option<Vec<BeaconWithdrawal>>,
+    #[serde(default)]
+    parent_beacon_block_root: Option<SH256>,
+}
+
+// a withdrawal as the beacon API encodes it, with decimal numbers
+#[derive(Deserialize)]
+struct BeaconWithdrawal {
+    index: String,
+    validator_index: String,
+    address: SH160,
+    amount: String, // gwei
+}
+
+impl BeaconWithdrawal {
+    fn into_withdrawal(self) -> Result<Withdrawal, MevBooTeeError> {
+        Ok(Withdrawal {
+            index: parse_number("withdrawal index", &self.index)?.into(),
+            validator_index: parse_number("validator index", &self.validator_index)?.into(),
+            address: self.address,
+            amount: parse_number("withdrawal amount", &self.amount)?.into(),
+        })
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u64, MevBooTeeError> {
+    value.parse().map_err(|_| MevBooTeeError::Beacon(format!("invalid {} {:?}", name, value)))
+}
+
+fn into_withdrawals(withdrawals: Vec<BeaconWithdrawal>) -> Result<Vec<Withdrawal>, MevBooTeeError> {
+    withdrawals.into_iter().map(BeaconWithdrawal::into_withdrawal).collect()
+}
+
+// the withdrawals of the block proposed at `slot` on top of the beacon head, for when the payload attributes do not carry them
+pub fn fetch_expected_withdrawals(beacon: &BeaconClient, slot: u64) -> Result<Vec<Withdrawal>, MevBooTeeError> {
+    let path = format!("/eth/v1/builder/states/head/expected_withdrawals?proposal_slot={}", slot);
+    let resp: BeaconResponse<Vec<BeaconWithdrawal>> = beacon.get(&path)?;
+    into_withdrawals(resp.data)
+}
+
+// the data of a `payload_attributes` event of the beacon node event stream
+pub fn parse_payload_attributes_event(data: &str) -> Result<PayloadAttributes, MevBooTeeError> {
+    let event: PayloadAttributesEvent = serde_json::from_str(data)
+        .map_err(|err| MevBooTeeError::Beacon(format!("invalid payload_attributes event: {}", err)))?;
+    let event = event.data;
+    let attributes = event.payload_attributes;
+    Ok(PayloadAttributes {
+        block_number: parse_number("parent_block_number", &event.parent_block_number)? + 1,
+        parent_hash: event.parent_block_hash,
+        timestamp: parse_number("timestamp", &attributes.timestamp)?,
+        prev_randao: attributes.prev_randao,
+        suggested_fee_recipient: attributes.suggested_fee_recipient,
+        withdrawals: attributes.withdrawals.map(into_withdrawals).transpose()?,
+        parent_beacon_block_root: attributes.parent_beacon_block_root,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_gas_limit() {
+        assert_eq!(next_gas_limit(30_000_000, 30_000_000), 30_000_000);
+        // 30M / 1024 - 1 = 29295
+        assert_eq!(next_gas_limit(30_000_000, 36_000_000), 30_029_295);
+        assert_eq!(next_gas_limit(30_000_000, 29_990_000), 29_990_000);
+        assert_eq!(next_gas_limit(30_000_000, 0), 29_970_705);
+        assert_eq!(next_gas_limit(5000, 0), 5000);
+    }
+
+    #[test]
+    fn test_register() {
+        let registration = |timestamp, gas_limit| ValidatorRegistration {
+            fee_recipient: SH160::default(),
+            gas_limit,
+            timestamp,
+            pubkey: vec![1; 48].into(),
+        };
+        let mut store = PayloadAttributesStore::default();
+        assert!(store.register(registration(10, 30_000_000)));
+        assert!(!store.register(registration(9, 36_000_000)));
+        assert!(store.register(registration(11, 36_000_000)));
+        assert_eq!(store.registration(&[1; 48]).unwrap().gas_limit, 36_000_000);
+    }
+
+    #[test]
+    fn test_parse_event() {
+        let data = r#"{"version": "capella", "data": {
+            "proposer_index": "123", "proposal_slot": "10", "parent_block_number": "9",
+            "parent_block_root": "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "parent_block_hash": "0x0000000000000000000000000000000000000000000000000000000000000002",
+            "payload_attributes": {
+                "timestamp": "1700000000",
+                "prev_randao": "0x0000000000000000000000000000000000000000000000000000000000000003",
+                "suggested_fee_recipient": "0x0000000000000000000000000000000000000004",
+                "withdrawals": [{"index": "7", "validator_index": "42", "address": "0x0000000000000000000000000000000000000005", "amount": "1000"}]
+            }
+        }}"#;
+        let attributes = parse_payload_attributes_event(data).unwrap();
+        assert_eq!(attributes.block_number, 10);
+        assert_eq!(attributes.timestamp, 1700000000);
+        let withdrawals = attributes.withdrawals.unwrap();
+        assert_eq!(withdrawals.len(), 1);
+        assert_eq!(withdrawals[0].validator_index.as_u64(), 42);
+        assert_eq!(withdrawals[0].amount.as_u64(), 1000);
+        assert_eq!(attributes.parent_beacon_block_root, None);
+        assert!(parse_payload_attributes_event("{}").is_err());
+
+        // from Cancun on the attributes carry the parent beacon block root
+        let data = data.replace("capella", "deneb").replace(
+            r#""withdrawals": ["#,
+            r#""parent_beacon_block_root": "0x0000000000000000000000000000000000000000000000000000000000000006", "withdrawals": ["#,
+        );
+        let attributes = parse_payload_attributes_event(&data).unwrap();
+        let mut root = [0_u8; 32];
+        root[31] = 6;
+        assert_eq!(attributes.parent_beacon_block_root, Some(SH256::from(root)));
+    }
+}
@@ -0,0 +1,350 @@
+use std::prelude::v1::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+use blst::min_pk::{PublicKey, Signature};
+use blst::BLST_ERROR;
+use eth_types::{HexBytes, SH256};
+use serde::Deserialize;
+
+use crate::{unix_timestamp, BeaconClient, BeaconResponse, ElClient, MevBooTeeError, SECONDS_PER_SLOT};
+
+// domain separation tag of the ethereum consensus BLS signatures
+pub(crate) const BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+const SLOTS_PER_EPOCH: u64 = 32;
+
+// tells who is expected to propose a given block
+pub trait ProposerDuties: Send + Sync {
+    // the BLS public key of the validator proposing `block_number`, `timestamp` is the one of the block when known
+    fn proposer_pubkey(&self, block_number: u64, timestamp: Option<u64>) -> Result<Vec<u8>, MevBooTeeError>;
+}
+
+#[derive(Deserialize)]
+struct DutyEntry {
+    block_number: u64,
+    pubkey: HexBytes,
+}
+
+// duties supplied by the operator: a json list of {"block_number": .., "pubkey": "0x.."}
+pub struct DutyFile {
+    duties: BTreeMap<u64, Vec<u8>>,
+}
+
+impl DutyFile {
+    pub fn from_file(path: &str) -> Result<Self, MevBooTeeError> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|err| MevBooTeeError::Config(format!("unable to read duty file {}: {}", path, err)))?;
+        Self::from_json(&data)
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, MevBooTeeError> {
+        let entries: Vec<DutyEntry> = serde_json::from_str(data)
+            .map_err(|err| MevBooTeeError::Config(format!("invalid duty file: {}", err)))?;
+        Ok(Self {
+            duties: entries.into_iter().map(|entry| (entry.block_number, entry.pubkey.to_vec())).collect(),
+        })
+    }
+}
+
+impl ProposerDuties for DutyFile {
+    fn proposer_pubkey(&self, block_number: u64, _timestamp: Option<u64>) -> Result<Vec<u8>, MevBooTeeError> {
+        self.duties.get(&block_number)
+            .cloned()
+            .ok_or_else(|| MevBooTeeError::Proposer(format!("no proposer duty for block {}", block_number)))
+    }
+}
+
+#[derive(Deserialize)]
+struct BeaconHeader {
+    header: BeaconSignedHeader,
+}
+
+#[derive(Deserialize)]
+struct BeaconSignedHeader {
+    message: BeaconHeaderMessage,
+}
+
+#[derive(Deserialize)]
+struct BeaconHeaderMessage {
+    slot: String,
+}
+
+#[derive(Deserialize)]
+struct Genesis {
+    genesis_time: String,
+}
+
+#[derive(Deserialize)]
+struct ProposerDuty {
+    pubkey: HexBytes,
+    slot: String,
+}
+
+pub fn genesis_time(beacon: &BeaconClient) -> Result<u64, MevBooTeeError> {
+    let genesis: BeaconResponse<Genesis> = beacon.get("/eth/v1/beacon/genesis")?;
+    genesis.data.genesis_time.parse()
+        .map_err(|_| MevBooTeeError::Beacon(format!("invalid genesis time {:?}", genesis.data.genesis_time)))
+}
+
+// the slot starting at `timestamp`
+pub fn slot_at(genesis_time: u64, timestamp: u64) -> Result<u64, MevBooTeeError> {
+    match timestamp.checked_sub(genesis_time) {
+        Some(elapsed) => Ok(elapsed / SECONDS_PER_SLOT),
+        None => Err(MevBooTeeError::Proposer(format!("timestamp {} is before genesis", timestamp))),
+    }
+}
+
+// the slot of the block following the head at `head_slot` when its timestamp is not known yet:
+// slots may be missed in between, but the block is not proposed before the current slot
+pub fn next_proposal_slot(genesis_time: u64, head_slot: u64, now: u64) -> u64 {
+    let current = slot_at(genesis_time, now).unwrap_or_default();
+    current.max(head_slot + 1)
+}
+
+// the slot of `block_number` from its timestamp, or else from the wall clock for the block following the head
+pub fn proposal_slot(beacon: &BeaconClient, el: &ElClient, genesis_time: u64, block_number: u64, timestamp: Option<u64>) -> Result<u64, MevBooTeeError> {
+    if let Some(timestamp) = timestamp {
+        return slot_at(genesis_time, timestamp);
+    }
+    let head_number = el.head()
+        .map_err(|err| MevBooTeeError::Execution(format!("unable to fetch chain head: {:?}", err)))?;
+    if block_number != head_number + 1 {
+        return Err(MevBooTeeError::Proposer(format!("slot of block {} unknown, the head is {}", block_number, head_number)));
+    }
+    let head: BeaconResponse<BeaconHeader> = beacon.get("/eth/v1/beacon/headers/head")?;
+    let head_slot: u64 = head.data.header.message.slot.parse()
+        .map_err(|_| MevBooTeeError::Beacon(format!("invalid slot {:?}", head.data.header.message.slot)))?;
+    Ok(next_proposal_slot(genesis_time, head_slot, unix_timestamp()))
+}
+
+// duties fetched from a beacon node, cached per epoch
+// the slot of a block is derived from its timestamp and the genesis time, see `proposal_slot`
+pub struct BeaconDuties {
+    beacon: BeaconClient,
+    el: ElClient,
+    genesis_time: Mutex<Option<u64>>,
+    epochs: Mutex<BTreeMap<u64, BTreeMap<u64, Vec<u8>>>>, // epoch -> slot -> pubkey
+}
+
+impl BeaconDuties {
+    pub fn new(beacon: BeaconClient, el: ElClient) -> Self {
+        Self {
+            beacon,
+            el,
+            genesis_time: Mutex::new(None),
+            epochs: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn genesis_time(&self) -> Result<u64, MevBooTeeError> {
+        let mut cached = self.genesis_time.lock().unwrap();
+        if let Some(genesis_time) = *cached {
+            return Ok(genesis_time);
+        }
+        let genesis_time = genesis_time(&self.beacon)?;
+        *cached = Some(genesis_time);
+        Ok(genesis_time)
+    }
+
+    fn epoch_duties(&self, epoch: u64) -> Result<BTreeMap<u64, Vec<u8>>, MevBooTeeError> {
+        if let Some(duties) = self.epochs.lock().unwrap().get(&epoch) {
+            return Ok(duties.clone());
+        }
+        let resp: BeaconResponse<Vec<ProposerDuty>> = self.beacon.get(&format!("/eth/v1/validator/duties/proposer/{}", epoch))?;
+        let mut duties = BTreeMap::new();
+        for duty in resp.data {
+            let slot = duty.slot.parse().map_err(|_| MevBooTeeError::Beacon(format!("invalid slot {:?}", duty.slot)))?;
+            duties.insert(slot, duty.pubkey.to_vec());
+        }
+        let mut epochs = self.epochs.lock().unwrap();
+        epochs.insert(epoch, duties.clone());
+        // keep the current and the next epoch around
+        *epochs = epochs.split_off(&epoch.saturating_sub(1));
+        Ok(duties)
+    }
+}
+
+impl ProposerDuties for BeaconDuties {
+    fn proposer_pubkey(&self, block_number: u64, timestamp: Option<u64>) -> Result<Vec<u8>, MevBooTeeError> {
+        let slot = proposal_slot(&self.beacon, &self.el, self.genesis_time()?, block_number, timestamp)?;
+        self.epoch_duties(slot / SLOTS_PER_EPOCH)?
+            .remove(&slot)
+            .ok_or_else(|| MevBooTeeError::Proposer(format!("no proposer duty for slot {}", slot)))
+    }
+}
+
+// remembers what the proposer of every slot already signed
+#[derive(Default)]
+pub struct ReplayGuard {
+    bid_requests: BTreeMap<u64, BTreeSet<SH256>>, // signing roots of the bid requests already served successfully
+    commits: BTreeMap<u64, SH256>, // the header committed by the proposer
+}
+
+impl ReplayGuard {
+    pub fn check_bid_request(&self, block_number: u64, signing_root: SH256) -> Result<(), MevBooTeeError> {
+        match self.bid_requests.get(&block_number).map(|served| served.contains(&signing_root)).unwrap_or(false) {
+            true => Err(MevBooTeeError::Proposer(format!("replayed request for block {}", block_number))),
+            false => Ok(()),
+        }
+    }
+
+    // only a request which got its answer is recorded, a failed one may be retried as it is
+    pub fn record_bid_request(&mut self, block_number: u64, signing_root: SH256) {
+        self.bid_requests.entry(block_number).or_default().insert(signing_root);
+    }
+
+    // a proposer may retry the commit of its header, but not commit a second one for the same block
+    pub fn check_commit(&mut self, block_number: u64, header_hash: SH256) -> Result<(), MevBooTeeError> {
+        match self.commits.get(&block_number) {
+            Some(committed) if *committed != header_hash => {
+                Err(MevBooTeeError::Proposer(format!("a different header was already committed for block {}", block_number)))
+            },
+            _ => {
+                self.commits.insert(block_number, header_hash);
+                Ok(())
+            },
+        }
+    }
+
+    // drop every slot before `block_number`
+    pub fn prune(&mut self, block_number: u64) {
+        self.bid_requests = self.bid_requests.split_off(&block_number);
+        self.commits = self.commits.split_off(&block_number);
+    }
+}
+
+pub struct ProposerAuthenticator {
+    duties: Box<dyn ProposerDuties>,
+    replay: Mutex<ReplayGuard>,
+    timestamps: Mutex<BTreeMap<u64, u64>>, // block number -> timestamp, as announced by the beacon node
+}
+
+impl ProposerAuthenticator {
+    pub fn new(duties: Box<dyn ProposerDuties>) -> Self {
+        Self {
+            duties,
+            replay: Mutex::new(ReplayGuard::default()),
+            timestamps: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // the timestamp of a block, which places it in its slot. only to be fed by the beacon node
+    pub fn note_timestamp(&self, block_number: u64, timestamp: u64) {
+        self.timestamps.lock().unwrap().insert(block_number, timestamp);
+    }
+
+    // a request signed by the proposer of `block_number`, each signed request is served successfully once
+    pub fn verify_request(&self, block_number: u64, signing_root: SH256, signature: &[u8]) -> Result<(), MevBooTeeError> {
+        self.verify_signature(block_number, signing_root, signature)?;
+        self.replay.lock().unwrap().check_bid_request(block_number, signing_root)
+    }
+
+    // to be called once a request passing `verify_request` got a successful answer
+    pub fn mark_served(&self, block_number: u64, signing_root: SH256) {
+        self.replay.lock().unwrap().record_bid_request(block_number, signing_root);
+    }
+
+    // a header signed by the proposer of its block
+    pub fn verify_header(&self, block_number: u64, header_hash: SH256, signing_root: SH256, signature: &[u8]) -> Result<(), MevBooTeeError> {
+        self.verify_signature(block_number, signing_root, signature)?;
+        self.replay.lock().unwrap().check_commit(block_number, header_hash)
+    }
+
+    pub fn prune(&self, block_number: u64) {
+        self.replay.lock().unwrap().prune(block_number);
+        let mut timestamps = self.timestamps.lock().unwrap();
+        *timestamps = timestamps.split_off(&block_number);
+    }
+
+    pub fn proposer_pubkey(&self, block_number: u64) -> Result<Vec<u8>, MevBooTeeError> {
+        let timestamp = self.timestamps.lock().unwrap().get(&block_number).cloned();
+        self.duties.proposer_pubkey(block_number, timestamp)
+    }
+
+    fn verify_signature(&self, block_number: u64, signing_root: SH256, signature: &[u8]) -> Result<(), MevBooTeeError> {
+        let pubkey = self.proposer_pubkey(block_number)?;
+        verify_bls(&pubkey, signing_root.as_bytes(), signature)
+    }
+}
+
+pub fn verify_bls(pubkey: &[u8], msg: &[u8], signature: &[u8]) -> Result<(), MevBooTeeError> {
+    let pubkey = PublicKey::from_bytes(pubkey)
+        .map_err(|err| MevBooTeeError::Proposer(format!("invalid proposer pubkey: {:?}", err)))?;
+    let signature = Signature::from_bytes(signature)
+        .map_err(|err| MevBooTeeError::Proposer(format!("invalid signature: {:?}", err)))?;
+    match signature.verify(true, msg, BLS_DST, &[], &pubkey, true) {
+        BLST_ERROR::BLST_SUCCESS => Ok(()),
+        err => Err(MevBooTeeError::Proposer(format!("signature verification failed: {:?}", err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blst::min_pk::SecretKey;
+
+    fn keypair(seed: u8) -> (SecretKey, Vec<u8>) {
+        let sk = SecretKey::key_gen(&[seed; 32], &[]).unwrap();
+        let pk = sk.sk_to_pk().to_bytes().to_vec();
+        (sk, pk)
+    }
+
+    fn authenticator(block_number: u64, pubkey: &[u8]) -> ProposerAuthenticator {
+        let mut duties = BTreeMap::new();
+        duties.insert(block_number, pubkey.to_vec());
+        ProposerAuthenticator::new(Box::new(DutyFile { duties }))
+    }
+
+    #[test]
+    fn test_verify_request() {
+        let (sk, pk) = keypair(1);
+        let auth = authenticator(10, &pk);
+        let root = SH256::from([7; 32]);
+        let signature = sk.sign(root.as_bytes(), BLS_DST, &[]).to_bytes();
+
+        assert!(auth.verify_request(10, root, &signature).is_ok());
+        // until it is served, e.g. when building the block failed, the request may be retried
+        assert!(auth.verify_request(10, root, &signature).is_ok());
+        auth.mark_served(10, root);
+        // the same signed request cannot be served twice
+        assert!(auth.verify_request(10, root, &signature).is_err());
+        // not the proposer of block 11
+        assert!(auth.verify_request(11, root, &signature).is_err());
+    }
+
+    #[test]
+    fn test_wrong_proposer() {
+        let (_, pk) = keypair(1);
+        let (other, _) = keypair(2);
+        let auth = authenticator(10, &pk);
+        let root = SH256::from([7; 32]);
+        let signature = other.sign(root.as_bytes(), BLS_DST, &[]).to_bytes();
+        assert!(auth.verify_request(10, root, &signature).is_err());
+    }
+
+    #[test]
+    fn test_proposal_slot() {
+        let genesis = 1_606_824_023;
+        assert_eq!(slot_at(genesis, genesis + 12 * 100).unwrap(), 100);
+        assert_eq!(slot_at(genesis, genesis + 12 * 100 + 11).unwrap(), 100);
+        assert!(slot_at(genesis, genesis - 1).is_err());
+        // the slot after the head was missed, the block lands in the current slot
+        assert_eq!(next_proposal_slot(genesis, 98, genesis + 12 * 100 + 3), 100);
+        // the head is from the current slot
+        assert_eq!(next_proposal_slot(genesis, 100, genesis + 12 * 100 + 3), 101);
+    }
+
+    #[test]
+    fn test_single_commit_per_slot() {
+        let (sk, pk) = keypair(1);
+        let auth = authenticator(10, &pk);
+        let (first, second) = (SH256::from([1; 32]), SH256::from([2; 32]));
+        let sign = |root: SH256| sk.sign(root.as_bytes(), BLS_DST, &[]).to_bytes();
+
+        assert!(auth.verify_header(10, first, first, &sign(first)).is_ok());
+        assert!(auth.verify_header(10, first, first, &sign(first)).is_ok());
+        assert!(auth.verify_header(10, second, second, &sign(second)).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeMap;
+
+use eth_types::{Block, BlockHeader, Transaction, SH256};
+use serde::Serialize;
+
+use crate::{ChainHead, MevBooTeeError};
+
+// PEPC-TEE: the proposer hands us an inclusion list, we build a block that contains it and
+// only release the header. The full block leaves the enclave once the proposer signed the header.
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum ProposerAideStatus {
+    InclusionListReceived,     // waiting for the block to be built
+    HeaderDelivered(SH256),    // header handed to the proposer, waiting for its signature
+    Publishing(SH256),         // signed header received, the block is being broadcast
+    Published(SH256),          // block broadcast to the network
+}
+
+pub struct ProposerAideSlot {
+    pub inclusion_list: Vec<Transaction>,
+    pub block: Option<Block>,
+    pub status: ProposerAideStatus,
+}
+
+#[derive(Default)]
+pub struct ProposerAide {
+    slots: BTreeMap<u64, ProposerAideSlot>,
+}
+
+impl ProposerAide {
+    // register the inclusion list of the proposer for `block_number`
+    // the list can be replaced as long as no header has been released for the slot
+    pub fn open(&mut self, block_number: u64, inclusion_list: Vec<Transaction>) -> Result<(), MevBooTeeError> {
+        if let Some(slot) = self.slots.get(&block_number) {
+            if slot.status != ProposerAideStatus::InclusionListReceived {
+                return Err(MevBooTeeError::ProposerAide(format!("header already released for block {}", block_number)));
+            }
+        }
+        self.slots.insert(block_number, ProposerAideSlot {
+            inclusion_list,
+            block: None,
+            status: ProposerAideStatus::InclusionListReceived,
+        });
+        Ok(())
+    }
+
+    // attach the built block to the slot and release its header
+    // the block must contain every transaction of the inclusion list
+    pub fn deliver(&mut self, block_number: u64, block: Block) -> Result<BlockHeader, MevBooTeeError> {
+        let slot = self.slots.get_mut(&block_number)
+            .ok_or_else(|| MevBooTeeError::ProposerAide(format!("no inclusion list for block {}", block_number)))?;
+        if slot.status != ProposerAideStatus::InclusionListReceived {
+            return Err(MevBooTeeError::ProposerAide(format!("header already released for block {}", block_number)));
+        }
+        if block.header.number.as_u64() != block_number {
+            return Err(MevBooTeeError::ProposerAide(format!("built block {} for slot {}", block.header.number.as_u64(), block_number)));
+        }
+        if let Some(missing) = missing_transaction(&block, &slot.inclusion_list) {
+            return Err(MevBooTeeError::ProposerAide(format!("block does not include txn {:?}", missing)));
+        }
+
+        let header = block.header.clone();
+        slot.status = ProposerAideStatus::HeaderDelivered(header.hash());
+        slot.block = Some(block);
+        Ok(header)
+    }
+
+    // called once the proposer signature over `header` checked out
+    // returns the block to broadcast, or None if it was already published
+    pub fn begin_publish(&mut self, header: &BlockHeader) -> Result<Option<Block>, MevBooTeeError> {
+        let block_number = header.number.as_u64();
+        let hash = header.hash();
+        let slot = self.slots.get_mut(&block_number)
+            .ok_or_else(|| MevBooTeeError::ProposerAide(format!("no block for slot {}", block_number)))?;
+        match &slot.status {
+            ProposerAideStatus::HeaderDelivered(delivered) if *delivered == hash => {
+                slot.status = ProposerAideStatus::Publishing(hash);
+                Ok(slot.block.clone())
+            },
+            ProposerAideStatus::Published(published) if *published == hash => Ok(None),
+            ProposerAideStatus::Publishing(_) => Err(MevBooTeeError::ProposerAide(format!("block {} is being published", block_number))),
+            _ => Err(MevBooTeeError::ProposerAide("Unknown header".into())),
+        }
+    }
+
+    // record the outcome of the broadcast started by `begin_publish`
+    // a failed broadcast puts the slot back so that the proposer can commit again
+    pub fn finish_publish(&mut self, header: &BlockHeader, published: bool) {
+        let hash = header.hash();
+        if let Some(slot) = self.slots.get_mut(&header.number.as_u64()) {
+            if slot.status == ProposerAideStatus::Publishing(hash) {
+                slot.status = match published {
+                    true => ProposerAideStatus::Published(hash),
+                    false => ProposerAideStatus::HeaderDelivered(hash),
+                };
+            }
+        }
+    }
+
+    pub fn status(&self, block_number: u64) -> Option<ProposerAideStatus> {
+        self.slots.get(&block_number).map(|slot| slot.status.clone())
+    }
+
+    // drop every slot before `block_number`
+    pub fn prune(&mut self, block_number: u64) {
+        self.slots = self.slots.split_off(&block_number);
+    }
+
+    // after a reorg the header released for the open slot no longer extends the chain,
+    // the slot goes back to its inclusion list so that the proposer can have the block rebuilt
+    pub fn rebase(&mut self, head: &ChainHead) {
+        if let Some(slot) = self.slots.get_mut(&head.open_slot()) {
+            let stale = slot.block.as_ref().map(|block| !head.is_candidate(block)).unwrap_or(false);
+            if stale && matches!(slot.status, ProposerAideStatus::HeaderDelivered(_)) {
+                slot.block = None;
+                slot.status = ProposerAideStatus::InclusionListReceived;
+            }
+        }
+    }
+}
+
+fn missing_transaction(block: &Block, inclusion_list: &[Transaction]) -> Option<SH256> {
+    inclusion_list.iter()
+        .find(|txn| !block.transactions.iter().any(|included| included.hash == txn.hash))
+        .map(|txn| txn.hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the handlers of MevBooTee drive the proposer aide in app.rs, these check its bookkeeping alone
+
+    fn txn(n: u8) -> Transaction {
+        let mut txn = Transaction::default();
+        txn.hash = SH256::from([n; 32]);
+        txn
+    }
+
+    // the mock blocks are built on the default parent hash
+    fn block(block_number: u64, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::default();
+        block.header.number = block_number.into();
+        block.transactions = transactions;
+        block
+    }
+
+    fn deliver(aide: &mut ProposerAide, block_number: u64, inclusion_list: Vec<Transaction>) -> Result<BlockHeader, MevBooTeeError> {
+        aide.open(block_number, inclusion_list.clone())?;
+        let mut transactions = vec![txn(1)];
+        transactions.extend(inclusion_list);
+        aide.deliver(block_number, block(block_number, transactions))
+    }
+
+    #[test]
+    fn test_block_must_contain_inclusion_list() {
+        let mut aide = ProposerAide::default();
+        aide.open(10, vec![txn(3)]).unwrap();
+        assert!(aide.deliver(10, block(10, vec![txn(1)])).is_err());
+        assert_eq!(aide.status(10), Some(ProposerAideStatus::InclusionListReceived));
+        assert!(aide.deliver(11, block(11, vec![txn(3)])).is_err());
+
+        // the proposer may retry once the builder behaves
+        assert!(aide.deliver(10, block(10, vec![txn(1), txn(3)])).is_ok());
+    }
+
+    #[test]
+    fn test_inclusion_list_is_final_once_header_released() {
+        let mut aide = ProposerAide::default();
+        deliver(&mut aide, 10, vec![txn(3)]).unwrap();
+        assert!(aide.open(10, vec![txn(4)]).is_err());
+    }
+
+    #[test]
+    fn test_publish() {
+        let mut aide = ProposerAide::default();
+        let header = deliver(&mut aide, 10, vec![txn(3)]).unwrap();
+
+        let mut other = header.clone();
+        other.gas_used = 1.into();
+        assert!(aide.begin_publish(&other).is_err());
+
+        let published = aide.begin_publish(&header).unwrap().unwrap();
+        assert!(published.transactions.iter().any(|tx| tx.hash == txn(3).hash));
+        // a single broadcast at a time
+        assert!(aide.begin_publish(&header).is_err());
+        aide.finish_publish(&header, false);
+        assert!(aide.begin_publish(&header).unwrap().is_some());
+        aide.finish_publish(&header, true);
+        assert_eq!(aide.status(10), Some(ProposerAideStatus::Published(header.hash())));
+        assert!(aide.begin_publish(&header).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut aide = ProposerAide::default();
+        deliver(&mut aide, 10, vec![txn(3)]).unwrap();
+        deliver(&mut aide, 11, vec![txn(4)]).unwrap();
+        aide.prune(11);
+        assert_eq!(aide.status(10), None);
+        assert!(aide.status(11).is_some());
+    }
+
+    #[test]
+    fn test_rebase_after_reorg() {
+        let mut aide = ProposerAide::default();
+        deliver(&mut aide, 10, vec![txn(3)]).unwrap();
+        let mut head = ChainHead { number: 9, hash: SH256::default(), parent_hash: SH256::default(), timestamp: 0 };
+        aide.rebase(&head);
+        assert!(matches!(aide.status(10), Some(ProposerAideStatus::HeaderDelivered(_))));
+
+        head.hash = SH256::from([9; 32]);
+        aide.rebase(&head);
+        assert_eq!(aide.status(10), Some(ProposerAideStatus::InclusionListReceived));
+        assert!(deliver(&mut aide, 10, vec![txn(3)]).is_ok());
+    }
+}
@@ -0,0 +1,506 @@
+use std::prelude::v1::*;
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base::trace::Alive;
+use eth_types::{Block, HexBytes, SH256};
+use jsonrpc::JsonrpcErrorObj;
+use net_http::{HttpClient, HttpMethod, HttpRequestBuilder, Uri};
+use serde::{Deserialize, Serialize};
+
+use crate::{blob_network_transaction, is_blob_transaction, BeaconClient, BlobSidecar, JsonRpcServerMsg, MevBooTeeError, RequestQueue};
+
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+// longest wait for a block to publish, so that a shutdown is noticed
+const PUBLISH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// the blinded beacon block signed by the proposer, in the JSON form of the beacon API
+// the execution payload header in its body commits to the header released by the enclave
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SignedBlindedBeaconBlock {
+    pub message: serde_json::Value,
+    pub signature: HexBytes,
+}
+
+// the block of a header signed by its proposer, as sent to the network
+#[derive(Clone, Debug, Serialize)]
+pub struct SignedBlock {
+    pub block: Block,
+    pub blob_sidecars: Vec<BlobSidecar>,
+    pub signed_blinded_block: Option<SignedBlindedBeaconBlock>, // required by the beacon publisher
+}
+
+impl SignedBlock {
+    pub fn new(block: Block, blob_sidecars: Vec<BlobSidecar>, signed_blinded_block: Option<SignedBlindedBeaconBlock>) -> Self {
+        Self {
+            block,
+            blob_sidecars,
+            signed_blinded_block,
+        }
+    }
+
+    pub fn hash(&self) -> SH256 {
+        self.block.header.hash()
+    }
+}
+
+pub trait BlockPublisher: Send + Sync {
+    fn endpoint(&self) -> &str;
+    fn publish(&self, block: &SignedBlock) -> Result<(), MevBooTeeError>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PublisherKind {
+    Beacon,    // beacon node publishBlock endpoint
+    Execution, // eth_sendRawTransaction on execution clients
+}
+
+impl std::str::FromStr for PublisherKind {
+    type Err = MevBooTeeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "beacon" => Ok(PublisherKind::Beacon),
+            "execution" => Ok(PublisherKind::Execution),
+            _ => Err(MevBooTeeError::Config(format!("unknown publisher {:?}, expected one of beacon, execution", s))),
+        }
+    }
+}
+
+// the execution payload of `block` in the JSON form of the beacon API,
+// quantities as decimal strings and transactions in their raw encoding
+pub fn execution_payload(block: &Block) -> Result<serde_json::Value, MevBooTeeError> {
+    let header = &block.header;
+    let transactions = block.transactions.iter()
+        .map(|txn| {
+            let inner = txn.inner()
+                .ok_or_else(|| MevBooTeeError::Publish(format!("unsupported transaction {:?}", txn.hash)))?;
+            Ok(HexBytes::from(inner.to_bytes()))
+        })
+        .collect::<Result<Vec<_>, MevBooTeeError>>()?;
+    let mut payload = serde_json::json!({
+        "parent_hash": header.parent_hash,
+        "fee_recipient": header.miner,
+        "state_root": header.state_root,
+        "receipts_root": header.receipts_root,
+        "logs_bloom": header.logs_bloom,
+        "prev_randao": header.mix_hash,
+        "block_number": header.number.as_u64().to_string(),
+        "gas_limit": header.gas_limit.as_u64().to_string(),
+        "gas_used": header.gas_used.as_u64().to_string(),
+        "timestamp": header.timestamp.as_u64().to_string(),
+        "extra_data": header.extra_data,
+        "base_fee_per_gas": header.base_fee_per_gas.unwrap_or_default().to_string(),
+        "block_hash": header.hash(),
+        "transactions": transactions,
+    });
+    // from Shanghai on
+    if let Some(withdrawals) = &block.withdrawals {
+        payload["withdrawals"] = withdrawals.iter()
+            .map(|withdrawal| serde_json::json!({
+                "index": withdrawal.index.as_u64().to_string(),
+                "validator_index": withdrawal.validator_index.as_u64().to_string(),
+                "address": withdrawal.address,
+                "amount": withdrawal.amount.as_u64().to_string(),
+            }))
+            .collect::<Vec<_>>()
+            .into();
+    }
+    // from Cancun on
+    if let (Some(blob_gas_used), Some(excess_blob_gas)) = (header.blob_gas_used, header.excess_blob_gas) {
+        payload["blob_gas_used"] = blob_gas_used.as_u64().to_string().into();
+        payload["excess_blob_gas"] = excess_blob_gas.as_u64().to_string().into();
+    }
+    Ok(payload)
+}
+
+// the body of publishBlock: the blinded block of the proposer with the execution payload in place of its header,
+// signed as it is. from Deneb on the blobs and proofs of its commitments go along, in transaction order
+pub fn beacon_block_contents(block: &SignedBlock) -> Result<serde_json::Value, MevBooTeeError> {
+    let signed = block.signed_blinded_block.as_ref()
+        .ok_or_else(|| MevBooTeeError::Publish("the proposer sent no signed beacon block".into()))?;
+    let mut message = signed.message.clone();
+    let body = message.get_mut("body").and_then(|body| body.as_object_mut())
+        .ok_or_else(|| MevBooTeeError::Publish("beacon block without a body".into()))?;
+    let payload_header = body.remove("execution_payload_header")
+        .ok_or_else(|| MevBooTeeError::Publish("beacon block without an execution payload header".into()))?;
+    let block_hash: SH256 = payload_header.get("block_hash").cloned()
+        .and_then(|hash| serde_json::from_value(hash).ok())
+        .ok_or_else(|| MevBooTeeError::Publish("execution payload header without a block hash".into()))?;
+    if block_hash != block.hash() {
+        return Err(MevBooTeeError::Publish(format!("the signed beacon block commits to {:?}, not {:?}", block_hash, block.hash())));
+    }
+    body.insert("execution_payload".into(), execution_payload(&block.block)?);
+    let commitments = body.get("blob_kzg_commitments").cloned();
+    let signed_block = serde_json::json!({ "message": message, "signature": signed.signature });
+
+    let commitments: Vec<HexBytes> = match commitments {
+        Some(commitments) => serde_json::from_value(commitments)
+            .map_err(|err| MevBooTeeError::Publish(format!("invalid blob kzg commitments: {}", err)))?,
+        None => return Ok(signed_block), // before Deneb
+    };
+    let (mut blobs, mut proofs, mut expected) = (Vec::new(), Vec::new(), Vec::new());
+    for txn in block.block.transactions.iter().filter(|txn| is_blob_transaction(txn)) {
+        let sidecar = block.blob_sidecars.iter().find(|sidecar| sidecar.tx_hash == txn.hash)
+            .ok_or_else(|| MevBooTeeError::Publish(format!("no sidecar for blob transaction {:?}", txn.hash)))?;
+        blobs.extend(sidecar.blobs.iter().cloned());
+        proofs.extend(sidecar.proofs.iter().cloned());
+        expected.extend(sidecar.commitments.iter().cloned());
+    }
+    if expected != commitments {
+        return Err(MevBooTeeError::Publish("the blob kzg commitments of the beacon block do not match the sidecars".into()));
+    }
+    Ok(serde_json::json!({ "signed_block": signed_block, "kzg_proofs": proofs, "blobs": blobs }))
+}
+
+// submit the signed block to the publishBlock endpoint of a beacon node
+pub struct BeaconPublisher {
+    client: BeaconClient,
+}
+
+impl BeaconPublisher {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            client: BeaconClient::new(endpoint),
+        }
+    }
+}
+
+impl BlockPublisher for BeaconPublisher {
+    fn endpoint(&self) -> &str {
+        self.client.endpoint()
+    }
+
+    fn publish(&self, block: &SignedBlock) -> Result<(), MevBooTeeError> {
+        self.client.post("/eth/v1/beacon/blocks", &beacon_block_contents(block)?)
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonRpcResponse {
+    #[serde(default)]
+    error: Option<JsonRpcError>,
+}
+
+#[derive(Deserialize)]
+struct JsonRpcError {
+    message: String,
+}
+
+// hand every transaction of the block to an execution client, in block order
+// blob transactions go in their network form, with their sidecar
+// this publishes no block and reveals the sealed transactions to the public mempool, see PublisherConfig::public_mempool
+pub struct ExecutionPublisher {
+    endpoint: String,
+    client: HttpClient,
+}
+
+impl ExecutionPublisher {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.to_owned(),
+            client: HttpClient::new(),
+        }
+    }
+
+    fn send_raw_transaction(&self, raw: &[u8]) -> Result<(), MevBooTeeError> {
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_sendRawTransaction",
+            "params": [HexBytes::from(raw.to_vec())],
+        });
+        let body = serde_json::to_vec(&body).map_err(|err| MevBooTeeError::Publish(format!("{:?}", err)))?;
+        let uri: Uri = self.endpoint.parse()
+            .map_err(|err| MevBooTeeError::Publish(format!("invalid url {}: {:?}", self.endpoint, err)))?;
+        let mut req = HttpRequestBuilder::new_ex(uri, Some(body), |req| {
+            req.method(HttpMethod::Post);
+            req.header("Content-Type", "application/json");
+        });
+        let resp = self.client.send(&mut req, Some(PUBLISH_TIMEOUT))
+            .map_err(|err| MevBooTeeError::Publish(format!("{}: {:?}", self.endpoint, err)))?;
+        if !resp.status.is_success() {
+            return Err(MevBooTeeError::Publish(format!("{}: status {}", self.endpoint, resp.status)));
+        }
+        let resp: JsonRpcResponse = serde_json::from_slice(&resp.body)
+            .map_err(|err| MevBooTeeError::Publish(format!("{}: {:?}", self.endpoint, err)))?;
+        match resp.error {
+            // a retry may resend transactions the node already has
+            Some(err) if !err.message.contains("already known") => {
+                Err(MevBooTeeError::Publish(format!("{}: {}", self.endpoint, err.message)))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl BlockPublisher for ExecutionPublisher {
+    fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    fn publish(&self, block: &SignedBlock) -> Result<(), MevBooTeeError> {
+        for txn in &block.block.transactions {
+            let inner = txn.inner()
+                .ok_or_else(|| MevBooTeeError::Publish(format!("unsupported transaction {:?}", txn.hash)))?;
+            let mut raw = inner.to_bytes();
+            if is_blob_transaction(txn) {
+                let sidecar = block.blob_sidecars.iter().find(|sidecar| sidecar.tx_hash == txn.hash)
+                    .ok_or_else(|| MevBooTeeError::Publish(format!("no sidecar for blob transaction {:?}", txn.hash)))?;
+                raw = blob_network_transaction(&raw, sidecar)?;
+            }
+            self.send_raw_transaction(&raw)?;
+        }
+        Ok(())
+    }
+}
+
+// tries every publisher in turn, for up to `rounds` rounds
+pub struct Publishers {
+    publishers: Vec<Box<dyn BlockPublisher>>,
+    rounds: usize,
+}
+
+impl Publishers {
+    pub fn new(publishers: Vec<Box<dyn BlockPublisher>>, rounds: usize) -> Self {
+        Self {
+            publishers,
+            rounds: rounds.max(1),
+        }
+    }
+
+    pub fn from_endpoints(kind: PublisherKind, endpoints: &[String], rounds: usize) -> Self {
+        let publishers = endpoints.iter()
+            .map(|endpoint| -> Box<dyn BlockPublisher> {
+                match kind {
+                    PublisherKind::Beacon => Box::new(BeaconPublisher::new(endpoint)),
+                    PublisherKind::Execution => Box::new(ExecutionPublisher::new(endpoint)),
+                }
+            })
+            .collect();
+        Self::new(publishers, rounds)
+    }
+
+    // returns the endpoint which accepted the block
+    pub fn publish(&self, block: &SignedBlock) -> Result<String, MevBooTeeError> {
+        let mut errors = Vec::new();
+        for _ in 0..self.rounds {
+            for publisher in &self.publishers {
+                match publisher.publish(block) {
+                    Ok(()) => {
+                        glog::info!("block {:?} published through {}", block.hash(), publisher.endpoint());
+                        return Ok(publisher.endpoint().to_owned());
+                    },
+                    Err(err) => {
+                        glog::warn!("unable to publish block {:?}: {}", block.hash(), err);
+                        errors.push(err.to_string());
+                    },
+                }
+            }
+        }
+        if errors.is_empty() {
+            return Err(MevBooTeeError::Publish("no publish endpoint configured".into()));
+        }
+        Err(MevBooTeeError::Publish(format!("block {:?} not published: {}", block.hash(), errors.join("; "))))
+    }
+}
+
+// a block handed to the publisher thread, `reply` gets the outcome once the dispatcher recorded it
+pub struct PublishJob {
+    pub block: SignedBlock,
+    pub reply: Sender<Result<bool, JsonrpcErrorObj>>,
+}
+
+// publish the blocks of `jobs` until shutdown, the outcomes go back to the dispatcher through `queue`
+// so that the retries do not hold back the other requests
+pub fn run_publisher(alive: Alive, publishers: Arc<Publishers>, jobs: Receiver<PublishJob>, queue: Arc<RequestQueue>) {
+    while alive.is_alive() {
+        let job = match jobs.recv_timeout(PUBLISH_POLL_INTERVAL) {
+            Ok(job) => job,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        let result = publishers.publish(&job.block);
+        if queue.send(JsonRpcServerMsg::Published(job, result)).is_err() {
+            glog::error!("request queue closed");
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct MockPublisher {
+        endpoint: String,
+        failures: usize, // number of calls failing before the block goes through
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl BlockPublisher for MockPublisher {
+        fn endpoint(&self) -> &str {
+            &self.endpoint
+        }
+
+        fn publish(&self, _: &SignedBlock) -> Result<(), MevBooTeeError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(MevBooTeeError::Publish(format!("{}: unavailable", self.endpoint)));
+            }
+            Ok(())
+        }
+    }
+
+    fn mock(endpoint: &str, failures: usize) -> (Box<dyn BlockPublisher>, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let publisher = MockPublisher { endpoint: endpoint.into(), failures, calls: calls.clone() };
+        (Box::new(publisher), calls)
+    }
+
+    fn signed_block() -> SignedBlock {
+        SignedBlock::new(Block::default(), Vec::new(), None)
+    }
+
+    fn blinded_block(block_hash: SH256, commitments: Option<Vec<HexBytes>>) -> SignedBlindedBeaconBlock {
+        let mut message = serde_json::json!({
+            "slot": "10", "proposer_index": "42",
+            "parent_root": "0x0101010101010101010101010101010101010101010101010101010101010101",
+            "state_root": "0x0202020202020202020202020202020202020202020202020202020202020202",
+            "body": {
+                "randao_reveal": "0x03", "graffiti": "0x0000000000000000000000000000000000000000000000000000000000000000",
+                "execution_payload_header": { "block_hash": block_hash, "transactions_root": "0x04" },
+            },
+        });
+        if let Some(commitments) = commitments {
+            message["body"]["blob_kzg_commitments"] = serde_json::json!(commitments);
+        }
+        SignedBlindedBeaconBlock { message, signature: vec![5; 96].into() }
+    }
+
+    // the block of the execution payload, with a withdrawal
+    fn payload_block() -> Block {
+        let mut block = Block::default();
+        block.header.number = 9.into();
+        block.header.gas_limit = 30_000_000.into();
+        block.header.gas_used = 21_000.into();
+        block.header.timestamp = 1_700_000_000.into();
+        block.header.miner = [6; 20].into();
+        block.header.base_fee_per_gas = Some(7.into());
+        block.withdrawals = Some(vec![eth_types::Withdrawal { index: 1.into(), validator_index: 2.into(), address: [8; 20].into(), amount: 3.into() }]);
+        block
+    }
+
+    #[test]
+    fn test_beacon_block_contents() {
+        let block = payload_block();
+        let hash = block.header.hash();
+        let signed = SignedBlock::new(block.clone(), Vec::new(), Some(blinded_block(hash, None)));
+        let header = &block.header;
+        // the request body of publishBlock, the blinded block with its payload header swapped for the payload
+        let expected: serde_json::Value = serde_json::from_str(&format!(r#"{{
+            "message": {{
+                "slot": "10", "proposer_index": "42",
+                "parent_root": "0x0101010101010101010101010101010101010101010101010101010101010101",
+                "state_root": "0x0202020202020202020202020202020202020202020202020202020202020202",
+                "body": {{
+                    "randao_reveal": "0x03", "graffiti": "0x0000000000000000000000000000000000000000000000000000000000000000",
+                    "execution_payload": {{
+                        "parent_hash": {parent_hash}, "fee_recipient": "0x0606060606060606060606060606060606060606",
+                        "state_root": {state_root}, "receipts_root": {receipts_root}, "logs_bloom": {logs_bloom},
+                        "prev_randao": {prev_randao}, "block_number": "9", "gas_limit": "30000000", "gas_used": "21000",
+                        "timestamp": "1700000000", "extra_data": "0x", "base_fee_per_gas": "7", "block_hash": {block_hash},
+                        "transactions": [],
+                        "withdrawals": [{{"index": "1", "validator_index": "2", "address": "0x0808080808080808080808080808080808080808", "amount": "3"}}]
+                    }}
+                }}
+            }},
+            "signature": "0x{signature}"
+        }}"#,
+            parent_hash = serde_json::to_string(&header.parent_hash).unwrap(),
+            state_root = serde_json::to_string(&header.state_root).unwrap(),
+            receipts_root = serde_json::to_string(&header.receipts_root).unwrap(),
+            logs_bloom = serde_json::to_string(&header.logs_bloom).unwrap(),
+            prev_randao = serde_json::to_string(&header.mix_hash).unwrap(),
+            block_hash = serde_json::to_string(&hash).unwrap(),
+            signature = "05".repeat(96),
+        )).unwrap();
+        assert_eq!(beacon_block_contents(&signed).unwrap(), expected);
+
+        // a blinded block for another payload, or none at all
+        let other = SignedBlock::new(block.clone(), Vec::new(), Some(blinded_block(SH256::from([9; 32]), None)));
+        assert!(beacon_block_contents(&other).is_err());
+        assert!(beacon_block_contents(&signed_block()).is_err());
+    }
+
+    #[test]
+    fn test_deneb_block_contents() {
+        let block = payload_block();
+        let hash = block.header.hash();
+        let signed = SignedBlock::new(block.clone(), Vec::new(), Some(blinded_block(hash, Some(Vec::new()))));
+        let contents = beacon_block_contents(&signed).unwrap();
+        assert_eq!(contents["signed_block"]["message"]["body"]["blob_kzg_commitments"], serde_json::json!([]));
+        assert_eq!(contents["kzg_proofs"], serde_json::json!([]));
+        assert_eq!(contents["blobs"], serde_json::json!([]));
+
+        // commitments without the blob transactions behind them
+        let signed = SignedBlock::new(block, Vec::new(), Some(blinded_block(hash, Some(vec![vec![1; 48].into()]))));
+        assert!(beacon_block_contents(&signed).is_err());
+    }
+
+    #[test]
+    fn test_fallback_to_next_endpoint() {
+        let (a, a_calls) = mock("a", usize::MAX);
+        let (b, b_calls) = mock("b", 0);
+        let publishers = Publishers::new(vec![a, b], 1);
+        assert_eq!(publishers.publish(&signed_block()).unwrap(), "b");
+        assert_eq!(a_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(b_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_rounds() {
+        let (a, a_calls) = mock("a", 2);
+        let publishers = Publishers::new(vec![a], 3);
+        assert_eq!(publishers.publish(&signed_block()).unwrap(), "a");
+        assert_eq!(a_calls.load(Ordering::SeqCst), 3);
+
+        let (a, _) = mock("a", usize::MAX);
+        let (b, _) = mock("b", usize::MAX);
+        let err = Publishers::new(vec![a, b], 2).publish(&signed_block()).unwrap_err().to_string();
+        assert_eq!(err.matches("unavailable").count(), 4);
+        assert!(Publishers::new(Vec::new(), 2).publish(&signed_block()).is_err());
+    }
+
+    #[test]
+    fn test_run_publisher() {
+        let (a, a_calls) = mock("a", 1);
+        let publishers = Arc::new(Publishers::new(vec![a], 2));
+        let (jobs, job_receiver) = std::sync::mpsc::channel();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let queue = Arc::new(RequestQueue::new(sender, Arc::default()));
+        let alive = Alive::new();
+        let handle = std::thread::spawn({
+            let alive = alive.clone();
+            move || run_publisher(alive, publishers, job_receiver, queue)
+        });
+
+        let (reply, _) = std::sync::mpsc::channel();
+        jobs.send(PublishJob { block: signed_block(), reply }).unwrap();
+        match receiver.recv().unwrap() {
+            JsonRpcServerMsg::Published(job, result) => {
+                assert_eq!(job.block.hash(), signed_block().hash());
+                assert_eq!(result.unwrap(), "a");
+            },
+            _ => panic!("expected the publish outcome"),
+        }
+        assert_eq!(a_calls.load(Ordering::SeqCst), 2);
+        alive.shutdown();
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use std::prelude::v1::*;
+
+use std::{collections::BTreeMap, sync::{Arc, Mutex}};
+
+use eth_tools::{ExecutionClient, MixRpcClient};
+use eth_types::{BlockHeader, EthereumEngineTypes, Receipt, Transaction, SH160, SH256, SU256};
+use evm_executor::{BlockBuilder, BlockHashGetter, ConsensusBlockInfo, Engine, Ethereum};
+use mpt::{BlockStateFetcher, Database, TrieState};
+use statedb::StateDB;
+
+use crate::{MevBooTeeError, TrackedState};
+
+pub type ElClient = Arc<ExecutionClient<Arc<MixRpcClient>>>;
+
+pub type ElTrieState = TrieState<BlockStateFetcher<Arc<MixRpcClient>, EthereumEngineTypes, ElClient>, Database>;
+
+// the state of the builder records what the transactions access
+pub type EvmBlockBuilder = BlockBuilder<Ethereum, TrackedState<ElTrieState>, BuilderFetcher>;
+
+// the beacon roots contract of EIP-4788 and the length of its ring buffer
+const BEACON_ROOTS_ADDRESS: [u8; 20] = [
+    0x00, 0x0f, 0x3d, 0xf6, 0xd7, 0x32, 0x80, 0x7e, 0xf1, 0x31, 0x9f, 0xb7, 0xb8, 0xbb, 0x85, 0x22, 0xd0, 0xbe, 0xac, 0x02,
+];
+const BEACON_ROOTS_HISTORY: u64 = 8191;
+
+// the world state after `parent`, fetched on demand from the execution client
+pub fn parent_state(el: &ElClient, parent: &BlockHeader) -> ElTrieState {
+    // a memory database which store the mpt nodes and codes
+    let db = Database::new(100000);
+    // state fetcher, fetch the states on demand.
+    let fetcher = mpt::BlockStateFetcher::new(el.clone(), parent.number.into());
+    // world state trie, use parent's state_root
+    mpt::TrieState::new(fetcher, parent.state_root, db)
+}
+
+// create a block builder on top of `parent`, the state is fetched on demand from the execution client
+// from Cancun on, `parent_beacon_block_root` is stored before any transaction executes
+pub fn new_block_builder(
+    el: &ElClient, chain_id: u64, parent: &BlockHeader, info: ConsensusBlockInfo, parent_beacon_block_root: Option<SH256>,
+) -> Result<EvmBlockBuilder, MevBooTeeError> {
+    let timestamp = info.timestamp;
+    // use the ethereum engine
+    let engine = Ethereum::new(chain_id.into());
+    let header = engine.new_block_header(parent, info);
+    let trie = TrackedState::new(parent_state(el, parent));
+    let hash_getter = BuilderFetcher::new(el.as_ref().clone());
+    let mut builder = BlockBuilder::new(engine, trie, hash_getter, header)
+        .map_err(|err| MevBooTeeError::Execution(format!("unable to create block builder: {:?}", err)))?;
+    if let Some(root) = parent_beacon_block_root {
+        apply_beacon_root(&mut builder, timestamp, root)?;
+    }
+    Ok(builder)
+}
+
+// the system call of EIP-4788: the beacon roots contract keeps the root in a ring buffer indexed by the timestamp
+pub fn apply_beacon_root(builder: &mut EvmBlockBuilder, timestamp: u64, root: SH256) -> Result<(), MevBooTeeError> {
+    let index = timestamp % BEACON_ROOTS_HISTORY;
+    let statedb = builder.statedb();
+    let result = statedb.set_state(&BEACON_ROOTS_ADDRESS.into(), &word(index), word(timestamp))
+        .and_then(|_| statedb.set_state(&BEACON_ROOTS_ADDRESS.into(), &word(index + BEACON_ROOTS_HISTORY), root));
+    result.map_err(|err| MevBooTeeError::Execution(format!("unable to store the parent beacon block root: {:?}", err)))
+}
+
+// a number as a 32 bytes big-endian storage word
+fn word(n: u64) -> SH256 {
+    let mut word = [0_u8; 32];
+    word[24..].copy_from_slice(&n.to_be_bytes());
+    word.into()
+}
+
+// execute `txn` on top of the builder state and append it to the block
+pub fn commit_transaction(builder: &mut EvmBlockBuilder, txn: &Transaction) -> Result<Receipt, MevBooTeeError> {
+    let inner = txn.inner()
+        .ok_or_else(|| MevBooTeeError::Execution(format!("unsupported transaction {:?}", txn.hash)))?;
+    let receipt = builder.commit(Arc::new(inner))
+        .map_err(|err| MevBooTeeError::Execution(format!("txn {:?} failed: {:?}", txn.hash, err)))?;
+    Ok(receipt.clone())
+}
+
+pub fn balance_of(builder: &mut EvmBlockBuilder, address: &SH160) -> Result<SU256, MevBooTeeError> {
+    builder.statedb().get_balance(address)
+        .map_err(|err| MevBooTeeError::Execution(format!("unable to read balance of {:?}: {:?}", address, err)))
+}
+
+#[derive(Clone)]
+pub struct BuilderFetcher {
+    client: ExecutionClient<Arc<MixRpcClient>>,
+    cache: Arc<Mutex<BTreeMap<u64, SH256>>>,
+}
+
+impl BuilderFetcher {
+    pub fn new(client: ExecutionClient<Arc<MixRpcClient>>) -> Self {
+        Self {
+            client,
+            cache: Default::default(),
+        }
+    }
+}
+
+impl BlockHashGetter for BuilderFetcher {
+    fn get_hash(&self, current: u64, target: u64) -> SH256 {
+        if target >= current || target < current.saturating_sub(256) {
+            return Default::default();
+        }
+        {
+            let cache = self.cache.lock().unwrap();
+            if let Some(hash) = cache.get(&target) {
+                return *hash;
+            }
+        }
+        match self.client.get_block_header(target.into()) {
+            Ok(header) => {
+                let hash = header.hash();
+                let mut cache = self.cache.lock().unwrap();
+                cache.insert(target, hash);
+                hash
+            }
+            Err(_) => Default::default(),
+        }
+    }
+}
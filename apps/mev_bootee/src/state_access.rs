@@ -0,0 +1,150 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use eth_types::{HexBytes, SH160, SH256, SU256};
+use statedb::{Error, StateDB};
+
+// a piece of the world state a transaction can depend on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StateKey {
+    Account(SH160),     // balance, nonce, code and existence
+    Slot(SH160, SH256), // a storage slot of a contract
+}
+
+// what was read and written since the last StateAccess::take
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StateAccess {
+    pub reads: BTreeSet<StateKey>,
+    pub writes: BTreeSet<StateKey>,
+}
+
+// a state which records every account and storage slot the EVM goes through, internal calls included
+pub struct TrackedState<S> {
+    inner: S,
+    access: StateAccess,
+}
+
+impl<S> TrackedState<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, access: StateAccess::default() }
+    }
+
+    // the accesses recorded so far, the record starts over
+    pub fn take_access(&mut self) -> StateAccess {
+        std::mem::take(&mut self.access)
+    }
+
+    fn read(&mut self, key: StateKey) {
+        self.access.reads.insert(key);
+    }
+
+    fn write(&mut self, key: StateKey) {
+        self.access.writes.insert(key);
+    }
+}
+
+impl<S: StateDB> StateDB for TrackedState<S> {
+    type StateAccount = S::StateAccount;
+
+    fn fork(&self) -> Self {
+        Self::new(self.inner.fork())
+    }
+
+    fn state_root(&self) -> SH256 {
+        self.inner.state_root()
+    }
+
+    fn try_get_account(&mut self, address: &SH160) -> Result<Option<Self::StateAccount>, Error> {
+        self.read(StateKey::Account(*address));
+        self.inner.try_get_account(address)
+    }
+
+    fn get_account_basic(&mut self, address: &SH160) -> Result<(SU256, u64), Error> {
+        self.read(StateKey::Account(*address));
+        self.inner.get_account_basic(address)
+    }
+
+    fn prefetch<'a, I>(&mut self, list: I) -> Result<usize, Error>
+    where I: Iterator<Item = (&'a SH160, Vec<SH256>)> {
+        // fetching ahead of the execution is not an access
+        self.inner.prefetch(list)
+    }
+
+    fn apply_states(&mut self, list: Vec<statedb::FetchStateResult>) -> Result<(), Error> {
+        self.inner.apply_states(list)
+    }
+
+    fn add_balance(&mut self, address: &SH160, val: &SU256) -> Result<(), Error> {
+        self.write(StateKey::Account(*address));
+        self.inner.add_balance(address, val)
+    }
+
+    fn set_balance(&mut self, address: &SH160, val: SU256) -> Result<(), Error> {
+        self.write(StateKey::Account(*address));
+        self.inner.set_balance(address, val)
+    }
+
+    fn sub_balance(&mut self, address: &SH160, val: &SU256) -> Result<(), Error> {
+        self.write(StateKey::Account(*address));
+        self.inner.sub_balance(address, val)
+    }
+
+    fn get_balance(&mut self, address: &SH160) -> Result<SU256, Error> {
+        self.read(StateKey::Account(*address));
+        self.inner.get_balance(address)
+    }
+
+    fn get_nonce(&mut self, address: &SH160) -> Result<u64, Error> {
+        self.read(StateKey::Account(*address));
+        self.inner.get_nonce(address)
+    }
+
+    fn set_nonce(&mut self, address: &SH160, val: u64) -> Result<(), Error> {
+        self.write(StateKey::Account(*address));
+        self.inner.set_nonce(address, val)
+    }
+
+    fn set_code(&mut self, address: &SH160, code: Vec<u8>) -> Result<(), Error> {
+        self.write(StateKey::Account(*address));
+        self.inner.set_code(address, code)
+    }
+
+    fn get_code(&mut self, address: &SH160) -> Result<Arc<HexBytes>, Error> {
+        self.read(StateKey::Account(*address));
+        self.inner.get_code(address)
+    }
+
+    fn get_state(&mut self, address: &SH160, index: &SH256) -> Result<SH256, Error> {
+        self.read(StateKey::Slot(*address, *index));
+        self.inner.get_state(address, index)
+    }
+
+    fn set_state(&mut self, address: &SH160, index: &SH256, value: SH256) -> Result<(), Error> {
+        self.write(StateKey::Slot(*address, *index));
+        self.inner.set_state(address, index, value)
+    }
+
+    fn suicide(&mut self, address: &SH160) -> Result<(), Error> {
+        self.write(StateKey::Account(*address));
+        self.inner.suicide(address)
+    }
+
+    fn exist(&mut self, address: &SH160) -> Result<bool, Error> {
+        self.read(StateKey::Account(*address));
+        self.inner.exist(address)
+    }
+
+    fn flush(&mut self) -> Result<SH256, Error> {
+        self.inner.flush()
+    }
+
+    fn revert(&mut self, root: SH256) {
+        self.inner.revert(root)
+    }
+
+    fn check_missing_state(&mut self, address: &SH160, storages: &[SH256]) -> Result<statedb::MissingState, Error> {
+        self.inner.check_missing_state(address, storages)
+    }
+}
@@ -0,0 +1,193 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeMap;
+
+use eth_types::{HexBytes, SH256};
+use serde::{Deserialize, Serialize};
+
+use crate::{verify_enclave_signature, EnclaveKey, MevBooTeeError};
+
+// how many sealed slots keep their transcript
+const TRANSCRIPT_SLOTS: u64 = 64;
+
+// the bids of a sealed slot, committed to by the Merkle root of their receipts and signed with the enclave key
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuctionTranscript {
+    pub block_number: u64,
+    pub deadline: u64,
+    pub bids: u64,
+    pub root: SH256, // over the signing roots of the bid receipts
+    pub signature: HexBytes,
+}
+
+impl AuctionTranscript {
+    pub fn new(key: &EnclaveKey, block_number: u64, deadline: u64, receipts: &[SH256]) -> Self {
+        let mut transcript = Self {
+            block_number,
+            deadline,
+            bids: receipts.len() as u64,
+            root: merkle_root(receipts),
+            signature: HexBytes::default(),
+        };
+        transcript.signature = key.sign(&transcript.signing_root());
+        transcript
+    }
+
+    pub fn signing_root(&self) -> SH256 {
+        let mut data = b"mev-bootee/auction_transcript".to_vec();
+        data.extend_from_slice(&self.block_number.to_be_bytes());
+        data.extend_from_slice(&self.deadline.to_be_bytes());
+        data.extend_from_slice(&self.bids.to_be_bytes());
+        data.extend_from_slice(self.root.as_bytes());
+        crypto::keccak_hash(&data).into()
+    }
+
+    pub fn verify(&self, pubkey: &[u8]) -> Result<(), MevBooTeeError> {
+        verify_enclave_signature(pubkey, &self.signing_root(), &self.signature)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GetTranscriptRequest {
+    pub block_number: u64,
+    #[serde(default)]
+    pub tob_id: Option<String>, // the bid to prove the inclusion of
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TranscriptResponse {
+    pub transcript: AuctionTranscript,
+    pub proof: Option<Vec<SH256>>, // for the requested bid, missing when it is not part of the transcript
+}
+
+impl TranscriptResponse {
+    // the transcript is signed by the enclave and `receipt`, the signing root of a bid receipt, is part of it
+    pub fn verify_inclusion(&self, pubkey: &[u8], receipt: &SH256) -> Result<(), MevBooTeeError> {
+        self.transcript.verify(pubkey)?;
+        let proof = self.proof.as_ref()
+            .ok_or_else(|| MevBooTeeError::Slot(format!("no bid {:?} in the transcript of block {}", receipt, self.transcript.block_number)))?;
+        match verify_merkle_proof(receipt, proof, &self.transcript.root) {
+            true => Ok(()),
+            false => Err(MevBooTeeError::Slot(format!("invalid inclusion proof of {:?}", receipt))),
+        }
+    }
+}
+
+struct SlotTranscript {
+    transcript: AuctionTranscript,
+    receipts: Vec<(String, SH256)>, // ToB id and receipt signing root, in the order of the leaves
+}
+
+// the transcripts of the last sealed slots
+#[derive(Default)]
+pub struct Transcripts {
+    slots: BTreeMap<u64, SlotTranscript>,
+}
+
+impl Transcripts {
+    pub fn insert(&mut self, key: &EnclaveKey, block_number: u64, deadline: u64, receipts: Vec<(String, SH256)>) -> AuctionTranscript {
+        let leaves: Vec<SH256> = receipts.iter().map(|(_, receipt)| *receipt).collect();
+        let transcript = AuctionTranscript::new(key, block_number, deadline, &leaves);
+        self.slots.insert(block_number, SlotTranscript { transcript: transcript.clone(), receipts });
+        self.slots = self.slots.split_off(&block_number.saturating_sub(TRANSCRIPT_SLOTS));
+        transcript
+    }
+
+    pub fn get(&self, req: &GetTranscriptRequest) -> Option<TranscriptResponse> {
+        let slot = self.slots.get(&req.block_number)?;
+        let leaves: Vec<SH256> = slot.receipts.iter().map(|(_, receipt)| *receipt).collect();
+        let proof = req.tob_id.as_ref()
+            .and_then(|tob_id| slot.receipts.iter().position(|(id, _)| id == tob_id))
+            .map(|index| merkle_proof(&leaves, index));
+        Some(TranscriptResponse { transcript: slot.transcript.clone(), proof })
+    }
+}
+
+fn hash_leaf(leaf: &SH256) -> SH256 {
+    let mut data = vec![0_u8];
+    data.extend_from_slice(leaf.as_bytes());
+    crypto::keccak_hash(&data).into()
+}
+
+// the pair is sorted, so that a proof needs no position
+fn hash_node(a: &SH256, b: &SH256) -> SH256 {
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    let mut data = vec![1_u8];
+    data.extend_from_slice(left.as_bytes());
+    data.extend_from_slice(right.as_bytes());
+    crypto::keccak_hash(&data).into()
+}
+
+// every level of the tree, from the hashed leaves up to the root, an odd node is carried up as it is
+fn merkle_levels(leaves: &[SH256]) -> Vec<Vec<SH256>> {
+    let mut levels = vec![leaves.iter().map(hash_leaf).collect::<Vec<_>>()];
+    while levels.last().unwrap().len() > 1 {
+        let next = levels.last().unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => hash_node(a, b),
+                [a] => *a,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+// zero without leaves
+pub fn merkle_root(leaves: &[SH256]) -> SH256 {
+    match leaves.is_empty() {
+        true => SH256::default(),
+        false => merkle_levels(leaves).last().unwrap()[0],
+    }
+}
+
+pub fn merkle_proof(leaves: &[SH256], mut index: usize) -> Vec<SH256> {
+    let mut proof = Vec::new();
+    for level in merkle_levels(leaves).iter() {
+        if let Some(sibling) = level.get(index ^ 1) {
+            proof.push(*sibling);
+        }
+        index /= 2;
+    }
+    proof
+}
+
+pub fn verify_merkle_proof(leaf: &SH256, proof: &[SH256], root: &SH256) -> bool {
+    let node = proof.iter().fold(hash_leaf(leaf), |node, sibling| hash_node(&node, sibling));
+    node == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merkle_proof() {
+        let leaves: Vec<SH256> = (1..=5_u8).map(|n| SH256::from([n; 32])).collect();
+        let root = merkle_root(&leaves);
+        for (index, leaf) in leaves.iter().enumerate() {
+            assert!(verify_merkle_proof(leaf, &merkle_proof(&leaves, index), &root));
+        }
+        assert!(!verify_merkle_proof(&SH256::from([6; 32]), &merkle_proof(&leaves, 0), &root));
+        assert_eq!(merkle_root(&leaves[..1]), hash_leaf(&leaves[0]));
+        assert_eq!(merkle_root(&[]), SH256::default());
+    }
+
+    #[test]
+    fn test_transcript() {
+        let key = EnclaveKey::from_ikm(&[1; 32]);
+        let mut transcripts = Transcripts::default();
+        let receipts = vec![("a".to_owned(), SH256::from([1; 32])), ("b".to_owned(), SH256::from([2; 32]))];
+        transcripts.insert(&key, 10, 1000, receipts);
+
+        let req = |tob_id: &str| GetTranscriptRequest { block_number: 10, tob_id: Some(tob_id.into()) };
+        let response = transcripts.get(&req("b")).unwrap();
+        assert_eq!(response.transcript.bids, 2);
+        assert!(response.verify_inclusion(key.public_key(), &SH256::from([2; 32])).is_ok());
+        assert!(response.verify_inclusion(key.public_key(), &SH256::from([3; 32])).is_err());
+        assert!(transcripts.get(&req("c")).unwrap().proof.is_none());
+        assert!(transcripts.get(&GetTranscriptRequest { block_number: 11, tob_id: None }).is_none());
+    }
+}
@@ -0,0 +1,251 @@
+use std::prelude::v1::*;
+
+use std::collections::BTreeMap;
+
+use eth_types::{Signer, Transaction, SH160};
+use jsonrpc::JsonrpcErrorObj;
+use serde::Serialize;
+use statedb::StateDB;
+
+use crate::decode_transaction;
+
+const TX_GAS: u64 = 21000;
+const TX_GAS_CONTRACT_CREATION: u64 = 53000;
+const TX_DATA_ZERO_GAS: u64 = 4;
+const TX_DATA_NON_ZERO_GAS: u64 = 16;
+const TX_ACCESS_LIST_ADDRESS_GAS: u64 = 2400;
+const TX_ACCESS_LIST_STORAGE_KEY_GAS: u64 = 1900;
+const INIT_CODE_WORD_GAS: u64 = 2;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxRejectReason {
+    Decode(String),
+    EmptyBundle,
+    ChainId { expected: u64, got: Option<u64> },
+    Signature(String),
+    Nonce { expected: u64, got: u64 },
+    IntrinsicGas { required: u64, limit: u64 },
+    BlobCreation,
+    Blob(String),
+    State(String),
+}
+
+// why the transaction at `index` of a request was rejected
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TxRejection {
+    pub index: usize,
+    pub reason: TxRejectReason,
+}
+
+impl std::fmt::Display for TxRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "txns[{}]: ", self.index)?;
+        match &self.reason {
+            TxRejectReason::Decode(err) => write!(f, "undecodable transaction: {}", err),
+            TxRejectReason::EmptyBundle => write!(f, "the bundle has no transaction"),
+            TxRejectReason::ChainId { expected, got: Some(got) } => write!(f, "wrong chain id {}, expected {}", got, expected),
+            TxRejectReason::ChainId { expected, got: None } => write!(f, "missing chain id, expected {}", expected),
+            TxRejectReason::Signature(err) => write!(f, "invalid signature: {}", err),
+            TxRejectReason::Nonce { expected, got } => write!(f, "nonce {} does not match the sender nonce {}", got, expected),
+            TxRejectReason::IntrinsicGas { required, limit } => write!(f, "gas limit {} below intrinsic gas {}", limit, required),
+            TxRejectReason::BlobCreation => write!(f, "blob transactions cannot create contracts"),
+            TxRejectReason::Blob(err) => write!(f, "invalid blobs: {}", err),
+            TxRejectReason::State(err) => write!(f, "unable to read sender state: {}", err),
+        }
+    }
+}
+
+// the index and the reason also go in the error data, e.g. {"index": 1, "reason": {"nonce": {"expected": 5, "got": 3}}}
+impl From<TxRejection> for JsonrpcErrorObj {
+    fn from(rejection: TxRejection) -> Self {
+        let mut err = JsonrpcErrorObj::client(format!("Bad request: {}", rejection));
+        err.data = serde_json::to_value(&rejection).ok();
+        err
+    }
+}
+
+pub fn decode_transactions(txns: &[String]) -> Result<Vec<Transaction>, TxRejection> {
+    txns.iter()
+        .enumerate()
+        .map(|(index, txn)| decode_transaction(txn).map_err(|err| TxRejection { index, reason: TxRejectReason::Decode(err) }))
+        .collect()
+}
+
+// where the validator reads the nonce of a sender it has not seen yet
+pub trait NonceSource {
+    fn nonce(&mut self, address: &SH160) -> Result<u64, String>;
+}
+
+impl<S: StateDB> NonceSource for S {
+    fn nonce(&mut self, address: &SH160) -> Result<u64, String> {
+        self.get_nonce(address).map_err(|err| format!("{:?}", err))
+    }
+}
+
+// checks a sequence of transactions as if they were executed in order on top of `state`
+pub struct TxValidator<'a, S: NonceSource> {
+    chain_id: u64,
+    signer: Signer,
+    state: &'a mut S,
+    nonces: BTreeMap<SH160, u64>, // next nonce of every sender seen so far
+}
+
+impl<'a, S: NonceSource> TxValidator<'a, S> {
+    pub fn new(chain_id: u64, state: &'a mut S) -> Self {
+        Self {
+            chain_id,
+            signer: Signer::new(chain_id.into()),
+            state,
+            nonces: BTreeMap::new(),
+        }
+    }
+
+    // returns the sender of the transaction
+    pub fn validate(&mut self, index: usize, txn: &Transaction) -> Result<SH160, TxRejection> {
+        let reject = |reason| TxRejection { index, reason };
+
+        let got = txn.chain_id.map(|id| id.as_u64());
+        if got != Some(self.chain_id) {
+            return Err(reject(TxRejectReason::ChainId { expected: self.chain_id, got }));
+        }
+
+        let inner = txn.inner().ok_or_else(|| reject(TxRejectReason::Decode("unsupported transaction type".into())))?;
+        let sender = self.signer.sender(&inner).map_err(|err| reject(TxRejectReason::Signature(format!("{:?}", err))))?;
+
+        let required = intrinsic_gas(txn);
+        let limit = txn.gas.as_u64();
+        if limit < required {
+            return Err(reject(TxRejectReason::IntrinsicGas { required, limit }));
+        }
+        if txn.r#type.as_u64() == 3 && txn.to.is_none() {
+            return Err(reject(TxRejectReason::BlobCreation));
+        }
+
+        self.check_nonce(index, &sender, txn)?;
+        Ok(sender)
+    }
+
+    // the txn must take the next nonce of its sender
+    fn check_nonce(&mut self, index: usize, sender: &SH160, txn: &Transaction) -> Result<(), TxRejection> {
+        let reject = |reason| TxRejection { index, reason };
+        let expected = match self.nonces.get(sender) {
+            Some(nonce) => *nonce,
+            None => self.state.nonce(sender).map_err(|err| reject(TxRejectReason::State(err)))?,
+        };
+        let got = txn.nonce.as_u64();
+        if got != expected {
+            return Err(reject(TxRejectReason::Nonce { expected, got }));
+        }
+        self.nonces.insert(*sender, expected + 1);
+        Ok(())
+    }
+
+    pub fn validate_all(&mut self, txns: &[Transaction]) -> Result<Vec<SH160>, TxRejection> {
+        txns.iter().enumerate().map(|(index, txn)| self.validate(index, txn)).collect()
+    }
+}
+
+// gas charged before any execution: base cost, calldata, access list and initcode
+pub fn intrinsic_gas(txn: &Transaction) -> u64 {
+    let input = txn.input.as_ref();
+    let mut gas = match txn.to {
+        Some(_) => TX_GAS,
+        None => TX_GAS_CONTRACT_CREATION + INIT_CODE_WORD_GAS * ((input.len() as u64 + 31) / 32),
+    };
+    let zeros = input.iter().filter(|b| **b == 0).count() as u64;
+    gas += zeros * TX_DATA_ZERO_GAS + (input.len() as u64 - zeros) * TX_DATA_NON_ZERO_GAS;
+    if let Some(access_list) = &txn.access_list {
+        for item in access_list {
+            gas += TX_ACCESS_LIST_ADDRESS_GAS + item.storage_keys.len() as u64 * TX_ACCESS_LIST_STORAGE_KEY_GAS;
+        }
+    }
+    gas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intrinsic_gas() {
+        let mut txn = Transaction::default();
+        txn.to = Some(SH160::default());
+        assert_eq!(intrinsic_gas(&txn), 21000);
+
+        txn.input = vec![0, 0, 1, 2].into();
+        assert_eq!(intrinsic_gas(&txn), 21000 + 2 * 4 + 2 * 16);
+
+        txn.to = None;
+        assert_eq!(intrinsic_gas(&txn), 53000 + 2 + 2 * 4 + 2 * 16);
+    }
+
+    #[test]
+    fn test_decode_rejection_names_index() {
+        let txns = vec!["0xzz".to_owned()];
+        let err = decode_transactions(&txns).unwrap_err();
+        assert_eq!(err.index, 0);
+        assert!(matches!(err.reason, TxRejectReason::Decode(_)));
+        assert!(err.to_string().starts_with("txns[0]: "));
+    }
+
+    #[derive(Default)]
+    struct MockNonces(BTreeMap<SH160, u64>);
+
+    impl NonceSource for MockNonces {
+        fn nonce(&mut self, address: &SH160) -> Result<u64, String> {
+            Ok(self.0.get(address).cloned().unwrap_or_default())
+        }
+    }
+
+    fn transfer(chain_id: Option<u64>, nonce: u64) -> Transaction {
+        let mut txn = Transaction::default();
+        txn.chain_id = chain_id.map(Into::into);
+        txn.nonce = nonce.into();
+        txn.to = Some(SH160::default());
+        txn.gas = 21000.into();
+        txn
+    }
+
+    #[test]
+    fn test_chain_id_rejection() {
+        let mut state = MockNonces::default();
+        let mut validator = TxValidator::new(1, &mut state);
+        let err = validator.validate(2, &transfer(Some(5), 0)).unwrap_err();
+        assert_eq!(err, TxRejection { index: 2, reason: TxRejectReason::ChainId { expected: 1, got: Some(5) } });
+        let err = validator.validate(0, &transfer(None, 0)).unwrap_err();
+        assert_eq!(err.reason, TxRejectReason::ChainId { expected: 1, got: None });
+        assert_eq!(err.to_string(), "txns[0]: missing chain id, expected 1");
+    }
+
+    #[test]
+    fn test_bad_signature_rejection() {
+        let mut state = MockNonces::default();
+        // no signature at all
+        let err = TxValidator::new(1, &mut state).validate(1, &transfer(Some(1), 0)).unwrap_err();
+        assert_eq!(err.index, 1);
+        assert!(matches!(err.reason, TxRejectReason::Signature(_)));
+    }
+
+    #[test]
+    fn test_nonce_rejection() {
+        let sender = SH160::from([1; 20]);
+        let mut state = MockNonces(vec![(sender, 5)].into_iter().collect());
+        let mut validator = TxValidator::new(1, &mut state);
+        let err = validator.check_nonce(3, &sender, &transfer(Some(1), 4)).unwrap_err();
+        assert_eq!(err, TxRejection { index: 3, reason: TxRejectReason::Nonce { expected: 5, got: 4 } });
+        // later txns of the sender follow the ones already validated
+        assert!(validator.check_nonce(0, &sender, &transfer(Some(1), 5)).is_ok());
+        assert!(validator.check_nonce(1, &sender, &transfer(Some(1), 5)).is_err());
+        assert!(validator.check_nonce(1, &sender, &transfer(Some(1), 6)).is_ok());
+    }
+
+    #[test]
+    fn test_rejection_error_data() {
+        let rejection = TxRejection { index: 1, reason: TxRejectReason::Nonce { expected: 5, got: 3 } };
+        let err = JsonrpcErrorObj::from(rejection);
+        assert_eq!(err.data, Some(serde_json::json!({"index": 1, "reason": {"nonce": {"expected": 5, "got": 3}}})));
+        let err = JsonrpcErrorObj::from(TxRejection { index: 0, reason: TxRejectReason::EmptyBundle });
+        assert_eq!(err.data, Some(serde_json::json!({"index": 0, "reason": "empty_bundle"})));
+    }
+}
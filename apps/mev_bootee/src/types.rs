@@ -1,9 +1,12 @@
 use std::prelude::v1::*;
 
 use eth_types::{SH160, Transaction};
+use jsonrpc::JsonrpcErrorObj;
 use serde::Deserialize;
 
-#[derive(Clone, Deserialize)]
+// spelled the same on the command line and in the config file
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum MevBooTeeMode {
     ProposerAide,
     BuilderAide,
@@ -11,8 +14,71 @@ pub enum MevBooTeeMode {
     FullTeeBuilder,
 }
 
+impl std::str::FromStr for MevBooTeeMode {
+    type Err = MevBooTeeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "proposer-aide" => Ok(MevBooTeeMode::ProposerAide),
+            "builder-aide" => Ok(MevBooTeeMode::BuilderAide),
+            "assembler" => Ok(MevBooTeeMode::Assembler),
+            "full-tee-builder" => Ok(MevBooTeeMode::FullTeeBuilder),
+            _ => Err(MevBooTeeError::Config(format!(
+                "unknown mode {:?}, expected one of proposer-aide, builder-aide, assembler, full-tee-builder", s
+            ))),
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum MevBooTeeError {
     #[error("bad signed header object: {0}")]
-    BadSignedHeader(String)
+    BadSignedHeader(String),
+    #[error("proposer aide: {0}")]
+    ProposerAide(String),
+    #[error("builder aide: {0}")]
+    BuilderAide(String),
+    #[error("full tee builder: {0}")]
+    FullTeeBuilder(String),
+    #[error("execution: {0}")]
+    Execution(String),
+    #[error("config: {0}")]
+    Config(String),
+    #[error("bad bundle: {0}")]
+    Bundle(String),
+    #[error("proposer: {0}")]
+    Proposer(String),
+    #[error("beacon: {0}")]
+    Beacon(String),
+    #[error("publish: {0}")]
+    Publish(String),
+    #[error("blob: {0}")]
+    Blob(String),
+    #[error("slot: {0}")]
+    Slot(String),
+    #[error("enclave: {0}")]
+    Enclave(String),
+    #[error("attestation: {0}")]
+    Attestation(String),
+}
+
+// seconds since the unix epoch
+pub fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn unix_timestamp_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl From<MevBooTeeError> for JsonrpcErrorObj {
+    fn from(err: MevBooTeeError) -> Self {
+        JsonrpcErrorObj::client(err.to_string())
+    }
 }
@@ -8,13 +8,12 @@
 #[macro_use]
 extern crate sgxlib as std;
 
-use app_mev_bootee::{MevBooTee, MevBooTeeMode};
+use app_mev_bootee::MevBooTee;
 use std::ffi::CStr;
 use std::os::raw::c_char;
 use std::prelude::v1::*;
 use std::sgx_trts;
 use std::sgx_types::sgx_status_t;
-use std::sync::Mutex;
 
 lazy_static::lazy_static! {
     static ref APP: MevBooTee = MevBooTee::default();
@@ -26,10 +25,6 @@ pub unsafe extern "C" fn enclave_entrypoint(eid: u64, args: *const c_char) -> sg
     glog::info!("Initialize Enclave!");
 
     let args = apps::parse_args(args);
-    // let mode = MevBooTeeMode::Assembler;
-    // let do_verification = true; // TODO: do we need this?
-    // let mut app = APP.lock().unwrap();
-    // app.config(mode, do_verification);
     match apps::run_enclave(&APP, eid, args) {
         Ok(()) => sgx_status_t::SGX_SUCCESS,
         Err(err) => err,